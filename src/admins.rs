@@ -0,0 +1,102 @@
+use std::error::Error;
+use std::sync::OnceLock;
+use teloxide::types::UserId;
+
+use crate::storage::Storage;
+
+// Роли по возрастанию прав: каждая следующая роль может всё, что и предыдущая
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Support, // просмотр служебной информации
+    Admin,   // рассылки пользователям
+    Owner,   // управление ролями и ключами шифрования
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Support => "support",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        match value.trim().to_lowercase().as_str() {
+            "support" => Some(Role::Support),
+            "admin" => Some(Role::Admin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+static OWNER_IDS: OnceLock<Vec<UserId>> = OnceLock::new();
+
+// Разбор списка id пользователей Telegram через запятую
+pub fn parse_owner_ids(value: &str) -> Result<Vec<UserId>, Box<dyn Error + Send + Sync>> {
+    value
+        .split(',')
+        .map(|id| id.trim())
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse::<u64>()
+                .map(UserId)
+                .map_err(|_| format!("BOT_OWNER_IDS: «{}» не является id пользователя Telegram", id).into())
+        })
+        .collect()
+}
+
+// Владельцы берутся из настроек (owner_ids или BOT_OWNER_IDS). Их роль хранится только там, поэтому ее нельзя отозвать командой
+pub fn init(owners: &[u64]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if owners.is_empty() {
+        log::warn!("Владельцы бота не заданы (owner_ids или BOT_OWNER_IDS), команды администратора будут доступны только по выданным ролям");
+    }
+    OWNER_IDS
+        .set(owners.iter().copied().map(UserId).collect())
+        .map_err(|_| "Владельцы бота уже инициализированы")?;
+    Ok(())
+}
+
+pub fn owner_ids() -> &'static [UserId] {
+    OWNER_IDS.get().map(|ids| ids.as_slice()).unwrap_or(&[])
+}
+
+pub fn is_config_owner(id: UserId) -> bool {
+    owner_ids().contains(&id)
+}
+
+pub async fn user_role(storage: &dyn Storage, id: UserId) -> Result<Option<Role>, Box<dyn Error + Send + Sync>> {
+    if is_config_owner(id) {
+        return Ok(Some(Role::Owner));
+    }
+    Ok(storage.get_admin_role(id).await?.and_then(|role| Role::parse(&role)))
+}
+
+// Единая проверка прав для всех команд администратора
+pub async fn authorize(storage: &dyn Storage, id: UserId, required: Role) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    Ok(user_role(storage, id).await?.is_some_and(|role| role >= required))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_are_ordered_by_privileges() {
+        assert!(Role::Owner > Role::Admin);
+        assert!(Role::Admin > Role::Support);
+        for role in [Role::Support, Role::Admin, Role::Owner] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse(" Admin "), Some(Role::Admin));
+        assert_eq!(Role::parse("root"), None);
+    }
+
+    #[test]
+    fn parses_owner_ids() {
+        assert!(parse_owner_ids("").unwrap().is_empty());
+        assert_eq!(parse_owner_ids("1, 42,").unwrap(), vec![UserId(1), UserId(42)]);
+        assert!(parse_owner_ids("1,@owner").is_err());
+    }
+}
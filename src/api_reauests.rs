@@ -1,7 +1,28 @@
-use reqwest::{Client, header::{HeaderMap, HeaderValue}};
-use crate::database::{insert_warehouses, add_or_update_warehouse_coefficents};
-use serde::{Deserialize, Serialize};
+use reqwest::{Client, StatusCode, header::{HeaderMap, HeaderValue, RETRY_AFTER}};
+use crate::storage::Storage;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+pub const DEFAULT_COMMON_API_URL: &str = "https://common-api.wildberries.ru";
+pub const DEFAULT_SUPPLIES_API_URL: &str = "https://supplies-api.wildberries.ru";
+
+// Метод коэффициентов отдает не больше 6 запросов в минуту на аккаунт
+const COEFFICIENTS_REQUEST_INTERVAL: Duration = Duration::from_secs(10);
+// Сколько раз повторяем запрос при 429, 5xx и сетевых ошибках
+const MAX_RETRIES: u32 = 3;
+// Начальная пауза перед повтором, дальше удваивается
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    // Общий клиент WB API на всё приложение
+    pub static ref WB_CLIENT: WbClient = WbClient::from_config();
+}
 
 #[derive(Deserialize)]
 struct PingResponse {
@@ -14,7 +35,7 @@ struct PingResponse {
 
 #[derive(Deserialize)]
 pub struct Warehouse {
-    #[serde(rename = "ID")] 
+    #[serde(rename = "ID")]
     pub id: u32,
     pub name: String,
 }
@@ -33,92 +54,394 @@ pub struct CoefficientResponse {
     pub box_type_id: Option<u32>, // boxTypeID может не быть
 }
 
-pub async fn check_token(api_key: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
-    let url = "https://common-api.wildberries.ru/ping";
-
-    // Создаем заголовок Authorization с токеном
-    let mut headers = HeaderMap::new();
-    headers.insert("Authorization", HeaderValue::from_str(api_key)?);
-
-    let client = reqwest::Client::new();
-    let response = client.get(url).headers(headers).send().await?;
-
-    // Проверяем, успешен ли статус ответа
-    if response.status().is_success() {
-        // Десериализуем тело ответа в структуру PingResponse
-        let body: PingResponse = response.json().await?;
-        // Проверяем, если "Status" == "OK", возвращаем true
-        return Ok(body.status == "OK");
-    }
-
-    // Если статус ответа неуспешный, возвращаем false
-    Ok(false)
-}
-
-pub async fn fetch_warehouses(api_key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let url = "https://supplies-api.wildberries.ru/api/v1/warehouses";
-
-    // Создаем заголовок Authorization с токеном
-    let mut headers = HeaderMap::new();
-    headers.insert("Authorization", HeaderValue::from_str(api_key)?);
-
-    // Создаем HTTP клиент
-    let client = Client::new();
-    
-    // Отправляем запрос и получаем ответ
-    let response = client.get(url)
-        .headers(headers)
-        .send()
-        .await?;
-
-    // Проверяем успешность ответа
-    if response.status().is_success() {
-        // Десериализуем тело ответа в список складов
-        let mut warehouses: Vec<Warehouse> = response.json().await?;
-        warehouses.sort_by(|a, b| a.name.cmp(&b.name));
-        insert_warehouses(warehouses).await?;
-        Ok(())
-    } else {
-        // Логируем ошибку если запрос неуспешный
-        let status = response.status();
-        let text = response.text().await.unwrap_or_default();
-        eprintln!("Ошибка: статус {} и сообщение {}", status, text);
-        Err("Не удалось получить список складов".into())
+#[derive(Debug)]
+pub enum WbApiError {
+    Unauthorized,                                // 401: токен невалиден или просрочен
+    Forbidden,                                   // 403: у токена нет доступа к категории
+    RateLimited { retry_after: Option<Duration> }, // 429: превышен лимит запросов
+    Server(StatusCode),                          // 5xx: ошибка на стороне WB
+    UnexpectedStatus(StatusCode, String),        // прочие неуспешные статусы
+    Decode(String),                              // не удалось разобрать ответ
+    NoData,                                      // WB вернул null вместо данных
+    InvalidToken,                                // токен нельзя передать в заголовке
+    Network(reqwest::Error),                     // сетевая ошибка
+}
+
+impl fmt::Display for WbApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WbApiError::Unauthorized => write!(f, "WB API: токен не авторизован (401)"),
+            WbApiError::Forbidden => write!(f, "WB API: у токена нет доступа к методу (403)"),
+            WbApiError::RateLimited { retry_after: Some(d) } => {
+                write!(f, "WB API: превышен лимит запросов, повторить через {} с (429)", d.as_secs())
+            }
+            WbApiError::RateLimited { retry_after: None } => write!(f, "WB API: превышен лимит запросов (429)"),
+            WbApiError::Server(status) => write!(f, "WB API: ошибка сервера {}", status),
+            WbApiError::UnexpectedStatus(status, text) => write!(f, "WB API: статус {} и сообщение {}", status, text),
+            WbApiError::Decode(e) => write!(f, "WB API: не удалось разобрать ответ: {}", e),
+            WbApiError::NoData => write!(f, "WB API: вернул null"),
+            WbApiError::InvalidToken => write!(f, "WB API: токен содержит недопустимые символы"),
+            WbApiError::Network(e) => write!(f, "WB API: сетевая ошибка: {}", e),
+        }
     }
 }
 
-pub async fn fetch_and_store_coefficients(api_key: &str, warehouse_ids: Option<Vec<u32>>) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let url = "https://supplies-api.wildberries.ru/api/v1/acceptance/coefficients";
+impl Error for WbApiError {}
 
-    // Создаем заголовок Authorization с токеном
-    let mut headers = HeaderMap::new();
-    headers.insert("Authorization", HeaderValue::from_str(api_key)?);
+impl WbApiError {
+    // Текст ошибки для пользователя
+    pub fn user_message(&self) -> String {
+        match self {
+            WbApiError::Unauthorized | WbApiError::InvalidToken => {
+                "WB не принял токен. Проверьте, что токен действителен, и введите его заново".to_string()
+            }
+            WbApiError::Forbidden => {
+                "У токена нет доступа к категории <b>'Поставки'</b>. Создайте токен с этой категорией".to_string()
+            }
+            WbApiError::RateLimited { retry_after: Some(d) } => {
+                format!("WB ограничил частоту запросов, попробуйте через {} с", d.as_secs().max(1))
+            }
+            WbApiError::RateLimited { retry_after: None } => {
+                "WB ограничил частоту запросов, попробуйте через минуту".to_string()
+            }
+            WbApiError::NoData => "WB не предоставил информации по данному складу".to_string(),
+            _ => "WB временно недоступен, попробуйте повторить позже".to_string(),
+        }
+    }
 
-    let client = reqwest::Client::new();
-    let mut request = client.get(url).headers(headers);
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            WbApiError::RateLimited { .. } | WbApiError::Server(_) | WbApiError::Network(_)
+        )
+    }
+}
+
+pub struct WbClient {
+    http: Client,
+    common_api_url: String,
+    supplies_api_url: String,
+    // Время последнего запроса коэффициентов по каждому токену. Mutex на токен выстраивает запросы в очередь
+    coefficients_queue: Mutex<HashMap<String, Arc<Mutex<Option<Instant>>>>>,
+    // Пауза между запросами коэффициентов с одним токеном, в тестах короче
+    coefficients_interval: Duration,
+    // Куда писать запросы для /stats. Без хранилища (в тестах) запросы не записываются
+    calls_storage: OnceLock<Arc<dyn Storage>>,
+}
+
+impl WbClient {
+    pub fn new(common_api_url: &str, supplies_api_url: &str) -> WbClient {
+        WbClient {
+            http: Client::new(),
+            common_api_url: common_api_url.trim_end_matches('/').to_string(),
+            supplies_api_url: supplies_api_url.trim_end_matches('/').to_string(),
+            coefficients_queue: Mutex::new(HashMap::new()),
+            coefficients_interval: COEFFICIENTS_REQUEST_INTERVAL,
+            calls_storage: OnceLock::new(),
+        }
+    }
 
-    // Добавляем параметр warehouseIDs, если он есть
-    if let Some(ids) = warehouse_ids {
-        let ids_string = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
-        request = request.query(&[("warehouseIDs", ids_string)]);
+    // Подключает запись запросов в статистику, вызывается один раз при запуске
+    pub fn record_calls_to(&self, storage: Arc<dyn Storage>) {
+        if self.calls_storage.set(storage).is_err() {
+            log::warn!("Хранилище для статистики запросов к WB API уже подключено");
+        }
     }
 
-    let response = request.send().await?;
+    // Адреса API из настроек (wb_api или WB_COMMON_API_URL и WB_SUPPLIES_API_URL), по умолчанию боевые адреса WB
+    pub fn from_config() -> WbClient {
+        let settings = &crate::config::get().wb_api;
+        WbClient::new(&settings.common_url, &settings.supplies_url)
+    }
 
-    if response.status().is_success() {
-        // Десериализуем ответ как опцию, чтобы обработать null
-        let coefficients: Option<Vec<CoefficientResponse>> = response.json().await?;
-        
-        // Проверяем, не вернул ли API null
-        if let Some(coefficients) = coefficients {
-            // Если данные есть, сохраняем их
-            add_or_update_warehouse_coefficents(coefficients).await?;
-        } else {
-            // Если API вернул null
-            eprintln!("В функции fetch_and_store_coefficients API вернул null");
-            return Err("WB не дал данных по складу".into());
+    pub async fn ping(&self, api_key: &str) -> Result<bool, WbApiError> {
+        let url = format!("{}/ping", self.common_api_url);
+        let body: Option<PingResponse> = self.get_json(&url, api_key, &[], Duration::ZERO).await?;
+        Ok(body.is_some_and(|b| b.status == "OK"))
+    }
+
+    pub async fn warehouses(&self, api_key: &str) -> Result<Vec<Warehouse>, WbApiError> {
+        let url = format!("{}/api/v1/warehouses", self.supplies_api_url);
+        let warehouses: Option<Vec<Warehouse>> = self.get_json(&url, api_key, &[], Duration::ZERO).await?;
+        warehouses.ok_or(WbApiError::NoData)
+    }
+
+    pub async fn coefficients(
+        &self,
+        api_key: &str,
+        warehouse_ids: Option<Vec<u32>>,
+    ) -> Result<Vec<CoefficientResponse>, WbApiError> {
+        let url = format!("{}/api/v1/acceptance/coefficients", self.supplies_api_url);
+
+        // Добавляем параметр warehouseIDs, если он есть
+        let mut query = vec![];
+        if let Some(ids) = warehouse_ids {
+            let ids_string = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+            query.push(("warehouseIDs", ids_string));
+        }
+
+        // Запросы с одним токеном идут по очереди и не чаще лимита
+        let slot = {
+            let mut queue = self.coefficients_queue.lock().await;
+            evict_idle_tokens(&mut queue, self.coefficients_interval);
+            queue.entry(api_key.to_string()).or_default().clone()
+        };
+        let mut last_request = slot.lock().await;
+        if let Some(last) = *last_request {
+            sleep_until_allowed(last, self.coefficients_interval).await;
+        }
+        // Повторы тоже считаются запросами, поэтому между ними не меньше того же интервала
+        let result: Result<Option<Vec<CoefficientResponse>>, WbApiError> =
+            self.get_json(&url, api_key, &query, self.coefficients_interval).await;
+        *last_request = Some(Instant::now());
+
+        match result? {
+            Some(coefficients) => Ok(coefficients),
+            None => {
+                eprintln!("В функции coefficients API вернул null");
+                Err(WbApiError::NoData)
+            }
+        }
+    }
+
+    // GET с авторизацией, повторами и разбором JSON. null в ответе превращается в None.
+    // min_retry_wait - наименьшая пауза перед повтором для методов со своим лимитом частоты
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        api_key: &str,
+        query: &[(&str, String)],
+        min_retry_wait: Duration,
+    ) -> Result<Option<T>, WbApiError> {
+        // Создаем заголовок Authorization с токеном
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            HeaderValue::from_str(api_key).map_err(|_| WbApiError::InvalidToken)?,
+        );
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let result = self.try_get_json(url, headers.clone(), query).await;
+            match result {
+                Err(e) if e.is_retryable() && attempt < MAX_RETRIES => {
+                    let wait = match &e {
+                        WbApiError::RateLimited { retry_after: Some(d) } => *d,
+                        _ => backoff,
+                    }
+                    .max(min_retry_wait);
+                    eprintln!("{}; повтор через {} с", e, wait.as_secs());
+                    sleep(wait).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_get_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        headers: HeaderMap,
+        query: &[(&str, String)],
+    ) -> Result<Option<T>, WbApiError> {
+        let response = match self.http.get(url).headers(headers).query(query).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                self.record_call(url, None).await;
+                return Err(WbApiError::Network(e));
+            }
+        };
+
+        let status = response.status();
+        self.record_call(url, Some(status)).await;
+        if status.is_success() {
+            let bytes = response.bytes().await.map_err(WbApiError::Network)?;
+            return serde_json::from_slice::<Option<T>>(&bytes)
+                .map_err(|e| WbApiError::Decode(e.to_string()));
         }
+
+        Err(match status {
+            StatusCode::UNAUTHORIZED => WbApiError::Unauthorized,
+            StatusCode::FORBIDDEN => WbApiError::Forbidden,
+            StatusCode::TOO_MANY_REQUESTS => WbApiError::RateLimited {
+                retry_after: parse_retry_after(response.headers()),
+            },
+            status if status.is_server_error() => WbApiError::Server(status),
+            status => {
+                let text = response.text().await.unwrap_or_default();
+                WbApiError::UnexpectedStatus(status, text)
+            }
+        })
+    }
+
+    // Запрос попадает в статистику /stats. Ошибка записи не должна мешать самому запросу
+    async fn record_call(&self, url: &str, status: Option<StatusCode>) {
+        let Some(storage) = self.calls_storage.get() else {
+            return;
+        };
+        let endpoint = reqwest::Url::parse(url).map(|u| u.path().to_string()).unwrap_or_else(|_| url.to_string());
+        let ok = status.is_some_and(|s| s.is_success());
+        let _ = storage.record_api_call(&endpoint, status.map(|s| s.as_u16()), ok).await;
     }
+}
+
+async fn sleep_until_allowed(last: Instant, interval: Duration) {
+    let allowed_at = last + interval;
+    if allowed_at > Instant::now() {
+        tokio::time::sleep_until(allowed_at).await;
+    }
+}
+
+// Токены, по которым сейчас никто не ждет и лимит уже не действует, из очереди убираем,
+// иначе очередь копит все токены, которые когда-либо запрашивали коэффициенты
+fn evict_idle_tokens(queue: &mut HashMap<String, Arc<Mutex<Option<Instant>>>>, interval: Duration) {
+    queue.retain(|_, slot| {
+        Arc::strong_count(slot) > 1 || slot.try_lock().is_ok_and(|last| last.is_some_and(|t| t.elapsed() < interval))
+    });
+}
 
+// WB присылает Retry-After (и X-Ratelimit-Retry) в секундах
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .or_else(|| headers.get("X-Ratelimit-Retry"))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+pub async fn check_token(api_key: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    match WB_CLIENT.ping(api_key).await {
+        Ok(is_ok) => Ok(is_ok),
+        // Неавторизованный токен - это ответ "невалиден", а не ошибка
+        Err(WbApiError::Unauthorized) | Err(WbApiError::Forbidden) | Err(WbApiError::InvalidToken) => Ok(false),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+pub async fn fetch_warehouses(storage: &dyn Storage, api_key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut warehouses = WB_CLIENT.warehouses(api_key).await?;
+    warehouses.sort_by(|a, b| a.name.cmp(&b.name));
+    storage.insert_warehouses(warehouses).await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub async fn fetch_and_store_coefficients(storage: &dyn Storage, api_key: &str, warehouse_ids: Option<Vec<u32>>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let coefficients = fetch_coefficients(api_key, warehouse_ids).await?;
+    storage.add_or_update_warehouse_coefficents(coefficients).await?;
+    Ok(())
+}
+
+pub async fn fetch_coefficients(api_key: &str, warehouse_ids: Option<Vec<u32>>) -> Result<Vec<CoefficientResponse>, WbApiError> {
+    WB_CLIENT.coefficients(api_key, warehouse_ids).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wb_mock::{MockResponse, MockWbServer};
+
+    async fn client() -> (MockWbServer, WbClient) {
+        let server = MockWbServer::start().await;
+        let mut client = WbClient::new(&server.url(), &server.url());
+        // Настоящий интервал в 10 с растянул бы тесты с повторами
+        client.coefficients_interval = Duration::from_millis(10);
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn ping_sends_token_and_reads_status() {
+        let (server, client) = client().await;
+        assert!(client.ping("token-ping").await.unwrap());
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/ping");
+        assert_eq!(requests[0].authorization.as_deref(), Some("token-ping"));
+    }
+
+    #[tokio::test]
+    async fn maps_auth_statuses() {
+        let (server, client) = client().await;
+        server.enqueue("/ping", MockResponse::status(401));
+        server.enqueue("/api/v1/warehouses", MockResponse::status(403));
+        assert!(matches!(client.ping("t").await, Err(WbApiError::Unauthorized)));
+        assert!(matches!(client.warehouses("t").await, Err(WbApiError::Forbidden)));
+    }
+
+    #[tokio::test]
+    async fn null_body_is_no_data() {
+        let (server, client) = client().await;
+        server.enqueue("/api/v1/acceptance/coefficients", MockResponse::json("null"));
+        assert!(matches!(client.coefficients("token-null", None).await, Err(WbApiError::NoData)));
+    }
+
+    #[tokio::test]
+    async fn broken_body_is_decode_error() {
+        let (server, client) = client().await;
+        server.enqueue("/api/v1/warehouses", MockResponse::json("[{"));
+        assert!(matches!(client.warehouses("t").await, Err(WbApiError::Decode(_))));
+    }
+
+    #[tokio::test]
+    async fn retries_after_rate_limit() {
+        let (server, client) = client().await;
+        server.enqueue(
+            "/api/v1/acceptance/coefficients",
+            MockResponse::status(429).with_header("Retry-After", "0"),
+        );
+        let coefficients = client.coefficients("token-429", Some(vec![507])).await.unwrap();
+        assert_eq!(coefficients.len(), 3);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].query, "warehouseIDs=507");
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (server, client) = client().await;
+        for _ in 0..=MAX_RETRIES {
+            server.enqueue("/ping", MockResponse::status(429).with_header("Retry-After", "0"));
+        }
+        assert!(matches!(
+            client.ping("t").await,
+            Err(WbApiError::RateLimited { retry_after: Some(_) })
+        ));
+        assert_eq!(server.requests().len() as u32, MAX_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (server, client) = client().await;
+        server.enqueue("/api/v1/warehouses", MockResponse::status(502));
+        let warehouses = client.warehouses("t").await.unwrap();
+        assert_eq!(warehouses.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn spaces_coefficients_retries_by_request_interval() {
+        let (server, mut client) = client().await;
+        client.coefficients_interval = Duration::from_millis(300);
+        server.enqueue(
+            "/api/v1/acceptance/coefficients",
+            MockResponse::status(429).with_header("Retry-After", "0"),
+        );
+        let started = Instant::now();
+        client.coefficients("token-spacing", None).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn evicts_idle_tokens_from_coefficients_queue() {
+        let (_server, mut client) = client().await;
+        client.coefficients_interval = Duration::from_millis(50);
+        client.coefficients("token-a", None).await.unwrap();
+        client.coefficients("token-b", None).await.unwrap();
+        sleep(Duration::from_millis(60)).await;
+        client.coefficients("token-c", None).await.unwrap();
+        let queue = client.coefficients_queue.lock().await;
+        assert_eq!(queue.keys().collect::<Vec<_>>(), vec!["token-c"]);
+    }
+}
@@ -0,0 +1,215 @@
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::InputFile;
+use thirtyfour::prelude::*;
+
+use crate::config;
+use crate::keyboards::to_main_menu_button;
+use crate::seller_portal::{click_step, lock_profile, selectors, start_browser, wait_for_any, PortalConfig, PortalError, PORTAL_CONFIG};
+use crate::storage::Storage;
+
+// Страница планирования поставки в кабинете продавца
+const SUPPLY_PLANNING_PATH: &str = "/supplies-management/new-supply";
+
+pub struct BookingRequest {
+    pub phone: String, // номер, под которым выполнен вход в профиле браузера
+    pub warehouse_id: i32,
+    pub warehouse_name: String,
+    pub box_type_name: String,
+    pub date: DateTime<Utc>,
+}
+
+pub struct BookingReport {
+    pub booked: bool,
+    pub message: String,
+    pub screenshot: Option<Vec<u8>>,
+}
+
+async fn run_booking(driver: &WebDriver, config: &PortalConfig, request: &BookingRequest) -> Result<String, PortalError> {
+    driver.goto(config.page_url(SUPPLY_PLANNING_PATH)).await?;
+
+    let plan = match wait_for_any(driver, &[selectors::PLAN_SUPPLY, selectors::LOGIN_FORM], config.step_timeout).await? {
+        Some((0, element)) => element,
+        Some(_) => return Err(PortalError::NotLoggedIn),
+        None => return Err(PortalError::ElementMissing("переход к планированию поставки")),
+    };
+    plan.click().await?;
+
+    let steps = [
+        (selectors::warehouse(request.warehouse_id), PortalError::ElementMissing("склад")),
+        (selectors::box_type(&request.box_type_name), PortalError::ElementMissing("тип поставки")),
+        (selectors::date(&request.date.format("%Y-%m-%d").to_string()), PortalError::SlotUnavailable),
+        (selectors::CONFIRM.to_string(), PortalError::ElementMissing("кнопка подтверждения")),
+    ];
+    for (selector, missing) in steps {
+        click_step(driver, config, &selector, missing).await?;
+    }
+
+    match wait_for_any(driver, &[selectors::SUCCESS, selectors::ERROR], config.result_timeout).await? {
+        Some((0, element)) => Ok(element.text().await?),
+        Some((_, element)) => Err(PortalError::Rejected(element.text().await?)),
+        None => Err(PortalError::NoResponse),
+    }
+}
+
+// Бронирует слот в браузере с профилем номера в чате. Скриншот снимаем при любом исходе, если браузер запустился
+pub async fn book_slot(config: &PortalConfig, chat_id: ChatId, request: &BookingRequest) -> BookingReport {
+    let driver = match start_browser(config, chat_id, &request.phone).await {
+        Ok(driver) => driver,
+        Err(e) => {
+            return BookingReport {
+                booked: false,
+                message: format!("Не удалось запустить браузер: {}", e),
+                screenshot: None,
+            }
+        }
+    };
+
+    let result = run_booking(&driver, config, request).await;
+    let screenshot = driver.screenshot_as_png().await.ok();
+    if let Err(e) = driver.quit().await {
+        log::warn!("Не удалось закрыть сессию WebDriver: {}", e);
+    }
+
+    match result {
+        Ok(message) => BookingReport { booked: true, message, screenshot },
+        Err(e) => BookingReport { booked: false, message: e.to_string(), screenshot },
+    }
+}
+
+fn format_report(request: &BookingRequest, report: &BookingReport) -> String {
+    let title = if report.booked {
+        "✅ Слот забронирован автоматически. Автобронирование для подписки выключено"
+    } else {
+        "❌ Не удалось забронировать слот автоматически"
+    };
+    format!(
+        "{}\n\n📍Склад: {}\n📦Тип поставки: {}\n⌛️Дата: {}\n\n{}",
+        title,
+        request.warehouse_name,
+        request.box_type_name,
+        (request.date + config::utc_offset()).format("%d.%m.%Y"),
+        report.message
+    )
+}
+
+// Бронирование по подписке из фона: результат со скриншотом уходит в чат подписки
+pub async fn book_and_report(bot: Bot, storage: Arc<dyn Storage>, chat_id: ChatId, subscription_id: i64, request: BookingRequest) {
+    let _profile = lock_profile(chat_id, &request.phone).await;
+
+    // Пока ждали очереди, слот по этой подписке могли уже забронировать или автобронирование выключили
+    match storage.get_subscription_auto_book(subscription_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Ошибка при чтении подписки {}: {:?}", subscription_id, e);
+            return;
+        }
+    }
+
+    let report = book_slot(&PORTAL_CONFIG, chat_id, &request).await;
+    // Одна подписка — одна поставка: после успеха больше не бронируем
+    if report.booked {
+        if let Err(e) = storage.set_subscription_auto_book(chat_id, subscription_id, None).await {
+            eprintln!("Ошибка при выключении автобронирования подписки {}: {:?}", subscription_id, e);
+        }
+    }
+
+    let text = format_report(&request, &report);
+    let sent = match report.screenshot {
+        Some(png) => bot
+            .send_photo(chat_id, InputFile::memory(png).file_name("booking.png"))
+            .caption(text)
+            .reply_markup(to_main_menu_button())
+            .await
+            .map(drop),
+        None => bot
+            .send_message(chat_id, text)
+            .reply_markup(to_main_menu_button())
+            .await
+            .map(drop),
+    };
+    if let Err(e) = sent {
+        eprintln!("Ошибка при отправке результата автобронирования в чат {}: {:?}", chat_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webdriver_mock::{MockWebDriver, MOCK_PORTAL, SCREENSHOT};
+    use tokio::time::Duration;
+
+    fn config(webdriver: &MockWebDriver) -> PortalConfig {
+        PortalConfig {
+            webdriver_url: webdriver.url(),
+            portal_url: "http://portal.test/".to_string(),
+            profiles_dir: "/tmp/profiles".into(),
+            step_timeout: Duration::from_millis(300),
+            result_timeout: Duration::from_millis(300),
+        }
+    }
+
+    fn request(date: &str) -> BookingRequest {
+        BookingRequest {
+            phone: "+7 (999) 000-11-22".to_string(),
+            warehouse_id: 507,
+            warehouse_name: "Коледино".to_string(),
+            box_type_name: "Короба".to_string(),
+            date: date.parse().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn books_slot_with_user_profile() {
+        let webdriver = MockWebDriver::start(MOCK_PORTAL).await;
+        let report = book_slot(&config(&webdriver), ChatId(42), &request("2024-09-05T00:00:00Z")).await;
+
+        assert!(report.booked, "{}", report.message);
+        assert_eq!(report.message, "Поставка 123456 запланирована на 05.09.2024");
+        assert_eq!(report.screenshot.as_deref(), Some(SCREENSHOT));
+        assert_eq!(webdriver.visited(), vec!["http://portal.test/supplies-management/new-supply"]);
+        assert_eq!(
+            webdriver.clicked(),
+            vec!["plan-supply", "warehouse-option", "box-type-option", "date-cell", "confirm-booking"]
+        );
+        assert!(webdriver.chrome_args().contains(&"--user-data-dir=/tmp/profiles/chat_42/9990001122".to_string()));
+        assert!(webdriver.quit());
+    }
+
+    #[tokio::test]
+    async fn taken_slot_is_reported_with_screenshot() {
+        let webdriver = MockWebDriver::start(MOCK_PORTAL).await;
+        let report = book_slot(&config(&webdriver), ChatId(42), &request("2024-09-06T00:00:00Z")).await;
+
+        assert!(!report.booked);
+        assert_eq!(report.message, PortalError::SlotUnavailable.to_string());
+        assert!(report.screenshot.is_some());
+        assert!(!webdriver.clicked().contains(&"confirm-booking".to_string()));
+        assert!(webdriver.quit());
+    }
+
+    #[tokio::test]
+    async fn expired_session_asks_to_log_in_again() {
+        let webdriver = MockWebDriver::start(r#"<form data-testid="login-form"><input name="phone"></form>"#).await;
+        let report = book_slot(&config(&webdriver), ChatId(42), &request("2024-09-05T00:00:00Z")).await;
+
+        assert!(!report.booked);
+        assert_eq!(report.message, PortalError::NotLoggedIn.to_string());
+        assert!(webdriver.clicked().is_empty());
+    }
+
+    #[tokio::test]
+    async fn portal_rejection_is_reported() {
+        let page = MOCK_PORTAL.replace(
+            r#"data-testid="booking-success" data-after="confirm-booking" hidden>Поставка 123456 запланирована на 05.09.2024"#,
+            r#"data-testid="booking-error" data-after="confirm-booking" hidden>Превышен лимит поставок"#,
+        );
+        let webdriver = MockWebDriver::start(&page).await;
+        let report = book_slot(&config(&webdriver), ChatId(42), &request("2024-09-05T00:00:00Z")).await;
+
+        assert!(!report.booked);
+        assert_eq!(report.message, "Портал отклонил запрос: Превышен лимит поставок");
+    }
+}
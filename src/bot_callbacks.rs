@@ -1,47 +1,204 @@
 use std::error::Error;
+use std::sync::Arc;
 use teloxide::prelude::*;
+use crate::callback_data::CallbackAction;
 use crate::callback_handlers::*;
+use crate::database::Member;
+use crate::dialogue::MemberDialogue;
+use crate::keyboards::main_menu;
+use crate::storage::Storage;
 
-pub async fn callback_handler(bot: Bot, q: CallbackQuery) -> Result<(), Box<dyn Error + Send + Sync>> {
+pub async fn callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: Arc<dyn Storage>,
+    dialogue: MemberDialogue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = storage.as_ref();
     if let Some(data) = q.clone().data {
         bot.answer_callback_query(q.clone().id).await?; //Ответ телеге что мы приняли коллбэк с клавиши клавиатуры
+        if let Err(e) = storage.record_usage_event("callback", Member::from_callback(&q).chat, Some(q.from.id)).await {
+            eprintln!("Ошибка при записи статистики: {:?}", e);
+        }
+
+        let action = match CallbackAction::from_callback_data(storage, &data).await {
+            Ok(action) => action,
+            Err(e) => {
+                eprintln!("Ошибка при разборе callback: {}", e);
+                bot.send_message(q.from.id, "Кнопка устарела или повреждена, начните из главного меню")
+                    .reply_markup(main_menu())
+                    .await?;
+                return Ok(());
+            }
+        };
 
-        match data.as_str() {
-            "main_menu" => {
-                main_menu_callback(bot, q).await?;
+        // Токен, кабинеты, вход по номеру и автобронирование группы меняет только ее администратор
+        let changes_cabinets = matches!(
+            action,
+            CallbackAction::EnterToken
+                | CallbackAction::CabinetAdd
+                | CallbackAction::CabinetSelect(_)
+                | CallbackAction::CabinetRename(_)
+                | CallbackAction::CabinetToken(_)
+                | CallbackAction::CabinetDelete(_)
+                | CallbackAction::SubscriptionAutoBook(_)
+                | CallbackAction::PhoneLogin
+                | CallbackAction::ProfileRelogin(_)
+                | CallbackAction::ProfileDelete(_)
+        );
+        if changes_cabinets && !can_manage_cabinets(&bot, Member::from_callback(&q)).await? {
+            return group_admins_only(&bot, Member::from_callback(&q)).await;
+        }
+
+        match action {
+            CallbackAction::MainMenu => {
+                main_menu_callback(bot, q, &dialogue).await?;
+            }
+            CallbackAction::TokenLifetime => {
+                token_lifetime_callback(bot, q, storage).await?;
+            }
+            CallbackAction::EnterToken => {
+                enter_token_callback(bot, q, &dialogue).await?;
+            }
+            CallbackAction::WarehousesList => {
+                warehouses_list_callback(bot, q, storage, &dialogue).await?;
+            }
+            CallbackAction::AnotherWarehouse => {
+                another_warehouse_callback(bot, q, storage, "Выберите другой склад".to_string()).await?;
+            }
+            CallbackAction::AnotherBoxType { warehouse_id } => {
+                another_box_type_callback(bot, q, storage, warehouse_id).await?;
+            }
+            CallbackAction::WarehousesPage(page) => {
+                warehouses_page_callback(bot, q, storage, page).await?;
+            }
+            CallbackAction::PhonesPage(page) => {
+                phone_page_callback(bot, q, storage, page).await?;
+            }
+            CallbackAction::ProfilesList => {
+                profiles_list_callback(bot, q, storage).await?;
+            }
+            CallbackAction::PhoneLogin => {
+                phone_login_callback(bot, q, storage, &dialogue).await?;
+            }
+            CallbackAction::Warehouse(warehouse_id) => {
+                warehouse_choosed_callback(bot, q, storage, &dialogue, warehouse_id).await?;
+            }
+            CallbackAction::BoxType { warehouse_id, box_type } => {
+                box_type_choosed_callback(bot, q, storage, warehouse_id, box_type).await?;
+            }
+            CallbackAction::SubscriptionsList => {
+                subscriptions_list_callback(bot, q, storage).await?;
+            }
+            CallbackAction::Subscribe { warehouse_id, box_type } => {
+                subscribe_callback(bot, q, &dialogue, warehouse_id, box_type).await?;
+            }
+            CallbackAction::SubscriptionDelete(subscription_id) => {
+                subscription_delete_callback(bot, q, storage, subscription_id).await?;
+            }
+            CallbackAction::SubscriptionAutoBook(subscription_id) => {
+                subscription_auto_book_callback(bot, q, storage, subscription_id).await?;
+            }
+            CallbackAction::PresetsPage(page) => {
+                presets_page_callback(bot, q, storage, page).await?;
+            }
+            CallbackAction::Preset(preset_id) => {
+                preset_callback(bot, q, storage, preset_id).await?;
+            }
+            CallbackAction::PresetRun(preset_id) => {
+                preset_run_callback(bot, q, storage, preset_id).await?;
+            }
+            CallbackAction::PresetEdit(preset_id) => {
+                preset_edit_callback(bot, q, storage, &dialogue, preset_id).await?;
+            }
+            CallbackAction::PresetDuplicate(preset_id) => {
+                preset_duplicate_callback(bot, q, storage, preset_id).await?;
+            }
+            CallbackAction::PresetDelete(preset_id) => {
+                preset_delete_callback(bot, q, storage, preset_id).await?;
+            }
+            CallbackAction::PresetAdd { warehouse_id, box_type } => {
+                preset_add_callback(bot, q, storage, &dialogue, warehouse_id, box_type).await?;
+            }
+            CallbackAction::PresetNew => {
+                preset_new_callback(bot, q, &dialogue).await?;
+            }
+            CallbackAction::PresetPut { preset_id, warehouse_id } => {
+                preset_put_callback(bot, q, storage, &dialogue, preset_id, warehouse_id).await?;
+            }
+            CallbackAction::PresetRemoveWarehouse { preset_id, warehouse_id } => {
+                preset_remove_warehouse_callback(bot, q, storage, &dialogue, preset_id, warehouse_id).await?;
+            }
+            CallbackAction::CoefficientHistory { warehouse_id, box_type } => {
+                coefficient_history_callback(bot, q, storage, warehouse_id, box_type).await?;
+            }
+            CallbackAction::CoefficientChart { warehouse_id, box_type } => {
+                coefficient_chart_callback(bot, q, storage, warehouse_id, box_type).await?;
+            }
+            CallbackAction::WarehouseSearch => {
+                warehouse_search_callback(bot, q, &dialogue).await?;
+            }
+            CallbackAction::FavouriteToggle(warehouse_id) => {
+                favourite_toggle_callback(bot, q, storage, warehouse_id).await?;
+            }
+            CallbackAction::FavouritesList => {
+                favourites_list_callback(bot, q, storage).await?;
+            }
+            CallbackAction::CheapestSearch => {
+                cheapest_search_callback(bot, q, storage).await?;
+            }
+            CallbackAction::CheapestBoxType(box_type) => {
+                cheapest_box_type_callback(bot, q, storage, box_type).await?;
+            }
+            CallbackAction::CheapestAllWarehouses => {
+                cheapest_scope_callback(bot, q, storage, &dialogue, None).await?;
+            }
+            CallbackAction::CheapestPreset(preset_id) => {
+                cheapest_scope_callback(bot, q, storage, &dialogue, Some(preset_id)).await?;
+            }
+            CallbackAction::CheapestScopePage(page) => {
+                cheapest_scope_page_callback(bot, q, storage, page).await?;
+            }
+            CallbackAction::CheapestPage(page) => {
+                cheapest_page_callback(bot, q, storage, page).await?;
+            }
+            CallbackAction::CabinetsList => {
+                cabinets_list_callback(bot, q, storage).await?;
+            }
+            CallbackAction::Cabinet(cabinet_id) => {
+                cabinet_callback(bot, q, storage, cabinet_id).await?;
+            }
+            CallbackAction::CabinetAdd => {
+                cabinet_add_callback(bot, q, &dialogue).await?;
             }
-            "token_lifetime_callback" => {
-                token_lifetime_callback(bot, q).await?;
+            CallbackAction::CabinetSelect(cabinet_id) => {
+                cabinet_select_callback(bot, q, storage, cabinet_id).await?;
             }
-            "warehouses_list_callback" => {
-                warehouses_list_callback(bot, q).await?;
+            CallbackAction::CabinetRename(cabinet_id) => {
+                cabinet_rename_callback(bot, q, storage, &dialogue, cabinet_id).await?;
             }
-            "another_warehouse_callback" => {
-                another_warehouse_callback(bot, q, "Выберите другой склад".to_string()).await?;
+            CallbackAction::CabinetToken(cabinet_id) => {
+                cabinet_token_callback(bot, q, storage, &dialogue, cabinet_id).await?;
             }
-            data if data.starts_with("another_box_type_callback:") => {
-                let warehouse_id = data.trim_start_matches("another_box_type_callback:").trim().parse::<i32>().ok().unwrap();
-                another_box_type_callback(bot, q, warehouse_id).await?;
+            CallbackAction::CabinetDelete(cabinet_id) => {
+                cabinet_delete_callback(bot, q, storage, cabinet_id).await?;
             }
-            data if data.starts_with("w_page:") => {
-                let page: i32 = data[7..].parse().unwrap_or(0);
-                warehouses_page_callback(bot, q, page).await?;
+            CallbackAction::BroadcastConfirm(broadcast_id) => {
+                broadcast_confirm_callback(bot, q, storage, broadcast_id).await?;
             }
-            data if data.starts_with("p_page:") => {
-                let page: i32 = data[7..].parse().unwrap_or(0);
-                phone_page_callback(bot, q, page).await?;
+            CallbackAction::BroadcastCancel(broadcast_id) => {
+                broadcast_cancel_callback(bot, q, storage, broadcast_id).await?;
             }
-            data if data.starts_with("whid:") => {
-                let warehouse_id: i32 = data[5..].parse().unwrap_or(0);
-                warehouse_choosed_callback(bot, q, warehouse_id).await?;
+            CallbackAction::Phone(phone) => {
+                profile_callback(bot, q, storage, phone).await?;
             }
-            data if data.starts_with("boxtype:") => {
-                box_type_choosed_callback(bot, q).await?;
+            CallbackAction::ProfileRelogin(phone) => {
+                profile_relogin_callback(bot, q, storage, &dialogue, phone).await?;
             }
-            _ => {
-                bot.send_message(q.from.id, format!("Неизвестный callback: {}", data)).await?;
+            CallbackAction::ProfileDelete(phone) => {
+                profile_delete_callback(bot, q, storage, phone).await?;
             }
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,6 +1,8 @@
 use crate::commands_handlers::*;
+use crate::storage::Storage;
 use std::error::Error;
-use teloxide::{prelude::*, utils::command::BotCommands};
+use std::sync::Arc;
+use teloxide::{prelude::*, types::Me, utils::command::BotCommands};
 use teloxide_macros::BotCommands;
 
 #[derive(BotCommands, Clone)]
@@ -9,30 +11,69 @@ pub enum Command {
     #[command(description = "Help")]
     Help,
     #[command(description = "Авторизация пользователя")]
-    Start,
-    #[command(description = "Отправляет сообщение всем пользователям.")]
+    Start(String), // Необязательный параметр из ссылки t.me/<бот>?start=...
+    #[command(description = "Рассылка всем пользователям (HTML, фото с подписью, кнопки строками \"Текст | https://...\").")]
     MsgToAll(String), // Передаём текст сообщения
+    #[command(description = "Перешифровывает все токены текущим ключом.")]
+    RotateTokenKey,
+    #[command(description = "Выдает роль: /grantrole <id или @username> <owner|admin|support>")]
+    GrantRole(String),
+    #[command(description = "Отзывает роль: /revokerole <id или @username>")]
+    RevokeRole(String),
+    #[command(description = "Список администраторов бота.")]
+    Admins,
+    #[command(description = "Статистика использования бота.")]
+    Stats,
 }
 
-pub async fn answer(bot: Bot, msg: Message) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let bot_user = bot.get_me().await?;
-    let bot_username = bot_user.username().to_string();
+// Команда из текста или из подписи к фото (рассылка с картинкой)
+pub fn parse_command(msg: Message, me: Me) -> Option<Command> {
+    Command::parse(msg.text().or(msg.caption())?, me.username()).ok()
+}
+
+// Статистика сообщений. Вид сообщения известен по ветке обработчика в main, повторно команду не разбираем
+pub async fn record_command_usage(msg: Message, storage: Arc<dyn Storage>) {
+    record_usage("command", &msg, storage.as_ref()).await;
+}
+
+pub async fn record_message_usage(msg: Message, storage: Arc<dyn Storage>) {
+    if msg.text().or(msg.caption()).is_some() {
+        record_usage("message", &msg, storage.as_ref()).await;
+    }
+}
+
+async fn record_usage(kind: &str, msg: &Message, storage: &dyn Storage) {
+    if let Err(e) = storage.record_usage_event(kind, msg.chat.id, msg.from.as_ref().map(|user| user.id)).await {
+        eprintln!("Ошибка при записи статистики: {:?}", e);
+    }
+}
 
-    if let Some(text) = msg.text() {
-        if let Ok(cmd) = Command::parse(text, &bot_username) {
-            match cmd {
-                Command::Help => {
-                    help_command_handler(bot, msg.chat.id).await?;
-                }
-                Command::Start => {
-                    start_command_handler(bot, &msg).await?;
-                }
-                Command::MsgToAll(text) => {
-                    msg_to_all_command_handler(bot, &msg, text).await?;
-                }
-            }
-        } else {
-            text_msg_handler(bot, &msg).await?;
+pub async fn answer(bot: Bot, msg: Message, storage: Arc<dyn Storage>, cmd: Command) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = storage.as_ref();
+    match cmd {
+        Command::Help => {
+            help_command_handler(bot, msg.chat.id).await?;
+        }
+        Command::Start(payload) => {
+            start_command_handler(bot, &msg, storage, payload).await?;
+        }
+        Command::MsgToAll(text) => {
+            msg_to_all_command_handler(bot, &msg, storage, text).await?;
+        }
+        Command::RotateTokenKey => {
+            rotate_token_key_command_handler(bot, &msg, storage).await?;
+        }
+        Command::GrantRole(args) => {
+            grant_role_command_handler(bot, &msg, storage, args).await?;
+        }
+        Command::RevokeRole(args) => {
+            revoke_role_command_handler(bot, &msg, storage, args).await?;
+        }
+        Command::Admins => {
+            admins_command_handler(bot, &msg, storage).await?;
+        }
+        Command::Stats => {
+            stats_command_handler(bot, &msg, storage).await?;
         }
     }
     Ok(())
@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode};
+use teloxide::{ApiError, RequestError};
+use tokio::time::sleep;
+
+use crate::database::{Broadcast, BroadcastStats};
+use crate::keyboards::broadcast_progress_keyboard;
+use crate::storage::Storage;
+
+// Telegram разрешает около 30 сообщений в секунду разным пользователям, держимся ниже
+const SEND_INTERVAL: Duration = Duration::from_millis(40);
+// Сколько получателей берем из базы за раз
+const BATCH_SIZE: i64 = 100;
+// Как часто обновляем сообщение с прогрессом
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+// Как часто проверяем очередь, когда рассылок нет
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// Сколько раз повторяем отправку одному получателю после 429 или сетевой ошибки
+const MAX_ATTEMPTS: u32 = 5;
+
+// Текст рассылки и кнопки-ссылки. Кнопки задаются последними строками вида "Текст | https://..."
+#[derive(Debug, PartialEq)]
+pub struct BroadcastContent {
+    pub text: String,
+    pub buttons: Vec<(String, String)>,
+}
+
+fn parse_button(line: &str) -> Option<(String, String)> {
+    let (text, url) = line.rsplit_once('|')?;
+    let (text, url) = (text.trim(), url.trim());
+    let parsed: reqwest::Url = url.parse().ok()?;
+    if text.is_empty() || !matches!(parsed.scheme(), "http" | "https") {
+        return None;
+    }
+    Some((text.to_string(), url.to_string()))
+}
+
+pub fn parse_broadcast(input: &str) -> BroadcastContent {
+    let mut lines: Vec<&str> = input.trim().lines().collect();
+    let mut buttons = vec![];
+    while let Some(button) = lines.last().and_then(|line| parse_button(line)) {
+        buttons.insert(0, button);
+        lines.pop();
+    }
+    BroadcastContent { text: lines.join("\n").trim().to_string(), buttons }
+}
+
+pub fn buttons_keyboard(buttons: &[(String, String)]) -> Option<InlineKeyboardMarkup> {
+    let rows: Vec<Vec<InlineKeyboardButton>> = buttons
+        .iter()
+        .filter_map(|(text, url)| url.parse().ok().map(|url| vec![InlineKeyboardButton::url(text.clone(), url)]))
+        .collect();
+    (!rows.is_empty()).then(|| InlineKeyboardMarkup::new(rows))
+}
+
+// Отправляет рассылку одному получателю, так же отправляется и предпросмотр администратору
+pub async fn send_broadcast_message(bot: &Bot, chat_id: ChatId, broadcast: &Broadcast) -> Result<(), RequestError> {
+    let keyboard = buttons_keyboard(&broadcast.buttons);
+    match &broadcast.photo {
+        Some(photo) => {
+            let mut request = bot
+                .send_photo(chat_id, InputFile::file_id(photo.clone()))
+                .caption(broadcast.text.clone())
+                .parse_mode(ParseMode::Html);
+            if let Some(keyboard) = keyboard {
+                request = request.reply_markup(keyboard);
+            }
+            request.await?;
+        }
+        None => {
+            let mut request = bot.send_message(chat_id, broadcast.text.clone()).parse_mode(ParseMode::Html);
+            if let Some(keyboard) = keyboard {
+                request = request.reply_markup(keyboard);
+            }
+            request.await?;
+        }
+    }
+    Ok(())
+}
+
+// Что делать после ошибки отправки. Нужно и рассылке, и уведомлениям подписок
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    Retry(Duration), // превышен лимит или временная ошибка сети
+    Blocked,         // получатель больше недоступен для бота
+    Failed,          // прочие ошибки, повтор не поможет
+}
+
+pub fn classify(error: &RequestError) -> Delivery {
+    match error {
+        RequestError::RetryAfter(seconds) => Delivery::Retry(seconds.duration()),
+        RequestError::Network(_) | RequestError::Io(_) => Delivery::Retry(Duration::from_secs(1)),
+        RequestError::Api(
+            ApiError::BotBlocked
+            | ApiError::UserDeactivated
+            | ApiError::ChatNotFound
+            | ApiError::BotKicked
+            | ApiError::BotKickedFromSupergroup
+            | ApiError::CantInitiateConversation
+            | ApiError::CantTalkWithBots
+            | ApiError::GroupDeactivated,
+        ) => Delivery::Blocked,
+        _ => Delivery::Failed,
+    }
+}
+
+pub fn format_progress(id: i64, status: &str, stats: &BroadcastStats) -> String {
+    let status = match status {
+        "sending" => "⏳ Идет отправка",
+        "done" => "✅ Завершена",
+        "cancelled" => "⛔️ Остановлена",
+        _ => "Ожидает подтверждения",
+    };
+    format!(
+        "📣 Рассылка #{}\n{}\n\nОтправлено: {} из {}\nЗаблокировали бота: {}\nОшибки: {}",
+        id, status, stats.sent, stats.total, stats.blocked, stats.failed
+    )
+}
+
+async fn report_progress(bot: &Bot, storage: &dyn Storage, broadcast: &Broadcast, status: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let stats = storage.get_broadcast_stats(broadcast.id).await?;
+    let text = format_progress(broadcast.id, status, &stats);
+    let chat_id = ChatId(broadcast.admin_chat_id);
+    match broadcast.progress_message_id {
+        Some(message_id) => {
+            let mut request = bot.edit_message_text(chat_id, teloxide::types::MessageId(message_id), text);
+            if status == "sending" {
+                request = request.reply_markup(broadcast_progress_keyboard(broadcast.id));
+            }
+            request.await?;
+        }
+        None => {
+            bot.send_message(chat_id, text).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn deliver(bot: &Bot, storage: &dyn Storage, broadcast: &Broadcast, chat_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for _ in 0..MAX_ATTEMPTS {
+        let error = match send_broadcast_message(bot, ChatId(chat_id), broadcast).await {
+            Ok(()) => return storage.set_recipient_status(broadcast.id, chat_id, "sent").await,
+            Err(error) => error,
+        };
+        match classify(&error) {
+            Delivery::Retry(delay) => sleep(delay).await,
+            Delivery::Blocked => {
+                storage.set_user_active(ChatId(chat_id), false).await?;
+                return storage.set_recipient_status(broadcast.id, chat_id, "blocked").await;
+            }
+            Delivery::Failed => {
+                log::warn!("Рассылка {}: не удалось отправить в чат {}: {}", broadcast.id, chat_id, error);
+                return storage.set_recipient_status(broadcast.id, chat_id, "failed").await;
+            }
+        }
+    }
+    log::warn!("Рассылка {}: чат {} не ответил после {} попыток", broadcast.id, chat_id, MAX_ATTEMPTS);
+    storage.set_recipient_status(broadcast.id, chat_id, "failed").await
+}
+
+async fn process_broadcast(bot: &Bot, storage: &dyn Storage, broadcast: &Broadcast) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut last_report = Instant::now();
+    loop {
+        // Рассылку могли остановить кнопкой
+        let status = storage.get_broadcast(broadcast.id).await?.map(|b| b.status).unwrap_or_default();
+        if status != "sending" {
+            return report_progress(bot, storage, broadcast, &status).await;
+        }
+
+        let recipients = storage.get_pending_recipients(broadcast.id, BATCH_SIZE).await?;
+        if recipients.is_empty() {
+            storage.finish_broadcast(broadcast.id).await?;
+            return report_progress(bot, storage, broadcast, "done").await;
+        }
+        for chat_id in recipients {
+            deliver(bot, storage, broadcast, chat_id).await?;
+            sleep(SEND_INTERVAL).await;
+        }
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            if let Err(e) = report_progress(bot, storage, broadcast, "sending").await {
+                log::warn!("Рассылка {}: не удалось обновить прогресс: {}", broadcast.id, e);
+            }
+            last_report = Instant::now();
+        }
+    }
+}
+
+// Фоновая отправка рассылок по очереди. После перезапуска продолжает с неотправленных получателей
+pub async fn run_broadcasts(bot: Bot, storage: Arc<dyn Storage>) {
+    loop {
+        match storage.get_sending_broadcast().await {
+            Ok(Some(broadcast)) => {
+                if let Err(e) = process_broadcast(&bot, storage.as_ref(), &broadcast).await {
+                    eprintln!("Ошибка при отправке рассылки {}: {:?}", broadcast.id, e);
+                    sleep(POLL_INTERVAL).await;
+                }
+            }
+            Ok(None) => sleep(POLL_INTERVAL).await,
+            Err(e) => {
+                eprintln!("Ошибка при чтении очереди рассылок: {:?}", e);
+                sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::Seconds;
+
+    #[test]
+    fn trailing_link_lines_become_buttons() {
+        let content = parse_broadcast("<b>Новости</b>\nСтрока | не ссылка\nСайт | https://example.com\nКанал|https://t.me/wb");
+        assert_eq!(content.text, "<b>Новости</b>\nСтрока | не ссылка");
+        assert_eq!(
+            content.buttons,
+            vec![
+                ("Сайт".to_string(), "https://example.com".to_string()),
+                ("Канал".to_string(), "https://t.me/wb".to_string()),
+            ]
+        );
+        assert!(buttons_keyboard(&content.buttons).is_some());
+    }
+
+    #[test]
+    fn text_without_buttons_is_kept() {
+        let content = parse_broadcast("  Привет | всем  ");
+        assert_eq!(content.text, "Привет | всем");
+        assert!(content.buttons.is_empty());
+        assert!(buttons_keyboard(&content.buttons).is_none());
+    }
+
+    #[test]
+    fn classifies_send_errors() {
+        assert_eq!(
+            classify(&RequestError::RetryAfter(Seconds::from_seconds(3))),
+            Delivery::Retry(Duration::from_secs(3))
+        );
+        assert_eq!(classify(&RequestError::Api(ApiError::BotBlocked)), Delivery::Blocked);
+        assert_eq!(classify(&RequestError::Api(ApiError::UserDeactivated)), Delivery::Blocked);
+        assert_eq!(classify(&RequestError::Api(ApiError::MessageTextIsEmpty)), Delivery::Failed);
+    }
+}
@@ -0,0 +1,425 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use teloxide::types::InlineKeyboardButton;
+
+use crate::storage::Storage;
+
+// Telegram ограничивает callback_data 64 байтами
+pub const CALLBACK_DATA_LIMIT: usize = 64;
+// Префикс для длинных данных, сохраненных в базе под коротким id
+const STORED_PREFIX: &str = "cb:";
+
+// Все действия, которые могут прийти с кнопок inline-клавиатуры
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallbackAction {
+    MainMenu,
+    TokenLifetime,
+    EnterToken,
+    WarehousesList,
+    AnotherWarehouse,
+    AnotherBoxType { warehouse_id: i32 },
+    WarehousesPage(i32),
+    PhonesPage(i32),
+    Phone(String),
+    ProfilesList,
+    PhoneLogin,
+    ProfileRelogin(String),
+    ProfileDelete(String),
+    Warehouse(i32),
+    BoxType { warehouse_id: i32, box_type: String },
+    SubscriptionsList,
+    Subscribe { warehouse_id: i32, box_type: String },
+    SubscriptionDelete(i64),
+    SubscriptionAutoBook(i64),
+    PresetsPage(i32),
+    Preset(i64),
+    PresetRun(i64),
+    PresetEdit(i64),
+    PresetDuplicate(i64),
+    PresetDelete(i64),
+    PresetAdd { warehouse_id: i32, box_type: String },
+    PresetNew,
+    PresetPut { preset_id: i64, warehouse_id: i32 },
+    PresetRemoveWarehouse { preset_id: i64, warehouse_id: i32 },
+    CoefficientHistory { warehouse_id: i32, box_type: String },
+    CoefficientChart { warehouse_id: i32, box_type: String },
+    WarehouseSearch,
+    FavouriteToggle(i32),
+    FavouritesList,
+    CheapestSearch,
+    CheapestBoxType(String),
+    CheapestAllWarehouses,
+    CheapestPreset(i64),
+    CheapestScopePage(i32),
+    CheapestPage(i32),
+    CabinetsList,
+    Cabinet(i64),
+    CabinetAdd,
+    CabinetSelect(i64),
+    CabinetRename(i64),
+    CabinetToken(i64),
+    CabinetDelete(i64),
+    BroadcastConfirm(i64),
+    BroadcastCancel(i64),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CallbackDataError {
+    Unknown(String),   // неизвестный префикс
+    Malformed(String), // префикс известен, но параметры не разобрать
+    Expired,           // сохраненные данные не найдены в базе
+}
+
+impl fmt::Display for CallbackDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackDataError::Unknown(data) => write!(f, "Неизвестный callback: {}", data),
+            CallbackDataError::Malformed(data) => write!(f, "Некорректный callback: {}", data),
+            CallbackDataError::Expired => write!(f, "Данные callback не найдены"),
+        }
+    }
+}
+
+impl Error for CallbackDataError {}
+
+impl CallbackAction {
+    // Строковое представление действия. Может быть длиннее лимита Telegram
+    pub fn encode(&self) -> String {
+        match self {
+            CallbackAction::MainMenu => "main_menu".to_string(),
+            CallbackAction::TokenLifetime => "token_lifetime_callback".to_string(),
+            CallbackAction::EnterToken => "enter_token".to_string(),
+            CallbackAction::WarehousesList => "warehouses_list_callback".to_string(),
+            CallbackAction::AnotherWarehouse => "another_warehouse_callback".to_string(),
+            CallbackAction::AnotherBoxType { warehouse_id } => {
+                format!("another_box_type_callback:{}", warehouse_id)
+            }
+            CallbackAction::WarehousesPage(page) => format!("w_page:{}", page),
+            CallbackAction::PhonesPage(page) => format!("p_page:{}", page),
+            CallbackAction::Phone(phone) => format!("phone:{}", phone),
+            CallbackAction::ProfilesList => "profiles".to_string(),
+            CallbackAction::PhoneLogin => "phone_login".to_string(),
+            CallbackAction::ProfileRelogin(phone) => format!("p_relogin:{}", phone),
+            CallbackAction::ProfileDelete(phone) => format!("p_del:{}", phone),
+            CallbackAction::Warehouse(warehouse_id) => format!("whid:{}", warehouse_id),
+            CallbackAction::BoxType { warehouse_id, box_type } => {
+                format!("bt:{}:{}", warehouse_id, box_type)
+            }
+            CallbackAction::SubscriptionsList => "subscriptions_list_callback".to_string(),
+            CallbackAction::Subscribe { warehouse_id, box_type } => {
+                format!("subscribe:{}:{}", warehouse_id, box_type)
+            }
+            CallbackAction::SubscriptionDelete(id) => format!("sub_del:{}", id),
+            CallbackAction::SubscriptionAutoBook(id) => format!("sub_auto:{}", id),
+            CallbackAction::PresetsPage(page) => format!("ps_page:{}", page),
+            CallbackAction::Preset(id) => format!("preset:{}", id),
+            CallbackAction::PresetRun(id) => format!("preset_run:{}", id),
+            CallbackAction::PresetEdit(id) => format!("preset_edit:{}", id),
+            CallbackAction::PresetDuplicate(id) => format!("preset_dup:{}", id),
+            CallbackAction::PresetDelete(id) => format!("preset_del:{}", id),
+            CallbackAction::PresetAdd { warehouse_id, box_type } => {
+                format!("preset_add:{}:{}", warehouse_id, box_type)
+            }
+            CallbackAction::PresetNew => "preset_new".to_string(),
+            CallbackAction::PresetPut { preset_id, warehouse_id } => {
+                format!("preset_put:{}:{}", preset_id, warehouse_id)
+            }
+            CallbackAction::PresetRemoveWarehouse { preset_id, warehouse_id } => {
+                format!("preset_rmw:{}:{}", preset_id, warehouse_id)
+            }
+            CallbackAction::CoefficientHistory { warehouse_id, box_type } => {
+                format!("hist:{}:{}", warehouse_id, box_type)
+            }
+            CallbackAction::CoefficientChart { warehouse_id, box_type } => {
+                format!("chart:{}:{}", warehouse_id, box_type)
+            }
+            CallbackAction::WarehouseSearch => "w_search".to_string(),
+            CallbackAction::FavouriteToggle(warehouse_id) => format!("fav:{}", warehouse_id),
+            CallbackAction::FavouritesList => "favs".to_string(),
+            CallbackAction::CheapestSearch => "cheap".to_string(),
+            CallbackAction::CheapestBoxType(box_type) => format!("cheap_bt:{}", box_type),
+            CallbackAction::CheapestAllWarehouses => "cheap_all".to_string(),
+            CallbackAction::CheapestPreset(id) => format!("cheap_ps:{}", id),
+            CallbackAction::CheapestScopePage(page) => format!("cheap_sp:{}", page),
+            CallbackAction::CheapestPage(page) => format!("cheap_page:{}", page),
+            CallbackAction::CabinetsList => "cabinets".to_string(),
+            CallbackAction::Cabinet(id) => format!("cabinet:{}", id),
+            CallbackAction::CabinetAdd => "cab_add".to_string(),
+            CallbackAction::CabinetSelect(id) => format!("cab_sel:{}", id),
+            CallbackAction::CabinetRename(id) => format!("cab_ren:{}", id),
+            CallbackAction::CabinetToken(id) => format!("cab_tok:{}", id),
+            CallbackAction::CabinetDelete(id) => format!("cab_del:{}", id),
+            CallbackAction::BroadcastConfirm(id) => format!("bc_ok:{}", id),
+            CallbackAction::BroadcastCancel(id) => format!("bc_cancel:{}", id),
+        }
+    }
+
+    pub fn decode(data: &str) -> Result<CallbackAction, CallbackDataError> {
+        let malformed = || CallbackDataError::Malformed(data.to_string());
+        let (tag, args) = data.split_once(':').unwrap_or((data, ""));
+        // Разбор пары "число:число"/"число:строка" (строка идет последней и может содержать ':')
+        let pair = || args.split_once(':').ok_or_else(malformed);
+
+        let action = match tag {
+            "main_menu" => CallbackAction::MainMenu,
+            "token_lifetime_callback" => CallbackAction::TokenLifetime,
+            "enter_token" => CallbackAction::EnterToken,
+            "warehouses_list_callback" => CallbackAction::WarehousesList,
+            "another_warehouse_callback" => CallbackAction::AnotherWarehouse,
+            "subscriptions_list_callback" => CallbackAction::SubscriptionsList,
+            "preset_new" => CallbackAction::PresetNew,
+            "w_search" => CallbackAction::WarehouseSearch,
+            "favs" => CallbackAction::FavouritesList,
+            "cheap" => CallbackAction::CheapestSearch,
+            "cheap_all" => CallbackAction::CheapestAllWarehouses,
+            "cabinets" => CallbackAction::CabinetsList,
+            "cab_add" => CallbackAction::CabinetAdd,
+            "profiles" => CallbackAction::ProfilesList,
+            "phone_login" => CallbackAction::PhoneLogin,
+            "another_box_type_callback" => CallbackAction::AnotherBoxType {
+                warehouse_id: parse_number(args.trim(), data)?,
+            },
+            "w_page" => CallbackAction::WarehousesPage(parse_number(args, data)?),
+            "p_page" => CallbackAction::PhonesPage(parse_number(args, data)?),
+            "phone" if !args.is_empty() => CallbackAction::Phone(args.to_string()),
+            "p_relogin" if !args.is_empty() => CallbackAction::ProfileRelogin(args.to_string()),
+            "p_del" if !args.is_empty() => CallbackAction::ProfileDelete(args.to_string()),
+            "cheap_bt" if !args.is_empty() => CallbackAction::CheapestBoxType(args.to_string()),
+            "fav" => CallbackAction::FavouriteToggle(parse_number(args, data)?),
+            "cheap_ps" => CallbackAction::CheapestPreset(parse_number(args, data)?),
+            "cheap_sp" => CallbackAction::CheapestScopePage(parse_number(args, data)?),
+            "cheap_page" => CallbackAction::CheapestPage(parse_number(args, data)?),
+            "whid" => CallbackAction::Warehouse(parse_number(args, data)?),
+            "bt" | "subscribe" | "preset_add" | "hist" | "chart" => {
+                let (warehouse_id, box_type) = pair()?;
+                let warehouse_id = parse_number(warehouse_id, data)?;
+                if box_type.is_empty() {
+                    return Err(malformed());
+                }
+                let box_type = box_type.to_string();
+                match tag {
+                    "bt" => CallbackAction::BoxType { warehouse_id, box_type },
+                    "subscribe" => CallbackAction::Subscribe { warehouse_id, box_type },
+                    "hist" => CallbackAction::CoefficientHistory { warehouse_id, box_type },
+                    "chart" => CallbackAction::CoefficientChart { warehouse_id, box_type },
+                    _ => CallbackAction::PresetAdd { warehouse_id, box_type },
+                }
+            }
+            // Старый формат кнопок "boxtype:<тип> whid:<id>" из уже отправленных сообщений
+            "boxtype" => {
+                let (box_type, warehouse_id) = args.rsplit_once(" whid:").ok_or_else(malformed)?;
+                if box_type.is_empty() {
+                    return Err(malformed());
+                }
+                CallbackAction::BoxType {
+                    warehouse_id: parse_number(warehouse_id, data)?,
+                    box_type: box_type.to_string(),
+                }
+            }
+            "sub_del" => CallbackAction::SubscriptionDelete(parse_number(args, data)?),
+            "sub_auto" => CallbackAction::SubscriptionAutoBook(parse_number(args, data)?),
+            "ps_page" => CallbackAction::PresetsPage(parse_number(args, data)?),
+            "preset" => CallbackAction::Preset(parse_number(args, data)?),
+            "preset_run" => CallbackAction::PresetRun(parse_number(args, data)?),
+            "preset_edit" => CallbackAction::PresetEdit(parse_number(args, data)?),
+            "preset_dup" => CallbackAction::PresetDuplicate(parse_number(args, data)?),
+            "preset_del" => CallbackAction::PresetDelete(parse_number(args, data)?),
+            "cabinet" => CallbackAction::Cabinet(parse_number(args, data)?),
+            "cab_sel" => CallbackAction::CabinetSelect(parse_number(args, data)?),
+            "cab_ren" => CallbackAction::CabinetRename(parse_number(args, data)?),
+            "cab_tok" => CallbackAction::CabinetToken(parse_number(args, data)?),
+            "cab_del" => CallbackAction::CabinetDelete(parse_number(args, data)?),
+            "bc_ok" => CallbackAction::BroadcastConfirm(parse_number(args, data)?),
+            "bc_cancel" => CallbackAction::BroadcastCancel(parse_number(args, data)?),
+            "preset_put" | "preset_rmw" => {
+                let (preset_id, warehouse_id) = pair()?;
+                let preset_id = parse_number(preset_id, data)?;
+                let warehouse_id = parse_number(warehouse_id, data)?;
+                if tag == "preset_put" {
+                    CallbackAction::PresetPut { preset_id, warehouse_id }
+                } else {
+                    CallbackAction::PresetRemoveWarehouse { preset_id, warehouse_id }
+                }
+            }
+            "phone" | "cheap_bt" => return Err(malformed()),
+            _ => return Err(CallbackDataError::Unknown(data.to_string())),
+        };
+        Ok(action)
+    }
+
+    // callback_data для кнопки: длинные данные сохраняем в базе и передаем короткий id.
+    // Если сохранить не удалось, кнопку не строим: подмененное действие увело бы не туда
+    pub async fn to_callback_data(&self, storage: &dyn Storage) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let data = self.encode();
+        if data.len() <= CALLBACK_DATA_LIMIT {
+            return Ok(data);
+        }
+        let id = storage.store_callback_payload(&data).await?;
+        Ok(format!("{}{}", STORED_PREFIX, id))
+    }
+
+    // Разбор callback_data, пришедшей от Telegram, с подгрузкой сохраненных данных
+    pub async fn from_callback_data(storage: &dyn Storage, data: &str) -> Result<CallbackAction, CallbackDataError> {
+        match data.strip_prefix(STORED_PREFIX) {
+            Some(id) => {
+                let id: i64 = id
+                    .parse()
+                    .map_err(|_| CallbackDataError::Malformed(data.to_string()))?;
+                match storage.load_callback_payload(id).await {
+                    Ok(Some(payload)) => CallbackAction::decode(&payload),
+                    _ => Err(CallbackDataError::Expired),
+                }
+            }
+            None => CallbackAction::decode(data),
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(s: &str, data: &str) -> Result<T, CallbackDataError> {
+    s.parse().map_err(|_| CallbackDataError::Malformed(data.to_string()))
+}
+
+// Кнопка для действий без произвольного текста, они всегда влезают в лимит Telegram
+pub fn callback_button<T: Into<String>>(text: T, action: CallbackAction) -> InlineKeyboardButton {
+    let data = action.encode();
+    debug_assert!(data.len() <= CALLBACK_DATA_LIMIT, "{}", data);
+    InlineKeyboardButton::callback(text, data)
+}
+
+// Кнопка для действий с типом поставки в данных: длинные названия могут не влезть в лимит
+pub async fn long_callback_button<T: Into<String>>(
+    storage: &dyn Storage,
+    text: T,
+    action: CallbackAction,
+) -> Result<InlineKeyboardButton, Box<dyn Error + Send + Sync>> {
+    Ok(InlineKeyboardButton::callback(text, action.to_callback_data(storage).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_storage::tests::{open_temp, remove_temp};
+
+    fn round_trip(action: CallbackAction) {
+        let encoded = action.encode();
+        assert_eq!(CallbackAction::decode(&encoded), Ok(action), "{}", encoded);
+    }
+
+    #[test]
+    fn round_trips_simple_actions() {
+        round_trip(CallbackAction::MainMenu);
+        round_trip(CallbackAction::TokenLifetime);
+        round_trip(CallbackAction::EnterToken);
+        round_trip(CallbackAction::WarehousesList);
+        round_trip(CallbackAction::AnotherWarehouse);
+        round_trip(CallbackAction::SubscriptionsList);
+        round_trip(CallbackAction::PresetNew);
+        round_trip(CallbackAction::CabinetsList);
+        round_trip(CallbackAction::CheapestSearch);
+        round_trip(CallbackAction::FavouritesList);
+        round_trip(CallbackAction::WarehouseSearch);
+        round_trip(CallbackAction::CheapestAllWarehouses);
+        round_trip(CallbackAction::CabinetAdd);
+        round_trip(CallbackAction::ProfilesList);
+        round_trip(CallbackAction::PhoneLogin);
+    }
+
+    #[test]
+    fn round_trips_numeric_actions() {
+        round_trip(CallbackAction::AnotherBoxType { warehouse_id: 507 });
+        round_trip(CallbackAction::WarehousesPage(3));
+        round_trip(CallbackAction::PhonesPage(0));
+        round_trip(CallbackAction::Warehouse(117986));
+        round_trip(CallbackAction::SubscriptionDelete(42));
+        round_trip(CallbackAction::SubscriptionAutoBook(42));
+        round_trip(CallbackAction::PresetsPage(2));
+        round_trip(CallbackAction::Preset(1));
+        round_trip(CallbackAction::PresetRun(2));
+        round_trip(CallbackAction::PresetEdit(3));
+        round_trip(CallbackAction::PresetDuplicate(4));
+        round_trip(CallbackAction::PresetDelete(5));
+        round_trip(CallbackAction::PresetPut { preset_id: 6, warehouse_id: 507 });
+        round_trip(CallbackAction::PresetRemoveWarehouse { preset_id: 7, warehouse_id: 117986 });
+        round_trip(CallbackAction::Cabinet(8));
+        round_trip(CallbackAction::CheapestPreset(13));
+        round_trip(CallbackAction::CheapestScopePage(2));
+        round_trip(CallbackAction::CheapestPage(1));
+        round_trip(CallbackAction::FavouriteToggle(507));
+        round_trip(CallbackAction::CabinetSelect(9));
+        round_trip(CallbackAction::CabinetRename(10));
+        round_trip(CallbackAction::CabinetToken(11));
+        round_trip(CallbackAction::CabinetDelete(12));
+        round_trip(CallbackAction::BroadcastConfirm(14));
+        round_trip(CallbackAction::BroadcastCancel(15));
+    }
+
+    #[test]
+    fn round_trips_text_actions() {
+        round_trip(CallbackAction::Phone("9991234567".to_string()));
+        round_trip(CallbackAction::ProfileRelogin("9991234567".to_string()));
+        round_trip(CallbackAction::ProfileDelete("9991234567".to_string()));
+        for box_type in ["Короба", "QR-поставка с коробами", "a:b c"] {
+            round_trip(CallbackAction::BoxType { warehouse_id: 507, box_type: box_type.to_string() });
+            round_trip(CallbackAction::Subscribe { warehouse_id: 507, box_type: box_type.to_string() });
+            round_trip(CallbackAction::PresetAdd { warehouse_id: 507, box_type: box_type.to_string() });
+            round_trip(CallbackAction::CoefficientHistory { warehouse_id: 507, box_type: box_type.to_string() });
+            round_trip(CallbackAction::CoefficientChart { warehouse_id: 507, box_type: box_type.to_string() });
+            round_trip(CallbackAction::CheapestBoxType(box_type.to_string()));
+        }
+    }
+
+    #[test]
+    fn decodes_legacy_box_type_format() {
+        assert_eq!(
+            CallbackAction::decode("boxtype:QR-поставка с коробами whid:507"),
+            Ok(CallbackAction::BoxType {
+                warehouse_id: 507,
+                box_type: "QR-поставка с коробами".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(matches!(CallbackAction::decode("another_box_type_callback:x"), Err(CallbackDataError::Malformed(_))));
+        assert!(matches!(CallbackAction::decode("whid:"), Err(CallbackDataError::Malformed(_))));
+        assert!(matches!(CallbackAction::decode("bt:507"), Err(CallbackDataError::Malformed(_))));
+        assert!(matches!(CallbackAction::decode("bt:507:"), Err(CallbackDataError::Malformed(_))));
+        assert!(matches!(CallbackAction::decode("preset_put:1"), Err(CallbackDataError::Malformed(_))));
+        assert!(matches!(CallbackAction::decode("phone:"), Err(CallbackDataError::Malformed(_))));
+        assert!(matches!(CallbackAction::decode("cheap_bt:"), Err(CallbackDataError::Malformed(_))));
+        assert!(matches!(CallbackAction::decode("nope"), Err(CallbackDataError::Unknown(_))));
+    }
+
+    #[tokio::test]
+    async fn short_actions_fit_telegram_limit() {
+        let action = CallbackAction::Subscribe {
+            warehouse_id: 117986,
+            box_type: "QR-поставка с коробами".to_string(),
+        };
+        assert!(action.encode().len() <= CALLBACK_DATA_LIMIT);
+        let (storage, path) = open_temp("short_callbacks");
+        assert_eq!(action.to_callback_data(&storage).await.unwrap(), action.encode());
+        remove_temp(path);
+    }
+
+    #[tokio::test]
+    async fn long_actions_are_stored_in_database() {
+        let (storage, path) = open_temp("long_callbacks");
+        let action = CallbackAction::CoefficientHistory {
+            warehouse_id: 117986,
+            box_type: "Суперсейф с монопаллетами и коробами".to_string(),
+        };
+        assert!(action.encode().len() > CALLBACK_DATA_LIMIT);
+
+        let data = action.to_callback_data(&storage).await.unwrap();
+        assert!(data.starts_with(STORED_PREFIX), "{}", data);
+        assert!(data.len() <= CALLBACK_DATA_LIMIT);
+        // Те же данные сохраняются под тем же id
+        assert_eq!(action.to_callback_data(&storage).await.unwrap(), data);
+        assert_eq!(CallbackAction::from_callback_data(&storage, &data).await, Ok(action));
+        assert_eq!(CallbackAction::from_callback_data(&storage, "cb:999999").await, Err(CallbackDataError::Expired));
+        remove_temp(path);
+    }
+}
@@ -1,23 +1,56 @@
 use std::error::Error;
-use teloxide::{prelude::*, types::CallbackQuery, Bot};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use teloxide::{prelude::*, types::CallbackQuery, types::InputFile, Bot};
+use tokio::task;
 
-use crate::api_reauests::{fetch_and_store_coefficients, fetch_warehouses};
+use crate::admins::{authorize, Role};
+use crate::api_reauests::{
+    fetch_and_store_coefficients, fetch_coefficients, fetch_warehouses, CoefficientResponse,
+    Warehouse, WbApiError,
+};
+use crate::broadcast::format_progress;
+use crate::charts::{render_coefficients_chart, CHART_DAYS};
+use crate::cheapest_search::results_page;
+use crate::coefficient_history::{format_history, HISTORY_PAST_DAYS};
 use crate::database::*;
+use crate::dialogue::{CabinetDraft, DialogueState, MemberDialogue, PresetDraft};
 use crate::keyboards::*;
+use crate::commands_handlers::reply_login_step;
+use crate::config;
+use crate::portal_login::{format_phone, start_login};
+use crate::seller_portal::{lock_profile, remove_profile_dir, PORTAL_CONFIG};
+use crate::storage::Storage;
 use crate::token_decoder::*;
 
+// В группе токен и кабинеты общие, поэтому менять их может только администратор группы
+pub async fn can_manage_cabinets(bot: &Bot, member: Member) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    if !member.is_group() {
+        return Ok(true);
+    }
+    Ok(bot.get_chat_member(member.chat, member.user).await?.is_privileged())
+}
+
+pub async fn group_admins_only(bot: &Bot, member: Member) -> Result<(), Box<dyn Error + Send + Sync>> {
+    bot.send_message(member.chat, "Токен и кабинеты группы может менять только администратор группы")
+        .reply_markup(main_menu())
+        .await?;
+    Ok(())
+}
+
 pub async fn main_menu_callback(
     bot: Bot,
     q: CallbackQuery,
+    dialogue: &MemberDialogue,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(message) = q.message {
         bot.send_message(message.chat().id, "Главное меню")
             .reply_markup(main_menu())
             .await?;
-        set_user_state(q.from.id, State::Idle).await?;
+        dialogue.exit().await?;
         Ok(())
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции main_menu_callback из callback_handlers.rs".into())
     }
@@ -26,11 +59,26 @@ pub async fn main_menu_callback(
 pub async fn token_lifetime_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(message) = q.message {
-        if get_user_state(q.from.id).await? == State::TokenEntered {
-            let token = get_user_token(q.from.id).await?;
-            let msg_to_user = format!("Токен действителен до {}", get_lifetime_str(token).await?);
+        let cabinet = storage.get_active_cabinet(member.chat).await?;
+        let (cabinet_name, token) = cabinet.map(|c| (c.name, c.token)).unwrap_or_default();
+        if let Ok(claims) = TokenClaims::parse(&token) {
+            let status = match token_status(&token) {
+                TokenStatus::Expired { .. } => "Срок действия токена истек",
+                TokenStatus::ExpiringSoon { .. } => "⚠️Срок действия токена скоро закончится",
+                _ => "Токен действителен",
+            };
+            let msg_to_user = format!(
+                "🗂Кабинет: {}\n{}\nДействует до {}\nКатегории: {}{}",
+                cabinet_name,
+                status,
+                get_lifetime_str(token).await?,
+                claims.scopes_str(),
+                if claims.read_only { "\nТолько на чтение" } else { "" }
+            );
             bot.delete_message(message.chat().id, message.id()).await?;
             bot.send_message(message.chat().id, msg_to_user).await?;
             bot.send_message(message.chat().id, "Главное меню")
@@ -42,7 +90,7 @@ pub async fn token_lifetime_callback(
         }
         Ok(())
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции token_lifetime_callback из callback_handlers.rs".into())
     }
@@ -51,48 +99,68 @@ pub async fn token_lifetime_callback(
 pub async fn warehouses_list_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(message) = q.message {
-        match get_user_token(q.from.id).await {
-            Ok(token) => {
-                if token.is_empty() {
-                    set_user_state(q.from.id, State::AwaitingToken).await?;
-                    bot.send_message(q.from.id, "Введите токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>")
+        match storage.get_active_cabinet(member.chat).await {
+            Ok(None) if !can_manage_cabinets(&bot, member).await? => group_admins_only(&bot, member).await,
+            Ok(None) => {
+                dialogue.update(DialogueState::AwaitingToken { cabinet: None }).await?;
+                bot.send_message(member.chat, "Введите токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>")
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(to_main_menu_button())
+                .await?;
+                Ok(())
+            }
+            Ok(Some(cabinet)) => {
+                let token = cabinet.token;
+                let status = token_status(&token);
+                if !status.is_usable() && !can_manage_cabinets(&bot, member).await? {
+                    group_admins_only(&bot, member).await
+                } else if token.is_empty() {
+                    dialogue.update(DialogueState::AwaitingToken { cabinet: None }).await?;
+                    bot.send_message(member.chat, "Введите токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>")
                     .parse_mode(teloxide::types::ParseMode::Html)
                     .reply_markup(to_main_menu_button())
                     .await?;
                     Ok(())
+                } else if status.is_usable() {
+                    fetch_warehouses(storage, &token).await?;
+                    bot.edit_message_text(message.chat().id, message.id(), format!("🗂Кабинет: {}\nВыберите склад", cabinet.name))
+                        .await?;
+                    bot.edit_message_reply_markup(message.chat().id, message.id())
+                        .reply_markup(create_warehouse_keyboard(storage, member.chat, 0, config::get().page_size).await)
+                        .await?;
+                    Ok(())
                 } else {
-                    if is_token_expired(token.clone()).await? {
-                        fetch_warehouses(&token).await?;
-                        bot.edit_message_text(message.chat().id, message.id(), "Выберите склад")
-                            .await?;
-                        bot.edit_message_reply_markup(message.chat().id, message.id())
-                            .reply_markup(create_warehouse_keyboard(0, 10).await)
-                            .await?;
-                        Ok(())
+                    dialogue
+                        .update(DialogueState::AwaitingToken { cabinet: Some(CabinetDraft::Edit { cabinet_id: cabinet.id }) })
+                        .await?;
+                    let msg_to_user = if status == TokenStatus::Malformed {
+                        "Сохраненный токен некорректен.\n\nВведите новый токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>".to_string()
                     } else {
-                        set_user_state(q.from.id, State::AwaitingToken).await?;
-                        let msg_to_user = format!("Срок действия токена истек. Действовал до {}.\n\nВведите новый токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>", get_lifetime_str(token.clone()).await?);
-                        bot.send_message(message.chat().id, msg_to_user)
-                            .parse_mode(teloxide::types::ParseMode::Html)
-                            .reply_markup(to_main_menu_button())
-                            .await?;
-                        Ok(())
-                    }
+                        format!("Срок действия токена истек. Действовал до {}.\n\nВведите новый токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>", get_lifetime_str(token.clone()).await?)
+                    };
+                    bot.send_message(message.chat().id, msg_to_user)
+                        .parse_mode(teloxide::types::ParseMode::Html)
+                        .reply_markup(to_main_menu_button())
+                        .await?;
+                    Ok(())
                 }
             }
             Err(e) => {
-                bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+                bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
                     .await?;
                 bot.send_message(message.chat().id, "Главное меню")
                     .reply_markup(main_menu())
                     .await?;
-                return Err(format!("Ошибка при работе функции warehouses_list_callback из callback_handlers.rs: {}", e).into());
+                Err(format!("Ошибка при работе функции warehouses_list_callback из callback_handlers.rs: {}", e).into())
             }
         }
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции warehouses_list_callback из callback_handlers.rs".into())
     }
@@ -101,15 +169,17 @@ pub async fn warehouses_list_callback(
 pub async fn warehouses_page_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
     page: i32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(message) = q.message {
         bot.edit_message_reply_markup(message.chat().id, message.id())
-            .reply_markup(create_warehouse_keyboard(page, 10).await)
+            .reply_markup(create_warehouse_keyboard(storage, member.chat, page, config::get().page_size).await)
             .await?;
         Ok(())
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции warehouses_page_callback из callback_handlers.rs".into())
     }
@@ -118,31 +188,182 @@ pub async fn warehouses_page_callback(
 pub async fn phone_page_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
     page: i32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(message) = q.message {
         bot.edit_message_reply_markup(message.chat().id, message.id())
-            .reply_markup(create_user_profiles_keyboard(q.from.id, page, 10).await)
+            .reply_markup(create_user_profiles_keyboard(storage, member.chat, page, config::get().page_size).await)
             .await?;
         Ok(())
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции warehouses_page_callback из callback_handlers.rs".into())
     }
 }
 
+pub async fn profiles_list_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        bot.edit_message_text(
+            message.chat().id,
+            message.id(),
+            "📱Профили браузера с входом в кабинет продавца WB. Они нужны для автобронирования слотов",
+        )
+        .reply_markup(create_user_profiles_keyboard(storage, member.chat, 0, config::get().page_size).await)
+        .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции profiles_list_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn phone_login_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        if storage.count_user_numbers(member.chat).await? >= config::get().portal.max_profiles_per_chat {
+            bot.edit_message_text(
+                message.chat().id,
+                message.id(),
+                format!("Можно сохранить не больше {} профилей. Удалите ненужный профиль, чтобы добавить новый", config::get().portal.max_profiles_per_chat),
+            )
+            .reply_markup(create_user_profiles_keyboard(storage, member.chat, 0, config::get().page_size).await)
+            .await?;
+            return Ok(());
+        }
+        dialogue.update(DialogueState::AwaitingNumber).await?;
+        bot.edit_message_text(
+            message.chat().id,
+            message.id(),
+            "Введите номер телефона, привязанный к кабинету продавца WB, например +7 999 123-45-67",
+        )
+        .reply_markup(to_main_menu_button())
+        .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции phone_login_callback из callback_handlers.rs".into())
+    }
+}
+
+fn format_profile_time(timestamp: Option<i64>) -> String {
+    match timestamp.map(|t| Utc.timestamp_opt(t, 0)) {
+        Some(chrono::LocalResult::Single(t)) => (t + config::utc_offset()).format("%d.%m.%Y %H:%M").to_string(),
+        _ => "—".to_string(),
+    }
+}
+
+pub fn describe_profile(profile: &BrowserProfile) -> String {
+    let health = match profile.health {
+        ProfileHealth::Active => "✅Вход действует",
+        ProfileHealth::Expired => "⛔️Вход истек, войдите заново",
+        ProfileHealth::Unknown => "❔Вход еще не проверялся",
+    };
+    format!(
+        "📱Профиль {}
+{}
+
+Последний вход: {}
+Проверен: {}",
+        format_phone(&profile.phone),
+        health,
+        format_profile_time(profile.last_login_at),
+        format_profile_time(profile.checked_at),
+    )
+}
+
+pub async fn profile_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    phone: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(ref message) = q.message {
+        match storage.get_browser_profile(member.chat, &phone).await? {
+            Some(profile) => {
+                bot.edit_message_text(message.chat().id, message.id(), describe_profile(&profile))
+                    .reply_markup(create_profile_keyboard(&profile.phone))
+                    .await?;
+                Ok(())
+            }
+            None => profiles_list_callback(bot, q, storage).await,
+        }
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции profile_callback из callback_handlers.rs".into())
+    }
+}
+
+// Повторный вход с тем же профилем: номер уже известен, сразу открываем портал
+pub async fn profile_relogin_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    phone: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if storage.get_browser_profile(member.chat, &phone).await?.is_none() {
+        return profiles_list_callback(bot, q, storage).await;
+    }
+    bot.send_message(member.chat, format!("⏳ Открываю вход в кабинет для номера {}, это может занять до минуты", format_phone(&phone)))
+        .await?;
+    let step = start_login(storage, member, phone.clone()).await;
+    reply_login_step(&bot, storage, dialogue, member, phone, step).await
+}
+
+// Удаляет профиль чата вместе с его папкой Chrome
+pub async fn profile_delete_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    phone: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    // Дожидаемся, пока профилем перестанут пользоваться вход и бронирование
+    let _profile = lock_profile(member.chat, &phone).await;
+    if storage.delete_browser_profile(member.chat, &phone).await? {
+        if let Err(e) = remove_profile_dir(&PORTAL_CONFIG, member.chat, &phone).await {
+            eprintln!("Ошибка при удалении папки профиля {}: {:?}", format_phone(&phone), e);
+        }
+    }
+    profiles_list_callback(bot, q, storage).await
+}
+
 pub async fn warehouse_choosed_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
     warehouse_id: i32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(ref message) = q.message {
-        let token = get_user_token(q.from.id).await?;
-        match fetch_and_store_coefficients(&token, Some(vec![warehouse_id.try_into()?])).await {
+        // Склад выбран, поиск по названию больше не нужен
+        if dialogue.get().await? == Some(DialogueState::AwaitingWarehouseSearch) {
+            dialogue.exit().await?;
+        }
+        let token = storage.get_user_token(member.chat).await?;
+        match fetch_and_store_coefficients(storage, &token, Some(vec![warehouse_id.try_into()?])).await {
             Ok(()) => {
                 // Обработка успешного выполнения
-                match get_unique_box_types(warehouse_id).await {
+                match storage.get_unique_box_types(warehouse_id).await {
                     Ok(box_types) => {
                         bot.edit_message_text(
                             message.chat().id,
@@ -151,7 +372,7 @@ pub async fn warehouse_choosed_callback(
                         )
                         .await?;
                         bot.edit_message_reply_markup(message.chat().id, message.id())
-                            .reply_markup(create_box_types_keyboard(box_types, warehouse_id))
+                            .reply_markup(create_box_types_keyboard(storage, box_types, warehouse_id, storage.is_favourite_warehouse(member.chat, warehouse_id).await?).await?)
                             .await?;
                     }
                     Err(e) => eprintln!("Ошибка при получении данных: {:?}", e),
@@ -160,12 +381,17 @@ pub async fn warehouse_choosed_callback(
             }
             Err(e) => {
                 // Обработка ошибки  create_warehouse_keyboard(0, 10)
+                let reason = match e.downcast_ref::<WbApiError>() {
+                    Some(api_error) => api_error.user_message(),
+                    None => "WB не предоставил информации по данному складу".to_string(),
+                };
                 bot.edit_message_text(
                     message.chat().id,
                     message.id(),
-                    "WB не предоставил информации по данному складу\nВыберите другой склад",
+                    format!("{}\nВыберите другой склад", reason),
                 )
-                .reply_markup(create_warehouse_keyboard(0, 10).await)
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(create_warehouse_keyboard(storage, member.chat, 0, config::get().page_size).await)
                 .await?;
                 eprintln!(
                     "Произошла ошибка в функции fetch_and_store_coefficients: {:?}",
@@ -175,75 +401,127 @@ pub async fn warehouse_choosed_callback(
             }
         }
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции warehouse_choosed из callback_handlers.rs".into())
     }
 }
 
+async fn get_warehouse_data(storage: &dyn Storage, wid: i32, btype: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let wname = storage.get_warehouse_name(wid).await?.ok_or("Склад не найден")?;
+    let mut result = format!("📍Склад: {}\n📦Тип поставки: {}\n\n", wname, btype);
+
+    let coefficients = storage.get_available_coefficients(wid, btype).await?;
+    for (date, coefficient) in &coefficients {
+        // Преобразование unix времени в формат dd.mm.yyyy hh:mm:ss и перевод в московское время
+        let moscow_time = Utc.timestamp_opt(*date, 0).unwrap() + config::utc_offset();
+        result.push_str(&format!(
+            "⌛️Дата: {}\n📈Коэффициент: {}\n\n",
+            moscow_time.format("%d.%m.%Y %H:%M:%S"),
+            coefficient
+        ));
+    }
+    if coefficients.is_empty() {
+        result.push_str("⛔️Нет доступных поставок");
+    }
+    Ok(result)
+}
+
 pub async fn box_type_choosed_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
+    whid: i32,
+    boxtype: String,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    match parse_callback_boxtype_text(&q.data.unwrap()) {
-        Some((boxtype, whid)) => {
-            if let Some(message) = q.message {
-                let msg_to_user = get_warehouse_data(whid, boxtype.clone()).await?;
-                bot.delete_message(message.chat().id, message.id()).await?;
-                bot.send_message(message.chat().id, msg_to_user)
-                    .reply_markup(create_coefficents_keyboard(whid, whid))
-                    .await?;
-                Ok(())
-            } else {
-                bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
-                    .await?;
-                Err("Ошибка при работе функции box_type_choosed_callback из callback_handlers.rs: не удалось получить message".into())
-            }
-        }
-        None => {
-            bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
-                .await?;
-            Err("Ошибка при работе функции parse_callback_boxtype_text из callback_handlers.rs: не удалось получить текст коллбэка".into())
-        }
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let msg_to_user = get_warehouse_data(storage, whid, &boxtype).await?;
+        bot.delete_message(message.chat().id, message.id()).await?;
+        bot.send_message(message.chat().id, msg_to_user)
+            .reply_markup(create_coefficents_keyboard(storage, whid, &boxtype).await?)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции box_type_choosed_callback из callback_handlers.rs: не удалось получить message".into())
     }
 }
 
-fn parse_callback_boxtype_text(callback_text: &str) -> Option<(String, i32)> {
-    let parts: Vec<&str> = callback_text.split_whitespace().collect();
-
-    let mut boxtype = String::new();
-    let mut whid: i32 = 0;
+pub async fn coefficient_history_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    warehouse_id: i32,
+    box_type: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let date_from = (Utc::now() - Duration::days(HISTORY_PAST_DAYS)).timestamp();
+        let changes = storage.get_coefficient_history(warehouse_id, &box_type, date_from).await?;
+        let warehouse_name = storage.get_warehouse_name(warehouse_id).await?.unwrap_or_else(|| warehouse_id.to_string());
+        bot.send_message(message.chat().id, format_history(&warehouse_name, &box_type, &changes))
+            .reply_markup(create_coefficents_keyboard(storage, warehouse_id, &box_type).await?)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции coefficient_history_callback из callback_handlers.rs".into())
+    }
+}
 
-    for part in parts {
-        if part.starts_with("boxtype:") {
-            boxtype = part.trim_start_matches("boxtype:").to_string();
-            if boxtype == "QR-поставка" {
-                boxtype = "QR-поставка с коробами".to_string();
-            }
-        } else if part.starts_with("whid:") {
-            whid = part[5..].parse().unwrap_or(0);
+pub async fn coefficient_chart_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    warehouse_id: i32,
+    box_type: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        // Даты WB - полночь UTC, график начинаем с сегодняшней
+        let date_from = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+        let date_to = date_from + Duration::days(CHART_DAYS).num_seconds();
+        let points = storage.get_warehouse_coefficients(warehouse_id, &box_type, date_from, date_to).await?;
+        let warehouse_name = storage.get_warehouse_name(warehouse_id).await?.unwrap_or_else(|| warehouse_id.to_string());
+        if points.is_empty() {
+            bot.send_message(message.chat().id, format!("📍Склад: {}\n📦Тип поставки: {}\n\n⛔️Нет данных для графика", warehouse_name, box_type))
+                .reply_markup(create_coefficents_keyboard(storage, warehouse_id, &box_type).await?)
+                .await?;
+            return Ok(());
         }
-    }
 
-    if !boxtype.is_empty() {
-        Some((boxtype, whid))
+        let title = format!("{} / {}", warehouse_name, box_type);
+        // Отрисовка занимает процессор, поэтому не делаем ее в потоке рантайма
+        let png = task::spawn_blocking(move || render_coefficients_chart(&title, date_from, &points)).await??;
+        bot.send_photo(message.chat().id, InputFile::memory(png).file_name("chart.png"))
+            .caption(format!("📍Склад: {}\n📦Тип поставки: {}\n📊Коэффициенты на {} дней", warehouse_name, box_type, CHART_DAYS))
+            .reply_markup(create_coefficents_keyboard(storage, warehouse_id, &box_type).await?)
+            .await?;
+        Ok(())
     } else {
-        None
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции coefficient_chart_callback из callback_handlers.rs".into())
     }
 }
 
 pub async fn another_warehouse_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
     msg: String,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(message) = q.message {
         bot.send_message(message.chat().id, msg)
-            .reply_markup(create_warehouse_keyboard(0, 10).await)
+            .reply_markup(create_warehouse_keyboard(storage, member.chat, 0, config::get().page_size).await)
             .await?;
         Ok(())
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции warehouses_page_callback из callback_handlers.rs".into())
     }
@@ -252,21 +530,920 @@ pub async fn another_warehouse_callback(
 pub async fn another_box_type_callback(
     bot: Bot,
     q: CallbackQuery,
+    storage: &dyn Storage,
     warehouse_id: i32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
     if let Some(message) = q.message {
-        match get_unique_box_types(warehouse_id).await {
+        match storage.get_unique_box_types(warehouse_id).await {
             Ok(box_types) => {
                 bot.send_message(message.chat().id, "Выберите тип поставки")
-                    .reply_markup(create_box_types_keyboard(box_types, warehouse_id))
+                    .reply_markup(create_box_types_keyboard(storage, box_types, warehouse_id, storage.is_favourite_warehouse(member.chat, warehouse_id).await?).await?)
                     .await?;
             }
             Err(e) => eprintln!("Ошибка при получении данных: {:?}", e),
         }
         Ok(())
     } else {
-        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
             .await?;
         Err("Ошибка при работе функции warehouse_choosed из callback_handlers.rs".into())
     }
 }
+
+pub async fn subscribe_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    dialogue: &MemberDialogue,
+    warehouse_id: i32,
+    box_type: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        dialogue
+            .update(DialogueState::AwaitingSubscriptionParams { warehouse_id, box_type_name: box_type.clone() })
+            .await?;
+        bot.send_message(
+            message.chat().id,
+            format!("📦Тип поставки: {}\n\nВведите максимальный коэффициент, при котором прислать уведомление.\n\nМожно указать период дат через пробел, например:\n<code>1 01.11.2024-15.11.2024</code>", box_type),
+        )
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(to_main_menu_button())
+        .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции subscribe_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn subscriptions_list_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let subscriptions = storage.get_user_subscriptions(member.chat).await?;
+        let msg_to_user = if subscriptions.is_empty() {
+            "У вас нет подписок.\n\nЧтобы подписаться, выберите склад и тип поставки, затем нажмите «🔔Уведомить о коэффициенте»"
+        } else {
+            "Ваши подписки. Нажмите на подписку, чтобы удалить её, 🤖 — чтобы включить или выключить автобронирование слота"
+        };
+        bot.edit_message_text(message.chat().id, message.id(), msg_to_user)
+            .reply_markup(create_subscriptions_keyboard(&subscriptions))
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции subscriptions_list_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn subscription_delete_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    subscription_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        storage.delete_subscription(member.chat, subscription_id).await?;
+        let subscriptions = storage.get_user_subscriptions(member.chat).await?;
+        bot.edit_message_text(message.chat().id, message.id(), "Подписка удалена")
+            .reply_markup(create_subscriptions_keyboard(&subscriptions))
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции subscription_delete_callback из callback_handlers.rs".into())
+    }
+}
+
+// Переключает автобронирование подписки. Бронирует бот через первый профиль браузера чата
+pub async fn subscription_auto_book_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    subscription_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let subscriptions = storage.get_user_subscriptions(member.chat).await?;
+        let enabled = match subscriptions.iter().find(|s| s.id == subscription_id) {
+            Some(subscription) => subscription.auto_book_phone.is_some(),
+            None => {
+                bot.edit_message_text(message.chat().id, message.id(), "Подписка не найдена")
+                    .reply_markup(create_subscriptions_keyboard(&subscriptions))
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let msg_to_user = if enabled {
+            storage.set_subscription_auto_book(member.chat, subscription_id, None).await?;
+            "Автобронирование выключено".to_string()
+        } else {
+            match storage.get_user_browser_profiles_page(member.chat, 0, 1).await?.into_iter().next() {
+                Some(phone) => {
+                    storage.set_subscription_auto_book(member.chat, subscription_id, Some(&phone)).await?;
+                    format!("🤖 Автобронирование включено: при появлении подходящего слота бот сам забронирует его в кабинете {} и пришлет скриншот", format_phone(&phone))
+                }
+                None => "Для автобронирования нужен профиль браузера с выполненным входом в кабинет продавца WB. Войдите по номеру телефона в разделе «📱Вход в кабинет по номеру» главного меню".to_string(),
+            }
+        };
+
+        let subscriptions = storage.get_user_subscriptions(member.chat).await?;
+        bot.edit_message_text(message.chat().id, message.id(), msg_to_user)
+            .reply_markup(create_subscriptions_keyboard(&subscriptions))
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции subscription_auto_book_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn presets_page_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    page: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let msg_to_user = if storage.count_user_presets(member.chat).await? == 0 {
+            "У вас нет пресетов.\n\nЧтобы создать пресет, выберите склад и тип поставки, затем нажмите «💾Сохранить в пресет»"
+        } else {
+            "Ваши пресеты"
+        };
+        bot.edit_message_text(message.chat().id, message.id(), msg_to_user)
+            .reply_markup(create_presets_keyboard(storage, member.chat, page, config::get().page_size).await?)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции presets_page_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn preset_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    preset_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        match storage.get_preset(member.chat, preset_id).await? {
+            Some(preset) => {
+                bot.edit_message_text(message.chat().id, message.id(), describe_preset(&preset))
+                    .reply_markup(create_preset_keyboard(preset.id))
+                    .await?;
+            }
+            None => {
+                bot.edit_message_text(message.chat().id, message.id(), "Пресет не найден")
+                    .reply_markup(create_presets_keyboard(storage, member.chat, 0, config::get().page_size).await?)
+                    .await?;
+            }
+        }
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции preset_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn preset_run_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    preset_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let preset = match storage.get_preset(member.chat, preset_id).await? {
+            Some(preset) => preset,
+            None => {
+                bot.edit_message_text(message.chat().id, message.id(), "Пресет не найден")
+                    .reply_markup(create_presets_keyboard(storage, member.chat, 0, config::get().page_size).await?)
+                    .await?;
+                return Ok(());
+            }
+        };
+        let token = storage.get_user_token(member.chat).await?;
+        if token.is_empty() {
+            bot.edit_message_text(message.chat().id, message.id(), "Токен не был введен. Откройте «📍Коэффиценты складов», чтобы ввести токен")
+                .reply_markup(main_menu())
+                .await?;
+            return Ok(());
+        }
+
+        let warehouse_ids = preset.warehouses.iter().map(|w| w.id).collect();
+        match fetch_coefficients(&token, Some(warehouse_ids)).await {
+            Ok(coefficients) => {
+                let msg_to_user = format_preset_result(&preset, &coefficients);
+                storage.add_or_update_warehouse_coefficents(coefficients).await?;
+                bot.edit_message_text(message.chat().id, message.id(), msg_to_user)
+                    .reply_markup(create_preset_keyboard(preset.id))
+                    .await?;
+                Ok(())
+            }
+            Err(e) => {
+                bot.edit_message_text(message.chat().id, message.id(), e.user_message())
+                    .parse_mode(teloxide::types::ParseMode::Html)
+                    .reply_markup(create_preset_keyboard(preset.id))
+                    .await?;
+                Err(e.into())
+            }
+        }
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции preset_run_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn preset_edit_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    preset_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        match storage.get_preset(member.chat, preset_id).await? {
+            Some(preset) => {
+                dialogue
+                    .update(DialogueState::AwaitingPresetParams { preset: PresetDraft::Edit { preset_id } })
+                    .await?;
+                let msg_to_user = format!(
+                    "{}\n\nОтправьте новое название и параметры пресета: название в первой строке, во второй — максимальный коэффициент и, при необходимости, период, например:\n<code>Подмосковье\n1 01.11.2024-15.11.2024</code>",
+                    describe_preset(&preset)
+                );
+                bot.edit_message_text(message.chat().id, message.id(), msg_to_user)
+                    .parse_mode(teloxide::types::ParseMode::Html)
+                    .reply_markup(create_preset_edit_keyboard(&preset))
+                    .await?;
+            }
+            None => {
+                bot.edit_message_text(message.chat().id, message.id(), "Пресет не найден")
+                    .reply_markup(create_presets_keyboard(storage, member.chat, 0, config::get().page_size).await?)
+                    .await?;
+            }
+        }
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции preset_edit_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn preset_duplicate_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    preset_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(new_id) = storage.duplicate_preset(member.chat, preset_id).await? {
+        preset_callback(bot, q, storage, new_id).await
+    } else {
+        preset_callback(bot, q, storage, preset_id).await
+    }
+}
+
+pub async fn preset_delete_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    preset_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    storage.delete_preset(member.chat, preset_id).await?;
+    presets_page_callback(bot, q, storage, 0).await
+}
+
+pub async fn preset_add_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    warehouse_id: i32,
+    box_type: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let presets = storage.get_user_presets_by_box_type(member.chat, &box_type).await?;
+        dialogue.update(DialogueState::ChoosingPreset { warehouse_id, box_type_name: box_type }).await?;
+        bot.send_message(message.chat().id, "Добавьте склад в существующий пресет с этим типом поставки или создайте новый")
+            .reply_markup(create_preset_choice_keyboard(presets, warehouse_id))
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции preset_add_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn preset_new_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    dialogue: &MemberDialogue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let Some(DialogueState::ChoosingPreset { warehouse_id, box_type_name }) = dialogue.get().await? else {
+            bot.edit_message_text(message.chat().id, message.id(), "Склад для пресета не выбран, начните заново")
+                .reply_markup(main_menu())
+                .await?;
+            return Ok(());
+        };
+        dialogue
+            .update(DialogueState::AwaitingPresetParams { preset: PresetDraft::New { warehouse_id, box_type_name } })
+            .await?;
+        bot.edit_message_text(
+            message.chat().id,
+            message.id(),
+            "Отправьте название пресета в первой строке, во второй — максимальный коэффициент и, при необходимости, период, например:\n<code>Подмосковье\n1 01.11.2024-15.11.2024</code>",
+        )
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(to_main_menu_button())
+        .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции preset_new_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn preset_put_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    preset_id: i64,
+    warehouse_id: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    storage.add_warehouse_to_preset(member.chat, preset_id, warehouse_id).await?;
+    dialogue.exit().await?;
+    preset_callback(bot, q, storage, preset_id).await
+}
+
+pub async fn preset_remove_warehouse_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    preset_id: i64,
+    warehouse_id: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    storage.remove_warehouse_from_preset(member.chat, preset_id, warehouse_id).await?;
+    preset_edit_callback(bot, q, storage, dialogue, preset_id).await
+}
+
+pub fn describe_preset(preset: &Preset) -> String {
+    let warehouses = preset
+        .warehouses
+        .iter()
+        .map(|w| w.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "📋Пресет: {}\n📍Склады: {}\n📦Тип поставки: {}\n📈Коэффициент: до {}\n⌛️Период: {}",
+        preset.name,
+        warehouses,
+        preset.box_type_name,
+        preset.max_coefficient,
+        format_period(preset.date_from, preset.date_to),
+    )
+}
+
+fn format_period(date_from: Option<i64>, date_to: Option<i64>) -> String {
+    let format_date = |date: i64| match Utc.timestamp_opt(date, 0) {
+        chrono::LocalResult::Single(t) => t.format("%d.%m.%Y").to_string(),
+        _ => "?".to_string(),
+    };
+    match (date_from, date_to) {
+        (Some(from), Some(to)) => format!("{} - {}", format_date(from), format_date(to)),
+        _ => "любой".to_string(),
+    }
+}
+
+// Максимум слотов в одном сообщении, чтобы не упереться в лимит длины сообщения
+const PRESET_RESULT_LIMIT: usize = 30;
+
+fn format_preset_result(preset: &Preset, coefficients: &[CoefficientResponse]) -> String {
+    let mut slots: Vec<(&CoefficientResponse, DateTime<Utc>)> = coefficients
+        .iter()
+        .filter_map(|c| c.date.parse::<DateTime<Utc>>().ok().map(|date| (c, date)))
+        .filter(|(c, date)| preset.matches(c, date.timestamp()))
+        .collect();
+    slots.sort_by(|a, b| a.0.coefficient.cmp(&b.0.coefficient).then(a.1.cmp(&b.1)));
+
+    let mut result = format!("{}\n\n", describe_preset(preset));
+    if slots.is_empty() {
+        result.push_str("⛔️Нет доступных поставок");
+        return result;
+    }
+    for (coefficient, date) in slots.iter().take(PRESET_RESULT_LIMIT) {
+        let moscow_time = *date + config::utc_offset();
+        result.push_str(&format!(
+            "📍{}\n⌛️Дата: {}\n📈Коэффициент: {}\n\n",
+            coefficient.warehouse_name,
+            moscow_time.format("%d.%m.%Y"),
+            coefficient.coefficient
+        ));
+    }
+    if slots.len() > PRESET_RESULT_LIMIT {
+        result.push_str(&format!("…и еще {}", slots.len() - PRESET_RESULT_LIMIT));
+    }
+    result
+}
+
+pub async fn enter_token_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    dialogue: &MemberDialogue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    // Без черновика токен сохранится в активный кабинет
+    dialogue.update(DialogueState::AwaitingToken { cabinet: None }).await?;
+    bot.send_message(member.chat, "Введите новый токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>")
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(to_main_menu_button())
+        .await?;
+    Ok(())
+}
+
+// Описание кабинета: название, срок действия и категории токена
+pub async fn describe_cabinet(cabinet: &Cabinet, is_active: bool) -> String {
+    let mut result = format!("🗂Кабинет: {}{}\n", cabinet.name, if is_active { " (активный)" } else { "" });
+    match TokenClaims::parse(&cabinet.token) {
+        Ok(claims) => {
+            let status = match token_status(&cabinet.token) {
+                TokenStatus::Expired { .. } => "⛔️Срок действия токена истек",
+                TokenStatus::ExpiringSoon { .. } => "⚠️Срок действия токена скоро закончится",
+                _ => "✅Токен действителен",
+            };
+            let lifetime = get_lifetime_str(cabinet.token.clone()).await.unwrap_or_else(|_| "?".to_string());
+            result.push_str(&format!("{}\nДействует до {}\nКатегории: {}", status, lifetime, claims.scopes_str()));
+        }
+        Err(_) => result.push_str("⛔️Токен некорректен"),
+    }
+    result
+}
+
+pub async fn cabinets_list_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let cabinets = storage.get_user_cabinets(member.chat).await?;
+        let active_id = storage.get_active_cabinet(member.chat).await?.map(|c| c.id);
+        let msg_to_user = if cabinets.is_empty() {
+            "У вас нет кабинетов.\n\nДобавьте кабинет и введите для него токен WB".to_string()
+        } else {
+            let mut text = "Ваши кабинеты. Коэффициенты и склады показываются для активного кабинета ✅\n".to_string();
+            for cabinet in &cabinets {
+                let lifetime = get_lifetime_str(cabinet.token.clone()).await.unwrap_or_else(|_| "токен некорректен".to_string());
+                text.push_str(&format!("\n{} — до {}", cabinet.name, lifetime));
+            }
+            text
+        };
+        bot.edit_message_text(message.chat().id, message.id(), msg_to_user)
+            .reply_markup(create_cabinets_keyboard(&cabinets, active_id))
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции cabinets_list_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn cabinet_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    cabinet_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(ref message) = q.message {
+        match storage.get_cabinet(member.chat, cabinet_id).await? {
+            Some(cabinet) => {
+                let is_active = storage.get_active_cabinet(member.chat).await?.is_some_and(|c| c.id == cabinet.id);
+                bot.edit_message_text(message.chat().id, message.id(), describe_cabinet(&cabinet, is_active).await)
+                    .reply_markup(create_cabinet_keyboard(cabinet.id, is_active))
+                    .await?;
+                Ok(())
+            }
+            None => cabinets_list_callback(bot, q, storage).await,
+        }
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции cabinet_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn cabinet_add_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    dialogue: &MemberDialogue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    dialogue
+        .update(DialogueState::AwaitingCabinetName { cabinet: CabinetDraft::New { name: None } })
+        .await?;
+    bot.send_message(member.chat, "Введите название нового кабинета, например <code>ИП Иванов</code>")
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(to_main_menu_button())
+        .await?;
+    Ok(())
+}
+
+pub async fn cabinet_select_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    cabinet_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    storage.set_active_cabinet(member.chat, cabinet_id).await?;
+    cabinet_callback(bot, q, storage, cabinet_id).await
+}
+
+pub async fn cabinet_rename_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    cabinet_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    match storage.get_cabinet(member.chat, cabinet_id).await? {
+        Some(cabinet) => {
+            dialogue
+                .update(DialogueState::AwaitingCabinetName { cabinet: CabinetDraft::Edit { cabinet_id } })
+                .await?;
+            bot.send_message(member.chat, format!("Введите новое название для кабинета «{}»", cabinet.name))
+                .reply_markup(to_main_menu_button())
+                .await?;
+            Ok(())
+        }
+        None => cabinets_list_callback(bot, q, storage).await,
+    }
+}
+
+pub async fn cabinet_token_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    cabinet_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    match storage.get_cabinet(member.chat, cabinet_id).await? {
+        Some(cabinet) => {
+            dialogue
+                .update(DialogueState::AwaitingToken { cabinet: Some(CabinetDraft::Edit { cabinet_id }) })
+                .await?;
+            bot.send_message(member.chat, format!("Введите новый токен для кабинета «{}»\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>", cabinet.name))
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(to_main_menu_button())
+                .await?;
+            Ok(())
+        }
+        None => {
+            bot.send_message(member.chat, "Кабинет не найден")
+                .reply_markup(main_menu())
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+pub async fn cabinet_delete_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    cabinet_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    storage.delete_cabinet(member.chat, cabinet_id).await?;
+    cabinets_list_callback(bot, q, storage).await
+}
+
+pub async fn cheapest_search_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let mut box_types = storage.get_all_box_types().await?;
+        // Кэш пуст - запрашиваем коэффициенты по всем складам, чтобы узнать типы поставки
+        if box_types.is_empty() {
+            let token = storage.get_user_token(member.chat).await?;
+            if token.is_empty() {
+                bot.edit_message_text(message.chat().id, message.id(), "Токен не был введен. Откройте «📍Коэффиценты складов», чтобы ввести токен")
+                    .reply_markup(main_menu())
+                    .await?;
+                return Ok(());
+            }
+            if let Err(e) = fetch_and_store_coefficients(storage, &token, None).await {
+                let reason = match e.downcast_ref::<WbApiError>() {
+                    Some(api_error) => api_error.user_message(),
+                    None => "Не удалось получить коэффициенты".to_string(),
+                };
+                bot.edit_message_text(message.chat().id, message.id(), reason)
+                    .parse_mode(teloxide::types::ParseMode::Html)
+                    .reply_markup(main_menu())
+                    .await?;
+                return Err(e);
+            }
+            box_types = storage.get_all_box_types().await?;
+        }
+        bot.edit_message_text(message.chat().id, message.id(), "🔎Поиск дешевой приемки по всем складам\n\nВыберите тип поставки")
+            .reply_markup(create_cheapest_box_types_keyboard(storage, box_types).await?)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции cheapest_search_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn cheapest_box_type_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    box_type: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        storage.start_cheapest_search(member, box_type.clone()).await?;
+        bot.edit_message_text(message.chat().id, message.id(), format!("📦Тип поставки: {}\n\nГде искать?", box_type))
+            .reply_markup(create_cheapest_scope_keyboard(storage, member.chat, 0, config::get().page_size).await?)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции cheapest_box_type_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn cheapest_scope_page_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    page: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        bot.edit_message_reply_markup(message.chat().id, message.id())
+            .reply_markup(create_cheapest_scope_keyboard(storage, member.chat, page, config::get().page_size).await?)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции cheapest_scope_page_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn cheapest_scope_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    preset_id: Option<i64>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    let warehouse_ids = match preset_id {
+        Some(preset_id) => match storage.get_preset(member.chat, preset_id).await? {
+            Some(preset) => Some(preset.warehouses.iter().map(|w| w.id).collect()),
+            None => {
+                bot.send_message(member.chat, "Пресет не найден")
+                    .reply_markup(main_menu())
+                    .await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    storage.set_cheapest_search_warehouses(member, warehouse_ids).await?;
+    dialogue.update(DialogueState::AwaitingSearchParams).await?;
+    bot.send_message(member.chat, "Введите максимальный коэффициент и, при необходимости, период:\n<code>1 01.11.2024-15.11.2024</code>\n\nЧтобы искать только в городе или регионе, во второй строке перечислите части названий складов через запятую:\n<code>1\nМосква, Подольск</code>")
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(to_main_menu_button())
+        .await?;
+    Ok(())
+}
+
+pub async fn cheapest_page_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    page: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let (text, keyboard) = results_page(storage, member, page).await?;
+        bot.edit_message_text(message.chat().id, message.id(), text)
+            .reply_markup(keyboard)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции cheapest_page_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn favourite_toggle_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    warehouse_id: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let is_favourite = storage.toggle_favourite_warehouse(member.chat, warehouse_id).await?;
+        let box_types = storage.get_unique_box_types(warehouse_id).await?;
+        bot.edit_message_reply_markup(message.chat().id, message.id())
+            .reply_markup(create_box_types_keyboard(storage, box_types, warehouse_id, is_favourite).await?)
+            .await?;
+        Ok(())
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции favourite_toggle_callback из callback_handlers.rs".into())
+    }
+}
+
+// Лучший коэффициент по каждому типу поставки для каждого избранного склада
+fn format_favourites(warehouses: &[Warehouse], coefficients: &[CoefficientResponse]) -> String {
+    let mut result = "⭐Мои склады: лучшие коэффициенты\n".to_string();
+    for w in warehouses {
+        result.push_str(&format!("\n📍{}\n", w.name));
+        let mut best: Vec<(&str, i32, &str)> = vec![];
+        for c in coefficients.iter().filter(|c| c.warehouse_id == w.id && c.coefficient != -1) {
+            match best.iter_mut().find(|(box_type, _, _)| *box_type == c.box_type_name) {
+                Some(entry) if (c.coefficient, c.date.as_str()) < (entry.1, entry.2) => {
+                    *entry = (&c.box_type_name, c.coefficient, &c.date);
+                }
+                Some(_) => {}
+                None => best.push((&c.box_type_name, c.coefficient, &c.date)),
+            }
+        }
+        if best.is_empty() {
+            result.push_str("⛔️Нет доступных поставок\n");
+            continue;
+        }
+        best.sort_by(|a, b| a.0.cmp(b.0));
+        for (box_type, coefficient, date) in best {
+            let date = date
+                .parse::<DateTime<Utc>>()
+                .map(|d| (d + config::utc_offset()).format("%d.%m.%Y").to_string())
+                .unwrap_or_default();
+            result.push_str(&format!("📦{}: {} ({})\n", box_type, coefficient, date));
+        }
+    }
+    result
+}
+
+pub async fn favourites_list_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    if let Some(message) = q.message {
+        let warehouses = storage.get_favourite_warehouses(member.chat).await?;
+        if warehouses.is_empty() {
+            bot.edit_message_text(message.chat().id, message.id(), "У вас нет избранных складов.\n\nЧтобы добавить склад, выберите его в «📍Коэффиценты складов» и нажмите «⭐ В избранное»")
+                .reply_markup(main_menu())
+                .await?;
+            return Ok(());
+        }
+        let token = storage.get_user_token(member.chat).await?;
+        if token.is_empty() {
+            bot.edit_message_text(message.chat().id, message.id(), "Токен не был введен. Откройте «📍Коэффиценты складов», чтобы ввести токен")
+                .reply_markup(main_menu())
+                .await?;
+            return Ok(());
+        }
+
+        let warehouse_ids = warehouses.iter().map(|w| w.id).collect();
+        match fetch_coefficients(&token, Some(warehouse_ids)).await {
+            Ok(coefficients) => {
+                let msg_to_user = format_favourites(&warehouses, &coefficients);
+                storage.add_or_update_warehouse_coefficents(coefficients).await?;
+                bot.edit_message_text(message.chat().id, message.id(), msg_to_user)
+                    .reply_markup(create_favourites_keyboard(&warehouses))
+                    .await?;
+                Ok(())
+            }
+            Err(e) => {
+                bot.edit_message_text(message.chat().id, message.id(), e.user_message())
+                    .parse_mode(teloxide::types::ParseMode::Html)
+                    .reply_markup(create_favourites_keyboard(&warehouses))
+                    .await?;
+                Err(e.into())
+            }
+        }
+    } else {
+        bot.send_message(member.chat, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции favourites_list_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn warehouse_search_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    dialogue: &MemberDialogue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let member = Member::from_callback(&q);
+    dialogue.update(DialogueState::AwaitingWarehouseSearch).await?;
+    bot.send_message(member.chat, "Введите часть названия склада, например <code>Коледино</code> или <code>Казань</code>")
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(to_main_menu_button())
+        .await?;
+    Ok(())
+}
+
+pub async fn broadcast_confirm_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    broadcast_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !authorize(storage, q.from.id, Role::Admin).await? {
+        bot.send_message(q.from.id, "Недостатоно прав.").await?;
+        return Ok(());
+    }
+    if let Some(message) = q.message {
+        // Сообщение с подтверждением превращается в отчет о прогрессе
+        let recipients = storage.get_active_users(message.chat().id).await?;
+        match storage.start_broadcast(broadcast_id, message.id().0, &recipients).await? {
+            Some(recipients) => {
+                let stats = BroadcastStats { total: recipients as i64, ..Default::default() };
+                bot.edit_message_text(message.chat().id, message.id(), format_progress(broadcast_id, "sending", &stats))
+                    .reply_markup(broadcast_progress_keyboard(broadcast_id))
+                    .await?;
+            }
+            None => {
+                bot.edit_message_text(message.chat().id, message.id(), "Рассылка уже запущена или отменена")
+                    .await?;
+            }
+        }
+        Ok(())
+    } else {
+        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции broadcast_confirm_callback из callback_handlers.rs".into())
+    }
+}
+
+pub async fn broadcast_cancel_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    storage: &dyn Storage,
+    broadcast_id: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !authorize(storage, q.from.id, Role::Admin).await? {
+        bot.send_message(q.from.id, "Недостатоно прав.").await?;
+        return Ok(());
+    }
+    if let Some(message) = q.message {
+        let was_draft = storage.get_broadcast(broadcast_id).await?.is_some_and(|b| b.status == "draft");
+        if storage.cancel_broadcast(broadcast_id).await? && was_draft {
+            bot.edit_message_text(message.chat().id, message.id(), "Рассылка отменена").await?;
+        }
+        // Запущенную рассылку останавливает фоновая задача и сама обновляет прогресс
+        Ok(())
+    } else {
+        bot.send_message(q.from.id, "Внутренняя ошибка, попробуйте повторить позже")
+            .await?;
+        Err("Ошибка при работе функции broadcast_cancel_callback из callback_handlers.rs".into())
+    }
+}
@@ -0,0 +1,144 @@
+// Картинки с графиками коэффициентов. Рисуем сами через plotters, без внешних сервисов
+use chrono::{Duration, TimeZone, Utc};
+use plotters::prelude::*;
+use plotters::style::{register_font, FontStyle};
+use std::error::Error;
+use std::sync::Once;
+
+// Шрифт с кириллицей вшит в бинарник, чтобы не зависеть от шрифтов в системе
+const FONT: &[u8] = include_bytes!("../assets/DejaVuSans.ttf");
+static FONT_REGISTERED: Once = Once::new();
+
+// На сколько дней вперед строим график
+pub const CHART_DAYS: i64 = 14;
+const WIDTH: u32 = 900;
+const HEIGHT: u32 = 500;
+
+const FREE_COLOR: RGBColor = RGBColor(46, 160, 67); // бесплатная приемка
+const PAID_COLOR: RGBColor = RGBColor(230, 140, 30); // платная приемка
+const UNAVAILABLE_COLOR: RGBColor = RGBColor(190, 190, 190); // приемка недоступна (-1)
+
+fn register_chart_font() {
+    FONT_REGISTERED.call_once(|| {
+        if register_font("sans-serif", FontStyle::Normal, FONT).is_err() {
+            eprintln!("Не удалось загрузить шрифт для графиков");
+        }
+    });
+}
+
+fn date_label(date: i64) -> String {
+    match Utc.timestamp_opt(date, 0) {
+        chrono::LocalResult::Single(t) => t.format("%d.%m").to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+// PNG с коэффициентами по дням начиная с date_from (полночь UTC, как в датах WB).
+// points - пары (дата, коэффициент), даты вне диапазона игнорируются
+pub fn render_coefficients_chart(
+    title: &str,
+    date_from: i64,
+    points: &[(i64, i32)],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    register_chart_font();
+    let day = Duration::days(1).num_seconds();
+    let days: Vec<(i64, i32)> = points
+        .iter()
+        .filter(|(date, _)| *date >= date_from && *date < date_from + CHART_DAYS * day)
+        .map(|(date, coefficient)| ((date - date_from) / day, *coefficient))
+        .collect();
+    let max_coefficient = days.iter().map(|(_, c)| *c).max().unwrap_or(0).max(1);
+    let y_max = (max_coefficient + 1) as f64;
+
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE).map_err(|e| e.to_string())?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 22))
+            .margin(15)
+            .x_label_area_size(35)
+            .y_label_area_size(40)
+            // День i занимает отрезок [i - 0.5, i + 0.5], чтобы подпись даты была под столбцом
+            .build_cartesian_2d(-0.5f64..CHART_DAYS as f64 - 0.5, 0f64..y_max)
+            .map_err(|e| e.to_string())?;
+
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(CHART_DAYS as usize)
+            .max_light_lines(0)
+            .x_label_formatter(&|x| date_label(date_from + (x.round() as i64) * day))
+            .y_labels((max_coefficient as usize + 2).min(11))
+            .y_label_formatter(&|y| format!("{:.0}", y))
+            .y_desc("Коэффициент")
+            .label_style(("sans-serif", 14))
+            .draw()
+            .map_err(|e| e.to_string())?;
+
+        // Столбец на каждый день: бесплатные - зеленым, платные - оранжевым, недоступные - серым на всю высоту
+        let bars = days.iter().map(|(index, coefficient)| {
+            let x0 = *index as f64 - 0.35;
+            let x1 = *index as f64 + 0.35;
+            let (top, color) = match *coefficient {
+                -1 => (y_max, UNAVAILABLE_COLOR.mix(0.5)),
+                0 => (y_max * 0.03, FREE_COLOR.mix(1.0)),
+                c => (c as f64, PAID_COLOR.mix(1.0)),
+            };
+            Rectangle::new([(x0, 0.0), (x1, top)], color.filled())
+        });
+        chart.draw_series(bars).map_err(|e| e.to_string())?;
+
+        // Легенда
+        for (text, color) in [
+            ("0 - бесплатно", FREE_COLOR),
+            ("платно", PAID_COLOR),
+            ("недоступно", UNAVAILABLE_COLOR),
+        ] {
+            chart
+                .draw_series(std::iter::once(Rectangle::new([(0.0, 0.0), (0.0, 0.0)], color.filled())))
+                .map_err(|e| e.to_string())?
+                .label(text)
+                .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 12, y + 5)], color.filled()));
+        }
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .label_font(("sans-serif", 14))
+            .position(SeriesLabelPosition::UpperRight)
+            .draw()
+            .map_err(|e| e.to_string())?;
+
+        root.present().map_err(|e| e.to_string())?;
+    }
+
+    encode_png(&buffer, WIDTH, HEIGHT)
+}
+
+fn encode_png(rgb: &[u8], width: u32, height: u32) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut png_bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png_bytes, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(rgb)?;
+    }
+    Ok(png_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_png() {
+        let day = Duration::days(1).num_seconds();
+        let date_from = 1725408000; // 04.09.2024 00:00 UTC
+        let points = vec![(date_from, 0), (date_from + day, 3), (date_from + 2 * day, -1), (date_from + 40 * day, 7)];
+        let png_bytes = render_coefficients_chart("Коледино / Короба", date_from, &points).unwrap();
+        assert_eq!(&png_bytes[..8], b"\x89PNG\r\n\x1a\n");
+    }
+}
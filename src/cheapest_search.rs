@@ -0,0 +1,137 @@
+use chrono::{TimeZone, Utc};
+use std::error::Error;
+use teloxide::types::InlineKeyboardMarkup;
+
+use crate::config;
+use crate::database::{CachedSlot, CheapestSearch, Member};
+use crate::keyboards::{create_cheapest_results_keyboard, main_menu};
+use crate::storage::Storage;
+
+// Подходящие слоты по возрастанию коэффициента, затем даты и названия склада
+pub fn rank_slots(search: &CheapestSearch, slots: Vec<CachedSlot>) -> Vec<CachedSlot> {
+    let mut ranked: Vec<CachedSlot> = slots.into_iter().filter(|slot| search.matches(slot)).collect();
+    ranked.sort_by(|a, b| {
+        a.coefficient
+            .cmp(&b.coefficient)
+            .then(a.date.cmp(&b.date))
+            .then(a.warehouse_name.cmp(&b.warehouse_name))
+    });
+    ranked
+}
+
+fn format_date(date: i64) -> String {
+    match Utc.timestamp_opt(date, 0) {
+        chrono::LocalResult::Single(t) => (t + config::utc_offset()).format("%d.%m.%Y").to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+pub fn describe_search(search: &CheapestSearch) -> String {
+    let warehouses = match (&search.warehouse_ids, search.name_filter.is_empty()) {
+        (Some(ids), true) => format!("выбрано {}", ids.len()),
+        (Some(ids), false) => format!("выбрано {}, с названием: {}", ids.len(), search.name_filter.join(", ")),
+        (None, false) => format!("с названием: {}", search.name_filter.join(", ")),
+        (None, true) => "все".to_string(),
+    };
+    let period = match (search.date_from, search.date_to) {
+        (Some(from), Some(to)) => format!("{} - {}", format_date(from), format_date(to)),
+        _ => "любой".to_string(),
+    };
+    format!(
+        "🔎Поиск дешевой приемки\n📦Тип поставки: {}\n📍Склады: {}\n📈Коэффициент: до {}\n⌛️Период: {}",
+        search.box_type_name,
+        warehouses,
+        search.max_coefficient.map(|c| c.to_string()).unwrap_or_else(|| "любой".to_string()),
+        period,
+    )
+}
+
+fn button_text(slot: &CachedSlot) -> String {
+    format!("{} · {} · {}", slot.coefficient, format_date(slot.date), slot.warehouse_name)
+}
+
+// Страница результатов последнего поиска участника по закэшированным коэффициентам
+pub async fn results_page(storage: &dyn Storage, member: Member, page: i32) -> Result<(String, InlineKeyboardMarkup), Box<dyn Error + Send + Sync>> {
+    let search = match storage.get_cheapest_search(member).await? {
+        Some(search) if search.max_coefficient.is_some() => search,
+        _ => return Ok(("Поиск не найден, начните заново".to_string(), main_menu())),
+    };
+    let ranked = rank_slots(&search, storage.get_box_type_slots(&search.box_type_name).await?);
+
+    let page_size = config::get().page_size as usize;
+    let page = page.max(0) as usize;
+    let start = page * page_size;
+    let slots: Vec<(String, CachedSlot)> = ranked
+        .iter()
+        .skip(start)
+        .take(page_size)
+        .map(|slot| (button_text(slot), slot.clone()))
+        .collect();
+    let has_next = ranked.len() > start + page_size;
+
+    let mut text = describe_search(&search);
+    if ranked.is_empty() {
+        text.push_str("\n\n⛔️Нет подходящих слотов");
+    } else {
+        text.push_str(&format!(
+            "\n\nНайдено вариантов: {}. Коэффициент · дата · склад, нажмите на вариант, чтобы открыть склад",
+            ranked.len()
+        ));
+    }
+    let keyboard = create_cheapest_results_keyboard(storage, &slots, &search.box_type_name, page as i32, has_next).await?;
+    Ok((text, keyboard))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(warehouse_id: u32, name: &str, date: i64, coefficient: i32) -> CachedSlot {
+        CachedSlot {
+            warehouse_id,
+            warehouse_name: name.to_string(),
+            date,
+            coefficient,
+        }
+    }
+
+    fn search() -> CheapestSearch {
+        CheapestSearch {
+            box_type_name: "Короба".to_string(),
+            warehouse_ids: None,
+            name_filter: vec![],
+            max_coefficient: Some(3),
+            date_from: Some(100),
+            date_to: Some(300),
+        }
+    }
+
+    #[test]
+    fn ranks_matching_slots_by_coefficient_then_date() {
+        let slots = vec![
+            slot(1, "Коледино", 200, 2),
+            slot(2, "Казань", 100, 0),
+            slot(1, "Коледино", 100, 2),
+            slot(3, "Тула", 100, -1),  // недоступно
+            slot(3, "Тула", 150, 5),   // дороже максимума
+            slot(2, "Казань", 400, 0), // вне периода
+        ];
+        let ranked = rank_slots(&search(), slots);
+        assert_eq!(
+            ranked,
+            vec![slot(2, "Казань", 100, 0), slot(1, "Коледино", 100, 2), slot(1, "Коледино", 200, 2)]
+        );
+    }
+
+    #[test]
+    fn filters_by_warehouse_set_and_name() {
+        let slots = vec![slot(1, "Коледино", 100, 0), slot(2, "Казань", 100, 0), slot(3, "Подольск 3", 100, 1)];
+
+        let by_ids = CheapestSearch { warehouse_ids: Some(vec![2, 3]), ..search() };
+        assert_eq!(rank_slots(&by_ids, slots.clone()).len(), 2);
+
+        let by_name = CheapestSearch { name_filter: vec!["подольск".to_string(), "колед".to_string()], ..search() };
+        let names: Vec<String> = rank_slots(&by_name, slots).into_iter().map(|s| s.warehouse_name).collect();
+        assert_eq!(names, vec!["Коледино", "Подольск 3"]);
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{TimeZone, Utc};
+
+use crate::config;
+use crate::database::CoefficientChange;
+
+// Сколько дней хранить историю, если не задан COEFFICIENT_HISTORY_DAYS
+pub const DEFAULT_RETENTION_DAYS: i64 = 90;
+// Сколько дат поставки показывать в одном сообщении
+const HISTORY_DATES_LIMIT: usize = 14;
+// За сколько прошедших дней показывать историю, чтобы было видно, когда открывались слоты
+pub const HISTORY_PAST_DAYS: i64 = 7;
+
+// Сводка по одной дате поставки
+#[derive(Debug, PartialEq)]
+pub struct SlotHistory {
+    pub date: i64,
+    pub last: i32,
+    pub min: Option<i32>,       // минимум среди доступных значений (без -1)
+    pub max: Option<i32>,       // максимум среди доступных значений (без -1)
+    pub changes: usize,         // сколько раз менялся коэффициент после первого наблюдения
+    pub last_change_at: i64,    // когда коэффициент менялся последний раз
+    pub opened_at: Option<i64>, // когда слот впервые стал доступен после -1
+}
+
+// changes должны быть отсортированы по дате поставки и времени наблюдения
+pub fn summarize(changes: &[CoefficientChange]) -> Vec<SlotHistory> {
+    let mut result: Vec<SlotHistory> = Vec::new();
+    for change in changes {
+        let available = (change.coefficient != -1).then_some(change.coefficient);
+        match result.last_mut() {
+            Some(slot) if slot.date == change.date => {
+                if slot.last == -1 && available.is_some() && slot.opened_at.is_none() {
+                    slot.opened_at = Some(change.observed_at);
+                }
+                slot.last = change.coefficient;
+                slot.min = slot.min.into_iter().chain(available).min();
+                slot.max = slot.max.into_iter().chain(available).max();
+                slot.changes += 1;
+                slot.last_change_at = change.observed_at;
+            }
+            _ => result.push(SlotHistory {
+                date: change.date,
+                last: change.coefficient,
+                min: available,
+                max: available,
+                changes: 0,
+                last_change_at: change.observed_at,
+                opened_at: None,
+            }),
+        }
+    }
+    result
+}
+
+fn moscow_time(timestamp: i64, format: &str) -> String {
+    match Utc.timestamp_opt(timestamp, 0) {
+        chrono::LocalResult::Single(t) => (t + config::utc_offset()).format(format).to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+fn coefficient_str(coefficient: Option<i32>) -> String {
+    match coefficient {
+        Some(-1) | None => "недоступно".to_string(),
+        Some(c) => c.to_string(),
+    }
+}
+
+pub fn format_history(warehouse_name: &str, box_type_name: &str, changes: &[CoefficientChange]) -> String {
+    let mut result = format!("📈История коэффициентов\n📍Склад: {}\n📦Тип поставки: {}\n\n", warehouse_name, box_type_name);
+    let slots = summarize(changes);
+    if slots.is_empty() {
+        result.push_str("Истории пока нет: она собирается каждый раз, когда бот получает коэффициенты склада");
+        return result;
+    }
+
+    for slot in slots.iter().take(HISTORY_DATES_LIMIT) {
+        result.push_str(&format!(
+            "⌛️{}: сейчас {}, мин {} / макс {}\n🔄Изменений: {}, последнее {}\n",
+            moscow_time(slot.date, "%d.%m.%Y"),
+            coefficient_str(Some(slot.last)),
+            coefficient_str(slot.min),
+            coefficient_str(slot.max),
+            slot.changes,
+            moscow_time(slot.last_change_at, "%d.%m %H:%M"),
+        ));
+        if let Some(opened_at) = slot.opened_at {
+            result.push_str(&format!("🔓Открылся: {}\n", moscow_time(opened_at, "%d.%m %H:%M")));
+        }
+        result.push('\n');
+    }
+    if slots.len() > HISTORY_DATES_LIMIT {
+        result.push_str(&format!("…и еще дат: {}", slots.len() - HISTORY_DATES_LIMIT));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(date: i64, coefficient: i32, observed_at: i64) -> CoefficientChange {
+        CoefficientChange { date, coefficient, observed_at }
+    }
+
+    #[test]
+    fn summarizes_changes_per_date() {
+        let changes = vec![
+            change(100, -1, 10),
+            change(100, 5, 20),
+            change(100, 0, 30),
+            change(100, -1, 40),
+            change(200, 2, 15),
+        ];
+        let slots = summarize(&changes);
+        assert_eq!(
+            slots,
+            vec![
+                SlotHistory { date: 100, last: -1, min: Some(0), max: Some(5), changes: 3, last_change_at: 40, opened_at: Some(20) },
+                SlotHistory { date: 200, last: 2, min: Some(2), max: Some(2), changes: 0, last_change_at: 15, opened_at: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_empty_history() {
+        let text = format_history("Коледино", "Короба", &[]);
+        assert!(text.contains("Истории пока нет"));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use teloxide::prelude::*;
+use tokio::task;
+
+use crate::api_reauests::fetch_coefficients;
+use crate::auto_booking::{book_and_report, BookingRequest};
+use crate::broadcast::{classify, Delivery};
+use crate::config;
+use crate::database::Subscription;
+use crate::keyboards::to_main_menu_button;
+use crate::storage::Storage;
+
+// Один проход по всем подпискам: запрашиваем коэффициенты с токеном каждого подписчика
+// и отправляем уведомления о новых подходящих слотах. Лимит запросов на токен соблюдает WbClient
+pub async fn check_subscriptions(bot: &Bot, storage: &Arc<dyn Storage>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut by_user: HashMap<i64, Vec<Subscription>> = HashMap::new();
+    for subscription in storage.get_all_subscriptions().await? {
+        by_user.entry(subscription.user_id).or_default().push(subscription);
+    }
+
+    for (user_id, subscriptions) in by_user {
+        if let Err(e) = check_user_subscriptions(bot, storage, user_id, subscriptions).await {
+            eprintln!("Ошибка при проверке подписок пользователя {}: {:?}", user_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_user_subscriptions(
+    bot: &Bot,
+    storage: &Arc<dyn Storage>,
+    user_id: i64,
+    subscriptions: Vec<Subscription>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let token = storage.get_user_token(ChatId(user_id)).await?;
+    if token.is_empty() {
+        return Ok(());
+    }
+
+    let mut warehouse_ids: Vec<u32> = subscriptions
+        .iter()
+        .map(|s| s.warehouse_id as u32)
+        .collect();
+    warehouse_ids.sort_unstable();
+    warehouse_ids.dedup();
+
+    let coefficients = fetch_coefficients(&token, Some(warehouse_ids)).await?;
+
+    // Подходящие слоты: (подписка, коэффициент, дата)
+    let mut matched: Vec<(&Subscription, i32, DateTime<Utc>)> = vec![];
+    for coefficient in &coefficients {
+        let date: DateTime<Utc> = match coefficient.date.parse() {
+            Ok(date) => date,
+            Err(_) => continue,
+        };
+        let unix_time = date.timestamp();
+        for subscription in subscriptions.iter().filter(|s| s.matches(coefficient, unix_time)) {
+            matched.push((subscription, coefficient.coefficient, date));
+        }
+    }
+    // Кэш обновляем до отправки: ошибка отправки не должна оставлять его устаревшим
+    storage.add_or_update_warehouse_coefficents(coefficients).await?;
+
+    // Сверх лимита слоты не отмечаем отправленными, они придут при следующей проверке
+    let max_alerts = config::get().alerts.max_per_check;
+    let mut sent = 0;
+    for (subscription, coefficient, date) in matched {
+        if sent >= max_alerts {
+            break;
+        }
+        let unix_time = date.timestamp();
+        // Отмечаем слот до отправки, чтобы параллельная проверка не сообщила о нем второй раз
+        if !storage.mark_slot_notified(subscription.id, unix_time).await? {
+            continue;
+        }
+        let moscow_time = date + config::utc_offset();
+        let msg_to_user = format!(
+            "🔔 Найден подходящий слот!\n\n📍Склад: {}\n📦Тип поставки: {}\n⌛️Дата: {}\n📈Коэффициент: {} (порог {})",
+            subscription.warehouse_name,
+            subscription.box_type_name,
+            moscow_time.format("%d.%m.%Y"),
+            coefficient,
+            subscription.max_coefficient,
+        );
+        if let Err(e) = bot
+            .send_message(ChatId(user_id), msg_to_user)
+            .reply_markup(to_main_menu_button())
+            .await
+        {
+            storage.unmark_slot_notified(subscription.id, unix_time).await?;
+            // Бот заблокирован или чата больше нет: как и в рассылке, отключаем чат.
+            // Его подписки не проверяются, пока он снова не запустит бота
+            if classify(&e) == Delivery::Blocked {
+                storage.set_user_active(ChatId(user_id), false).await?;
+                log::warn!("Чат {} недоступен ({}), его подписки отключены", user_id, e);
+                return Ok(());
+            }
+            return Err(e.into());
+        }
+        sent += 1;
+        if let Err(e) = storage.record_usage_event("alert", ChatId(user_id), None).await {
+            eprintln!("Ошибка при записи статистики: {:?}", e);
+        }
+        // Бронирование занимает до минуты, поэтому не задерживаем остальные подписки
+        if let Some(phone) = &subscription.auto_book_phone {
+            let request = BookingRequest {
+                phone: phone.clone(),
+                warehouse_id: subscription.warehouse_id,
+                warehouse_name: subscription.warehouse_name.clone(),
+                box_type_name: subscription.box_type_name.clone(),
+                date,
+            };
+            task::spawn(book_and_report(bot.clone(), storage.clone(), ChatId(user_id), subscription.id, request));
+        }
+    }
+
+    Ok(())
+}
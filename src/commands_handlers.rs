@@ -1,6 +1,28 @@
 use std::error::Error;
+use std::sync::Arc;
+use chrono::NaiveDate;
 use teloxide::prelude::*;
-use crate::{api_reauests::*, keyboards::main_menu};
+use crate::{api_reauests::*, keyboards::main_menu, keyboards::to_main_menu_button};
+use crate::callback_handlers::{describe_cabinet, describe_preset};
+use crate::config;
+use crate::cheapest_search::results_page;
+use crate::keyboards::{
+    broadcast_confirm_keyboard, create_cabinet_keyboard, create_found_warehouses_keyboard, create_inline_warehouse_keyboard,
+    create_preset_keyboard, create_user_profiles_keyboard, open_warehouse_keyboard,
+};
+use crate::admins::{authorize, is_config_owner, owner_ids, Role};
+use crate::broadcast::{parse_broadcast, send_broadcast_message};
+use crate::portal_login::{
+    cancel_login, enter_captcha, enter_sms_code, format_phone, normalize_phone, start_login, LoginStep,
+};
+use crate::seller_portal::PortalError;
+use crate::stats::{collect_stats, format_stats};
+use crate::dialogue::{CabinetDraft, DialogueState, MemberDialogue, PresetDraft};
+use crate::storage::Storage;
+use crate::warehouse_search::search_warehouses;
+use teloxide::types::{
+    InlineQueryResult, InlineQueryResultArticle, InputFile, InputMessageContent, InputMessageContentText, Me,
+};
 
 use crate::{database::*, token_decoder::*};
 use crate::keyboards::create_warehouse_keyboard;
@@ -11,67 +33,617 @@ pub async fn help_command_handler(bot: Bot, id: ChatId) -> Result<(), Box<dyn Er
     Ok(())
 }
 
-pub async fn start_command_handler(bot: Bot, msg: &Message) -> Result<(), Box<dyn Error + Send + Sync>> {   
-    let id = UserId(msg.chat.id.0.try_into()?);
-    let username = get_username_from_msg(&msg);
-    add_user_to_db(id, username).await?; //  добавим юзера в общий список
-    set_user_state(id, State::Idle).await?;
-    bot.send_message(msg.chat.id, 
-        "🍆 Я бот для работы с <b>Wildberris</b>! 🍆\n\nНа <b>Wildberris</b> я могу показать тебе коэффиценты по складам (в скором времени надеюсь смогу уведомлять о 😋вкусных😋 коэффицентах), а так же найду слот с <b>бесплатной или платной приемкой</b> до подходящего коэффицента.\n\nВыбирай!")
+pub async fn start_command_handler(bot: Bot, msg: &Message, storage: &dyn Storage, payload: String) -> Result<(), Box<dyn Error + Send + Sync>> {   
+    let member = match Member::from_message(msg) {
+        Some(member) => member,
+        None => return Ok(()),
+    };
+    // Группа попадает в общий список под своим названием
+    let username = match msg.chat.title() {
+        Some(title) => title.to_string(),
+        None => get_username_from_msg(msg),
+    };
+    storage.add_user(msg.chat.id, username).await?; //  добавим юзера в общий список
+    storage.remove_dialogue(member).await?; // /start прерывает начатый диалог
+    let mut greeting = "🍆 Я бот для работы с <b>Wildberris</b>! 🍆\n\nНа <b>Wildberris</b> я могу показать тебе коэффиценты по складам (в скором времени надеюсь смогу уведомлять о 😋вкусных😋 коэффицентах), а так же найду слот с <b>бесплатной или платной приемкой</b> до подходящего коэффицента.\n\nВыбирай!".to_string();
+    if member.is_group() {
+        greeting.push_str("\n\nВ группе токен, кабинеты, подписки и пресеты общие. Токен и кабинеты может менять только администратор группы");
+    }
+    bot.send_message(msg.chat.id, greeting)
     .parse_mode(teloxide::types::ParseMode::Html)
     .reply_markup(main_menu())
     .await?;
+
+    // Переход по кнопке из inline-режима: t.me/<бот>?start=wh_<id склада>
+    if let Some(warehouse_id) = payload.trim().strip_prefix("wh_").and_then(|id| id.parse::<u32>().ok()) {
+        if let Some(name) = storage.get_warehouse_name(warehouse_id as i32).await? {
+            bot.send_message(msg.chat.id, format!("📍Склад: {}", name))
+                .reply_markup(open_warehouse_keyboard(warehouse_id))
+                .await?;
+        }
+    }
     Ok(())
 }
 
-pub async fn msg_to_all_command_handler(bot: Bot, msg: &Message, text: String) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let username: String = get_username_from_msg(&msg);
-    let admin_username = "SET_YOUR_LOGIN_HERE".to_string(); // Имя пользователя администратора
-    let command_sender_id = msg.chat.id.0;
+// Проверка прав отправителя команды. Если прав не хватает, сообщает об этом сам
+async fn check_role(bot: &Bot, msg: &Message, storage: &dyn Storage, required: Role) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let allowed = match &msg.from {
+        Some(user) => authorize(storage, user.id, required).await?,
+        None => false,
+    };
+    if !allowed {
+        bot.send_message(msg.chat.id, "Недостатоно прав.").await?;
+    }
+    Ok(allowed)
+}
 
-    if username == admin_username {
-        let user_ids = get_user_ids().await?;
-        for user in user_ids {
-            if user != command_sender_id {
-                bot.send_message(ChatId(user), &text).await?;
-            }            
+// Рассылка сначала сохраняется черновиком и показывается администратору,
+// отправку запускает кнопка подтверждения, дальше работает фоновая задача из broadcast.rs
+pub async fn msg_to_all_command_handler(bot: Bot, msg: &Message, storage: &dyn Storage, text: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !check_role(&bot, msg, storage, Role::Admin).await? {
+        return Ok(());
+    }
+    let content = parse_broadcast(&text);
+    let photo = msg.photo().and_then(|sizes| sizes.last()).map(|size| size.file.id.clone());
+    if content.text.is_empty() && photo.is_none() {
+        bot.send_message(msg.chat.id, "Использование: /msgtoall <текст в HTML>\nКнопки-ссылки - последними строками вида <code>Текст | https://...</code>. Чтобы отправить фото, добавьте команду в подпись к нему")
+            .parse_mode(teloxide::types::ParseMode::Html)
+            .await?;
+        return Ok(());
+    }
+
+    let broadcast_id = storage.create_broadcast(msg.chat.id, &content.text, photo, &content.buttons).await?;
+    let broadcast = storage.get_broadcast(broadcast_id).await?.ok_or("Рассылка не сохранилась")?;
+    if let Err(e) = send_broadcast_message(&bot, msg.chat.id, &broadcast).await {
+        storage.cancel_broadcast(broadcast_id).await?;
+        bot.send_message(msg.chat.id, format!("Не удалось показать рассылку, проверьте HTML: {}", e)).await?;
+        return Ok(());
+    }
+    let recipients = storage.get_active_users(msg.chat.id).await?.len() as i64;
+    bot.send_message(msg.chat.id, format!("👆 Так выглядит рассылка #{}. Получателей: {}", broadcast_id, recipients))
+        .reply_markup(broadcast_confirm_keyboard(broadcast_id, recipients))
+        .await?;
+    Ok(())
+}
+
+// Ротация ключа: новый ключ кладется в TOKEN_ENCRYPTION_KEY, прежний - в TOKEN_ENCRYPTION_OLD_KEYS,
+// после перезапуска эта команда перешифровывает все токены новым ключом
+pub async fn rotate_token_key_command_handler(bot: Bot, msg: &Message, storage: &dyn Storage) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if check_role(&bot, msg, storage, Role::Owner).await? {
+        let updated = storage.reencrypt_user_tokens().await?;
+        bot.send_message(msg.chat.id, format!("Перешифровано токенов: {}. Старые ключи можно убрать из TOKEN_ENCRYPTION_OLD_KEYS.", updated)).await?;
+    }
+    Ok(())
+}
+
+pub async fn stats_command_handler(bot: Bot, msg: &Message, storage: &dyn Storage) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if check_role(&bot, msg, storage, Role::Support).await? {
+        bot.send_message(msg.chat.id, format_stats(&collect_stats(storage).await?)).await?;
+    }
+    Ok(())
+}
+
+// Пользователь по id или @username (если он запускал бота)
+async fn resolve_user(storage: &dyn Storage, arg: &str) -> Option<UserId> {
+    match arg.strip_prefix('@') {
+        Some(username) => storage.get_id_by_username(username.to_string())
+            .await
+            .ok()
+            .and_then(|id| u64::try_from(id).ok())
+            .map(UserId),
+        None => arg.parse().ok().map(UserId),
+    }
+}
+
+pub async fn grant_role_command_handler(bot: Bot, msg: &Message, storage: &dyn Storage, args: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !check_role(&bot, msg, storage, Role::Owner).await? {
+        return Ok(());
+    }
+    let mut parts = args.split_whitespace();
+    let (target, role) = match (parts.next(), parts.next().and_then(Role::parse)) {
+        (Some(target), Some(role)) => (target, role),
+        _ => {
+            bot.send_message(msg.chat.id, "Использование: /grantrole <id или @username> <owner|admin|support>").await?;
+            return Ok(());
         }
-        bot.send_message(msg.chat.id, "Отправлено всем.").await?;
-        } else {            
-            let response_text = format!("Недостатоно прав.");
-            bot.send_message(msg.chat.id, response_text).await?;
+    };
+    let (user_id, granted_by) = match (resolve_user(storage, target).await, &msg.from) {
+        (Some(user_id), Some(sender)) => (user_id, sender.id),
+        _ => {
+            bot.send_message(msg.chat.id, "Пользователь не найден. Укажите id или @username пользователя, который запускал бота").await?;
+            return Ok(());
         }
+    };
+    storage.set_admin_role(user_id, role.as_str(), granted_by).await?;
+    bot.send_message(msg.chat.id, format!("Пользователю {} выдана роль {}", target, role.as_str())).await?;
     Ok(())
 }
 
-pub async fn text_msg_handler(bot: Bot, msg: &Message) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let id = UserId(msg.chat.id.0.try_into()?);
-    let user_state = get_user_state(id).await?;
-    if user_state == State::AwaitingToken {
-        let token = msg.text().unwrap_or("").to_string(); // Получаем введённый токен
-        let is_token_valid = check_token(&token).await?;
-        if is_token_valid {
-            set_user_state(id, State::Idle).await?;
-            set_user_token(id, token.to_string()).await?;   
-            fetch_warehouses(&token).await?;
-            bot.send_message(msg.chat.id, "Выберите склад")
-            .reply_markup(create_warehouse_keyboard(0, 10).await)
-            .await?;
+pub async fn revoke_role_command_handler(bot: Bot, msg: &Message, storage: &dyn Storage, args: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !check_role(&bot, msg, storage, Role::Owner).await? {
+        return Ok(());
+    }
+    let target = args.trim();
+    let user_id = match resolve_user(storage, target).await {
+        Some(user_id) => user_id,
+        None => {
+            bot.send_message(msg.chat.id, "Использование: /revokerole <id или @username>").await?;
+            return Ok(());
+        }
+    };
+    let msg_to_user = if is_config_owner(user_id) {
+        "Владелец задан в BOT_OWNER_IDS, его роль можно убрать только там".to_string()
+    } else if storage.delete_admin_role(user_id).await? {
+        format!("Роль пользователя {} отозвана", target)
+    } else {
+        format!("У пользователя {} нет роли", target)
+    };
+    bot.send_message(msg.chat.id, msg_to_user).await?;
+    Ok(())
+}
+
+pub async fn admins_command_handler(bot: Bot, msg: &Message, storage: &dyn Storage) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !check_role(&bot, msg, storage, Role::Support).await? {
+        return Ok(());
+    }
+    let mut text = "Администраторы бота:\n".to_string();
+    for id in owner_ids() {
+        text.push_str(&format!("\n{} — owner (BOT_OWNER_IDS)", id));
+    }
+    for (user_id, role, username) in storage.get_admins().await? {
+        match username {
+            Some(username) => text.push_str(&format!("\n{} (@{}) — {}", user_id, username, role)),
+            None => text.push_str(&format!("\n{} — {}", user_id, role)),
+        }
+    }
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+// Ответы на шаги многошаговых диалогов. В main сообщения разводятся по состояниям DialogueState,
+// данные шага (черновик кабинета, выбранный склад, номер телефона) приходят вместе с состоянием
+
+pub async fn token_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Arc<dyn Storage>,
+    member: Member,
+    dialogue: MemberDialogue,
+    cabinet: Option<CabinetDraft>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = storage.as_ref();
+    let token = msg.text().unwrap_or("").trim().to_string(); // Получаем введённый токен
+    if member.is_group() {
+        // Токен не должен оставаться в переписке группы. Без права на удаление сообщение останется
+        if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
+            log::warn!("Не удалось удалить сообщение с токеном в чате {}: {}", msg.chat.id, e);
+        }
+    }
+    match TokenClaims::parse(&token) {
+        Ok(claims) if !claims.has_scope(TokenScope::Supplies) => {
+            let msg_to_user = format!(
+                "У токена нет доступа к категории <b>'Поставки'</b>, без неё бот не сможет получить коэффициенты.\n\nКатегории этого токена: {}\n\nСоздайте токен с категорией <b>'Поставки'</b> и введите его",
+                claims.scopes_str()
+            );
+            bot.send_message(msg.chat.id, msg_to_user)
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(to_main_menu_button())
+                .await?;
+            return Ok(());
+        }
+        Ok(_) => {}
+        Err(_) => {
+            bot.send_message(msg.chat.id, "Токен невалиден, введите другой токен").await?;
+            return Ok(());
+        }
+    }
+    let is_token_valid = check_token(&token).await?;
+    if is_token_valid {
+        dialogue.exit().await?;
+        save_cabinet_token(storage, member, cabinet, token.to_string()).await?;
+        fetch_warehouses(storage, &token).await?;
+        let cabinet_name = storage.get_active_cabinet(member.chat).await?.map(|c| c.name).unwrap_or_default();
+        bot.send_message(msg.chat.id, format!("🗂Кабинет: {}\nВыберите склад", cabinet_name))
+        .reply_markup(create_warehouse_keyboard(storage, member.chat, 0, config::get().page_size).await)
+        .await?;
+    } else {
+        if matches!(token_status(&token), TokenStatus::Expired { .. }) {
+            bot.send_message(msg.chat.id, "Токен просрочен, введите другой токен").await?;
         } else {
-            if is_token_expired(token).await? {
-                bot.send_message(msg.chat.id, "Токен просрочен, введите другой токен").await?;
-            } else {
-                bot.send_message(msg.chat.id, "Токен невалиден, введите другой токен").await?;
+            bot.send_message(msg.chat.id, "Токен невалиден, введите другой токен").await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn subscription_params_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Arc<dyn Storage>,
+    dialogue: MemberDialogue,
+    (warehouse_id, box_type_name): (i32, String),
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match parse_subscription_params(msg.text().unwrap_or("")) {
+        Some(limits) => {
+            let Some(warehouse_name) = storage.get_warehouse_name(warehouse_id).await? else {
+                dialogue.exit().await?;
+                bot.send_message(msg.chat.id, "Склад не найден, выберите его заново")
+                    .reply_markup(main_menu())
+                    .await?;
+                return Ok(());
+            };
+            storage.add_subscription(dialogue.chat_id(), warehouse_id, warehouse_name, box_type_name, limits).await?;
+            dialogue.exit().await?;
+            bot.send_message(msg.chat.id, format!("🔔 Подписка создана. Пришлю уведомление, когда коэффициент будет не выше {}", limits.max_coefficient))
+                .reply_markup(main_menu())
+                .await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, "Не удалось разобрать параметры. Введите коэффициент, например <code>1</code>, или коэффициент и период: <code>1 01.11.2024-15.11.2024</code>")
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(to_main_menu_button())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn captcha_handler(bot: Bot, msg: Message, storage: Arc<dyn Storage>, member: Member, dialogue: MemberDialogue, phone: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let step = enter_captcha(storage.as_ref(), member, msg.text().unwrap_or("").trim()).await;
+    reply_login_step(&bot, storage.as_ref(), &dialogue, member, phone, step).await
+}
+
+pub async fn sms_code_handler(bot: Bot, msg: Message, storage: Arc<dyn Storage>, member: Member, dialogue: MemberDialogue, phone: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Код из СМС дает вход в кабинет, в группе его не оставляем
+    if member.is_group() {
+        if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
+            log::warn!("Не удалось удалить сообщение с кодом в чате {}: {}", msg.chat.id, e);
+        }
+    }
+    let step = enter_sms_code(storage.as_ref(), member, msg.text().unwrap_or("").trim()).await;
+    reply_login_step(&bot, storage.as_ref(), &dialogue, member, phone, step).await
+}
+
+pub async fn warehouse_search_handler(bot: Bot, msg: Message, storage: Arc<dyn Storage>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let query = msg.text().unwrap_or("");
+    let found = search_warehouses(query, storage.get_all_warehouses().await?);
+    let msg_to_user = if found.is_empty() {
+        "Склады не найдены, попробуйте другое название".to_string()
+    } else {
+        format!("Найдено складов: {}. Можно ввести другое название", found.len())
+    };
+    bot.send_message(msg.chat.id, msg_to_user)
+        .reply_markup(create_found_warehouses_keyboard(&found))
+        .await?;
+    Ok(())
+}
+
+// Проверенный токен сохраняем в кабинет из черновика, а без черновика - в активный кабинет
+async fn save_cabinet_token(
+    storage: &dyn Storage,
+    member: Member,
+    cabinet: Option<CabinetDraft>,
+    token: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match cabinet {
+        Some(CabinetDraft::New { name: Some(name) }) => {
+            storage.create_cabinet(member.chat, name, token).await?;
+        }
+        Some(CabinetDraft::Edit { cabinet_id }) => {
+            storage.update_cabinet_token(member.chat, cabinet_id, token).await?;
+            storage.set_active_cabinet(member.chat, cabinet_id).await?;
+        }
+        _ => storage.set_user_token(member.chat, token).await?,
+    }
+    Ok(())
+}
+
+pub async fn phone_number_handler(bot: Bot, msg: Message, storage: Arc<dyn Storage>, member: Member, dialogue: MemberDialogue) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let phone = match normalize_phone(msg.text().unwrap_or("")) {
+        Some(phone) => phone,
+        None => {
+            bot.send_message(msg.chat.id, "Не удалось разобрать номер. Введите номер мобильного телефона, например +7 999 123-45-67")
+                .reply_markup(to_main_menu_button())
+                .await?;
+            return Ok(());
+        }
+    };
+    // Повторный вход в уже сохраненный профиль не занимает новое место
+    if storage.get_browser_profile(member.chat, &phone).await?.is_none() && storage.count_user_numbers(member.chat).await? >= config::get().portal.max_profiles_per_chat {
+        dialogue.exit().await?;
+        bot.send_message(msg.chat.id, format!("Можно сохранить не больше {} профилей. Удалите ненужный профиль, чтобы добавить новый", config::get().portal.max_profiles_per_chat))
+            .reply_markup(create_user_profiles_keyboard(storage.as_ref(), member.chat, 0, config::get().page_size).await)
+            .await?;
+        return Ok(());
+    }
+    bot.send_message(msg.chat.id, format!("⏳ Открываю вход в кабинет для номера {}, это может занять до минуты", format_phone(&phone)))
+        .await?;
+    let step = start_login(storage.as_ref(), member, phone.clone()).await;
+    reply_login_step(&bot, storage.as_ref(), &dialogue, member, phone, step).await
+}
+
+// Ответ на очередной шаг входа по номеру. Капчу присылаем картинкой и ждем от участника следующий ввод
+pub async fn reply_login_step(
+    bot: &Bot,
+    storage: &dyn Storage,
+    dialogue: &MemberDialogue,
+    member: Member,
+    phone: String,
+    step: Result<LoginStep, PortalError>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match step {
+        Ok(LoginStep::Captcha(image)) | Ok(LoginStep::WrongCaptcha(image)) if !image.is_empty() => {
+            dialogue.update(DialogueState::AwaitingCaptcha { phone }).await?;
+            bot.send_photo(member.chat, InputFile::memory(image).file_name("captcha.png"))
+                .caption("Введите текст с картинки")
+                .reply_markup(to_main_menu_button())
+                .await?;
+        }
+        Ok(LoginStep::Captcha(_)) | Ok(LoginStep::WrongCaptcha(_)) => {
+            dialogue.exit().await?;
+            cancel_login(member).await;
+            bot.send_message(member.chat, "❌ Не удалось получить капчу, попробуйте войти еще раз")
+                .reply_markup(to_main_menu_button())
+                .await?;
+        }
+        Ok(LoginStep::SmsCode) => {
+            bot.send_message(member.chat, format!("WB отправил код в СМС на номер {}, введите его", format_phone(&phone)))
+                .reply_markup(to_main_menu_button())
+                .await?;
+        }
+        Ok(LoginStep::WrongCode) => {
+            bot.send_message(member.chat, "Код не подошел, проверьте СМС и введите код еще раз")
+                .reply_markup(to_main_menu_button())
+                .await?;
+        }
+        Ok(LoginStep::LoggedIn) => {
+            dialogue.exit().await?;
+            bot.send_message(member.chat, "✅ Вход выполнен, профиль браузера сохранен. Теперь для подписок можно включить автобронирование 🤖")
+                .reply_markup(create_user_profiles_keyboard(storage, member.chat, 0, config::get().page_size).await)
+                .await?;
+        }
+        Err(e) => {
+            dialogue.exit().await?;
+            bot.send_message(member.chat, format!("❌ Не удалось войти в кабинет: {}", e))
+                .reply_markup(to_main_menu_button())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// Ограничение на длину названия, чтобы оно помещалось в кнопку
+const CABINET_NAME_MAX_LEN: usize = 40;
+
+pub async fn cabinet_name_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Arc<dyn Storage>,
+    member: Member,
+    dialogue: MemberDialogue,
+    cabinet: CabinetDraft,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = storage.as_ref();
+    let name = msg.text().unwrap_or("").trim().to_string();
+    if name.is_empty() || name.chars().count() > CABINET_NAME_MAX_LEN {
+        bot.send_message(msg.chat.id, format!("Название должно быть от 1 до {} символов, введите другое", CABINET_NAME_MAX_LEN))
+            .reply_markup(to_main_menu_button())
+            .await?;
+        return Ok(());
+    }
+
+    let editing = match cabinet {
+        CabinetDraft::Edit { cabinet_id } => Some(cabinet_id),
+        CabinetDraft::New { .. } => None,
+    };
+    let cabinets = storage.get_user_cabinets(member.chat).await?;
+    if cabinets.iter().any(|c| c.name == name && Some(c.id) != editing) {
+        bot.send_message(msg.chat.id, "Кабинет с таким названием уже есть, введите другое")
+            .reply_markup(to_main_menu_button())
+            .await?;
+        return Ok(());
+    }
+
+    match cabinet {
+        CabinetDraft::New { .. } => {
+            dialogue
+                .update(DialogueState::AwaitingToken { cabinet: Some(CabinetDraft::New { name: Some(name.clone()) }) })
+                .await?;
+            bot.send_message(msg.chat.id, format!("Введите токен для кабинета «{}»\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>", name))
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(to_main_menu_button())
+                .await?;
+        }
+        CabinetDraft::Edit { cabinet_id } => {
+            storage.rename_cabinet(member.chat, cabinet_id, name).await?;
+            dialogue.exit().await?;
+            let active_id = storage.get_active_cabinet(member.chat).await?.map(|c| c.id);
+            match storage.get_cabinet(member.chat, cabinet_id).await? {
+                Some(cabinet) => {
+                    let is_active = active_id == Some(cabinet.id);
+                    bot.send_message(msg.chat.id, describe_cabinet(&cabinet, is_active).await)
+                        .reply_markup(create_cabinet_keyboard(cabinet.id, is_active))
+                        .await?;
+                }
+                None => {
+                    bot.send_message(msg.chat.id, "Кабинет не найден")
+                        .reply_markup(main_menu())
+                        .await?;
+                }
             }
         }
     }
     Ok(())
 }
 
+pub async fn preset_params_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Arc<dyn Storage>,
+    member: Member,
+    dialogue: MemberDialogue,
+    preset: PresetDraft,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let text = msg.text().unwrap_or("");
+    let parsed = text.split_once('\n').and_then(|(name, params)| {
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        parse_subscription_params(params).map(|params| (name.to_string(), params))
+    });
+
+    let (name, limits) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            bot.send_message(msg.chat.id, "Не удалось разобрать пресет. Отправьте название в первой строке, а во второй — коэффициент и, при необходимости, период:\n<code>Подмосковье\n1 01.11.2024-15.11.2024</code>")
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(to_main_menu_button())
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let preset_id = match preset {
+        PresetDraft::New { warehouse_id, box_type_name } => {
+            storage.create_preset(member.chat, name, warehouse_id, box_type_name, limits).await?
+        }
+        PresetDraft::Edit { preset_id } => {
+            storage.update_preset(member.chat, preset_id, name, limits).await?;
+            preset_id
+        }
+    };
+    dialogue.exit().await?;
+
+    match storage.get_preset(member.chat, preset_id).await? {
+        Some(preset) => {
+            bot.send_message(msg.chat.id, format!("💾 Пресет сохранен\n\n{}", describe_preset(&preset)))
+                .reply_markup(create_preset_keyboard(preset.id))
+                .await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, "Пресет не найден")
+                .reply_markup(main_menu())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn search_params_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Arc<dyn Storage>,
+    member: Member,
+    dialogue: MemberDialogue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = storage.as_ref();
+    let text = msg.text().unwrap_or("");
+    let (params, name_filter) = text.split_once('\n').unwrap_or((text, ""));
+    let name_filter: Vec<String> = name_filter
+        .split(',')
+        .map(|part| part.trim().to_lowercase())
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    let limits = match parse_subscription_params(params) {
+        Some(params) => params,
+        None => {
+            bot.send_message(msg.chat.id, "Не удалось разобрать параметры. Введите коэффициент, например <code>1</code>, или коэффициент и период: <code>1 01.11.2024-15.11.2024</code>")
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .reply_markup(to_main_menu_button())
+                .await?;
+            return Ok(());
+        }
+    };
+    storage.set_cheapest_search_params(member, limits, name_filter).await?;
+    dialogue.exit().await?;
+
+    let token = storage.get_user_token(member.chat).await?;
+    if token.is_empty() {
+        bot.send_message(msg.chat.id, "Токен не был введен. Откройте «📍Коэффиценты складов», чтобы ввести токен")
+            .reply_markup(main_menu())
+            .await?;
+        return Ok(());
+    }
+    // Один запрос по всем складам, дальше фильтруем и сортируем закэшированные коэффициенты
+    if let Err(e) = fetch_and_store_coefficients(storage, &token, None).await {
+        let reason = match e.downcast_ref::<WbApiError>() {
+            Some(api_error) => api_error.user_message(),
+            None => "Не удалось получить коэффициенты".to_string(),
+        };
+        bot.send_message(msg.chat.id, reason)
+            .parse_mode(teloxide::types::ParseMode::Html)
+            .reply_markup(main_menu())
+            .await?;
+        return Err(e);
+    }
+
+    let (text, keyboard) = results_page(storage, member, 0).await?;
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+// Разбор строки вида "1" или "1 01.11.2024-15.11.2024" в коэффициент и период
+fn parse_subscription_params(text: &str) -> Option<SlotLimits> {
+    let mut parts = text.split_whitespace();
+    let max_coefficient: i32 = parts.next()?.parse().ok()?;
+    if max_coefficient < 0 {
+        return None;
+    }
+
+    let (date_from, date_to) = match parts.next() {
+        Some(period) => {
+            let (from, to) = period.split_once('-')?;
+            // WB присылает даты как полночь UTC, храним границы в том же виде
+            let parse_date = |s: &str| {
+                NaiveDate::parse_from_str(s, "%d.%m.%Y")
+                    .ok()
+                    .and_then(|d| d.and_hms_opt(0, 0, 0))
+                    .map(|d| d.and_utc().timestamp())
+            };
+            let (from, to) = (parse_date(from)?, parse_date(to)?);
+            if from > to {
+                return None;
+            }
+            (Some(from), Some(to))
+        }
+        None => (None, None),
+    };
+
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(SlotLimits { max_coefficient, date_from, date_to })
+}
+
+// Inline-режим: "@бот Подольск" в любом чате возвращает подходящие склады
+pub async fn inline_query_handler(bot: Bot, q: InlineQuery, storage: Arc<dyn Storage>, me: Me) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let found = search_warehouses(&q.query, storage.get_all_warehouses().await?);
+    let results: Vec<InlineQueryResult> = found
+        .iter()
+        .map(|w| {
+            let content = InputMessageContent::Text(InputMessageContentText::new(format!("📍Склад WB: {} (ID {})", w.name, w.id)));
+            InlineQueryResult::Article(
+                InlineQueryResultArticle::new(w.id.to_string(), w.name.clone(), content)
+                    .description(format!("ID {}", w.id))
+                    .reply_markup(create_inline_warehouse_keyboard(me.username(), w.id)),
+            )
+        })
+        .collect();
+    bot.answer_inline_query(q.id, results).cache_time(60).await?;
+    Ok(())
+}
+
+// Сообщаем владельцам о запуске. Владелец мог еще не написать боту, это не мешает запуску
 pub async fn bot_started_msg(bot: Bot) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let id_i64 = get_id_by_username("polchasaa".to_string()).await?;
-    let id = ChatId(id_i64);
-    bot.send_message(id, "Бот запущен".to_string()).await?;
+    for owner in owner_ids() {
+        if let Err(e) = bot.send_message(*owner, "Бот запущен".to_string()).await {
+            log::warn!("Не удалось отправить владельцу {} сообщение о запуске: {}", owner, e);
+        }
+    }
     Ok(())
 }
 
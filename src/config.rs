@@ -0,0 +1,337 @@
+// Настройки бота: файл TOML (config.toml, другой путь задается через --config или CONFIG_PATH)
+// и переменные окружения, которые важнее файла. Секреты (TELOXIDE_TOKEN, ключи шифрования токенов,
+// DATABASE_URL) задаются только через окружение и в настройки не попадают
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::admins::parse_owner_ids;
+use crate::api_reauests::{DEFAULT_COMMON_API_URL, DEFAULT_SUPPLIES_API_URL};
+use crate::coefficient_history::DEFAULT_RETENTION_DAYS;
+use crate::seller_portal::{DEFAULT_BROWSER_PROFILES_DIR, DEFAULT_SELLER_PORTAL_URL, DEFAULT_WEBDRIVER_URL};
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+// Больше кнопок на странице Telegram покажет, но списком будет неудобно пользоваться
+const MAX_PAGE_SIZE: i32 = 50;
+// Метод коэффициентов WB отдает не больше 6 запросов в минуту на токен, чаще проверять подписки бессмысленно
+const MIN_WATCH_INTERVAL_SECS: u64 = 60;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub db_path: String,
+    pub worker_threads: usize,
+    pub page_size: i32,        // сколько складов, пресетов, профилей и вариантов поиска на одной странице
+    pub utc_offset_hours: i64, // часовой пояс дат в сообщениях, по умолчанию московское время
+    pub coefficient_history_days: i64,
+    pub owner_ids: Vec<u64>,   // владельцы бота, их роль нельзя отозвать командой
+    pub background_tasks: bool, // профили браузера лежат на диске экземпляра, фон запускаем только в одном
+    pub wb_api: WbApiSettings,
+    pub portal: PortalSettings,
+    pub intervals: IntervalSettings,
+    pub alerts: AlertSettings,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WbApiSettings {
+    pub common_url: String,
+    pub supplies_url: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortalSettings {
+    pub webdriver_url: String,
+    pub url: String,
+    pub profiles_dir: String,
+    pub max_profiles_per_chat: i32,
+}
+
+// Периоды фоновых задач в секундах
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntervalSettings {
+    pub cleanup_secs: u64,
+    pub coefficients_watch_secs: u64,
+    pub token_check_secs: u64,
+    pub profile_check_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertSettings {
+    pub token_expiry_warning_days: i64, // за сколько дней предупреждаем об окончании срока токена
+    pub max_per_check: usize,           // сколько уведомлений о слотах отправляем чату за одну проверку
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            db_path: "bot.db".to_string(),
+            worker_threads: 4,
+            page_size: 10,
+            utc_offset_hours: 3,
+            coefficient_history_days: DEFAULT_RETENTION_DAYS,
+            owner_ids: vec![],
+            background_tasks: true,
+            wb_api: WbApiSettings::default(),
+            portal: PortalSettings::default(),
+            intervals: IntervalSettings::default(),
+            alerts: AlertSettings::default(),
+        }
+    }
+}
+
+impl Default for WbApiSettings {
+    fn default() -> WbApiSettings {
+        WbApiSettings {
+            common_url: DEFAULT_COMMON_API_URL.to_string(),
+            supplies_url: DEFAULT_SUPPLIES_API_URL.to_string(),
+        }
+    }
+}
+
+impl Default for PortalSettings {
+    fn default() -> PortalSettings {
+        PortalSettings {
+            webdriver_url: DEFAULT_WEBDRIVER_URL.to_string(),
+            url: DEFAULT_SELLER_PORTAL_URL.to_string(),
+            profiles_dir: DEFAULT_BROWSER_PROFILES_DIR.to_string(),
+            max_profiles_per_chat: 5,
+        }
+    }
+}
+
+impl Default for IntervalSettings {
+    fn default() -> IntervalSettings {
+        IntervalSettings {
+            cleanup_secs: 60 * 60,
+            coefficients_watch_secs: 10 * 60,
+            token_check_secs: 60 * 60,
+            profile_check_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+impl Default for AlertSettings {
+    fn default() -> AlertSettings {
+        AlertSettings { token_expiry_warning_days: 3, max_per_check: 20 }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub fn init(config: Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    CONFIG.set(config).map_err(|_| "Настройки уже инициализированы")?;
+    Ok(())
+}
+
+// Действующие настройки. До init (в тестах) - значения по умолчанию
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+// Смещение часового пояса, в котором показываем даты пользователям
+pub fn utc_offset() -> chrono::Duration {
+    chrono::Duration::hours(get().utc_offset_hours)
+}
+
+// Аргументы запуска: --config <путь> и --check-config (проверить настройки и вывести итоговые значения)
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub config_path: Option<String>,
+    pub check_config: bool,
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, Box<dyn Error + Send + Sync>> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check-config" => parsed.check_config = true,
+            "--config" => parsed.config_path = Some(args.next().ok_or("--config: не указан путь к файлу настроек")?),
+            _ => return Err(format!("Неизвестный аргумент «{}». Доступны --config <путь> и --check-config", arg).into()),
+        }
+    }
+    Ok(parsed)
+}
+
+// Читает файл настроек, применяет переменные окружения и проверяет итог.
+// Файл по умолчанию необязателен, а явно указанный файл должен существовать
+pub fn load(path: Option<String>) -> Result<Config, Box<dyn Error + Send + Sync>> {
+    let (path, explicit) = match path.or_else(|| std::env::var("CONFIG_PATH").ok().filter(|path| !path.trim().is_empty())) {
+        Some(path) => (path, true),
+        None => (DEFAULT_CONFIG_PATH.to_string(), false),
+    };
+    let mut config = match std::fs::read_to_string(&path) {
+        Ok(text) => from_toml(&text).map_err(|e| format!("{}: {}", path, e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => Config::default(),
+        Err(e) => return Err(format!("Не удалось прочитать файл настроек {}: {}", path, e).into()),
+    };
+    apply_env(&mut config, |name| std::env::var(name).ok())?;
+    config.validate()?;
+    Ok(config)
+}
+
+pub fn from_toml(text: &str) -> Result<Config, Box<dyn Error + Send + Sync>> {
+    Ok(toml::from_str(text)?)
+}
+
+// Итоговые настройки в формате файла
+pub fn to_toml(config: &Config) -> Result<String, Box<dyn Error + Send + Sync>> {
+    Ok(toml::to_string_pretty(config)?)
+}
+
+fn override_value<T: FromStr>(value: &mut T, name: &str, env: &impl Fn(&str) -> Option<String>, errors: &mut Vec<String>) {
+    if let Some(raw) = env(name).filter(|raw| !raw.trim().is_empty()) {
+        match raw.trim().parse() {
+            Ok(parsed) => *value = parsed,
+            Err(_) => errors.push(format!("{}: некорректное значение «{}»", name, raw)),
+        }
+    }
+}
+
+// Переменные окружения перекрывают значения из файла. Пустые переменные не учитываются
+pub fn apply_env(config: &mut Config, env: impl Fn(&str) -> Option<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut errors = vec![];
+    override_value(&mut config.db_path, "DB_PATH", &env, &mut errors);
+    override_value(&mut config.worker_threads, "WORKER_THREADS", &env, &mut errors);
+    override_value(&mut config.page_size, "PAGE_SIZE", &env, &mut errors);
+    override_value(&mut config.utc_offset_hours, "UTC_OFFSET_HOURS", &env, &mut errors);
+    override_value(&mut config.coefficient_history_days, "COEFFICIENT_HISTORY_DAYS", &env, &mut errors);
+    if let Some(raw) = env("BOT_OWNER_IDS").filter(|raw| !raw.trim().is_empty()) {
+        match parse_owner_ids(&raw) {
+            Ok(ids) => config.owner_ids = ids.into_iter().map(|id| id.0).collect(),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    override_value(&mut config.background_tasks, "BACKGROUND_TASKS", &env, &mut errors);
+    override_value(&mut config.wb_api.common_url, "WB_COMMON_API_URL", &env, &mut errors);
+    override_value(&mut config.wb_api.supplies_url, "WB_SUPPLIES_API_URL", &env, &mut errors);
+    override_value(&mut config.portal.webdriver_url, "WEBDRIVER_URL", &env, &mut errors);
+    override_value(&mut config.portal.url, "SELLER_PORTAL_URL", &env, &mut errors);
+    override_value(&mut config.portal.profiles_dir, "BROWSER_PROFILES_DIR", &env, &mut errors);
+    override_value(&mut config.portal.max_profiles_per_chat, "MAX_PROFILES_PER_CHAT", &env, &mut errors);
+    override_value(&mut config.intervals.cleanup_secs, "CLEANUP_INTERVAL_SECS", &env, &mut errors);
+    override_value(&mut config.intervals.coefficients_watch_secs, "COEFFICIENTS_WATCH_INTERVAL_SECS", &env, &mut errors);
+    override_value(&mut config.intervals.token_check_secs, "TOKEN_CHECK_INTERVAL_SECS", &env, &mut errors);
+    override_value(&mut config.intervals.profile_check_secs, "PROFILE_CHECK_INTERVAL_SECS", &env, &mut errors);
+    override_value(&mut config.alerts.token_expiry_warning_days, "TOKEN_EXPIRY_WARNING_DAYS", &env, &mut errors);
+    override_value(&mut config.alerts.max_per_check, "MAX_ALERTS_PER_CHECK", &env, &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n").into())
+    }
+}
+
+fn check_range<T: PartialOrd + std::fmt::Display>(errors: &mut Vec<String>, key: &str, value: T, min: T, max: T) {
+    if value < min || value > max {
+        errors.push(format!("{}: должно быть от {} до {}, указано {}", key, min, max, value));
+    }
+}
+
+fn check_url(errors: &mut Vec<String>, key: &str, value: &str) {
+    match reqwest::Url::parse(value) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+        _ => errors.push(format!("{}: «{}» не является адресом http(s)://", key, value)),
+    }
+}
+
+impl Config {
+    // Проверяет все значения сразу, чтобы в ошибке были перечислены все неверные ключи
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut errors = vec![];
+        if self.db_path.trim().is_empty() {
+            errors.push("db_path: не указан путь к базе данных".to_string());
+        }
+        check_range(&mut errors, "worker_threads", self.worker_threads, 1, 64);
+        check_range(&mut errors, "page_size", self.page_size, 1, MAX_PAGE_SIZE);
+        check_range(&mut errors, "utc_offset_hours", self.utc_offset_hours, -12, 14);
+        check_range(&mut errors, "coefficient_history_days", self.coefficient_history_days, 1, 3650);
+        check_url(&mut errors, "wb_api.common_url", &self.wb_api.common_url);
+        check_url(&mut errors, "wb_api.supplies_url", &self.wb_api.supplies_url);
+        check_url(&mut errors, "portal.webdriver_url", &self.portal.webdriver_url);
+        check_url(&mut errors, "portal.url", &self.portal.url);
+        if self.portal.profiles_dir.trim().is_empty() {
+            errors.push("portal.profiles_dir: не указана папка профилей браузера".to_string());
+        }
+        check_range(&mut errors, "portal.max_profiles_per_chat", self.portal.max_profiles_per_chat, 1, 100);
+        check_range(&mut errors, "intervals.cleanup_secs", self.intervals.cleanup_secs, 60, u64::MAX);
+        check_range(&mut errors, "intervals.coefficients_watch_secs", self.intervals.coefficients_watch_secs, MIN_WATCH_INTERVAL_SECS, u64::MAX);
+        check_range(&mut errors, "intervals.token_check_secs", self.intervals.token_check_secs, 60, u64::MAX);
+        check_range(&mut errors, "intervals.profile_check_secs", self.intervals.profile_check_secs, 60, u64::MAX);
+        check_range(&mut errors, "alerts.token_expiry_warning_days", self.alerts.token_expiry_warning_days, 1, 30);
+        check_range(&mut errors, "alerts.max_per_check", self.alerts.max_per_check, 1, 1000);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Ошибки в настройках:\n{}", errors.join("\n")).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn file_values_are_overridden_by_env() {
+        let mut config = from_toml(
+            "page_size = 8\nowner_ids = [1]\n\n[wb_api]\ncommon_url = \"http://localhost:8080\"\n\n[intervals]\ncleanup_secs = 600\n",
+        )
+        .unwrap();
+        assert_eq!(config.page_size, 8);
+        assert_eq!(config.wb_api.common_url, "http://localhost:8080");
+        assert_eq!(config.wb_api.supplies_url, DEFAULT_SUPPLIES_API_URL);
+        assert_eq!(config.intervals.cleanup_secs, 600);
+
+        apply_env(&mut config, env(&[("PAGE_SIZE", "12"), ("BOT_OWNER_IDS", "5, 7"), ("DB_PATH", "")])).unwrap();
+        assert_eq!(config.page_size, 12);
+        assert_eq!(config.owner_ids, vec![5, 7]);
+        assert_eq!(config.db_path, "bot.db");
+        assert_eq!(config.intervals.cleanup_secs, 600);
+        config.validate().unwrap();
+        assert_eq!(from_toml(&to_toml(&config).unwrap()).unwrap(), config);
+    }
+
+    #[test]
+    fn reports_every_invalid_value() {
+        assert!(from_toml("page_sise = 8").unwrap_err().to_string().contains("page_sise"));
+        assert!(from_toml("page_size = \"ten\"").is_err());
+
+        let err = apply_env(&mut Config::default(), env(&[("PAGE_SIZE", "ten"), ("BOT_OWNER_IDS", "@admin")])).unwrap_err();
+        assert!(err.to_string().contains("PAGE_SIZE: некорректное значение «ten»"));
+        assert!(err.to_string().contains("BOT_OWNER_IDS"));
+
+        let mut config = Config { page_size: 0, ..Config::default() };
+        config.wb_api.supplies_url = "supplies-api.wildberries.ru".to_string();
+        config.intervals.coefficients_watch_secs = 5;
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("page_size: должно быть от 1 до 50, указано 0"));
+        assert!(err.contains("wb_api.supplies_url"));
+        assert!(err.contains("intervals.coefficients_watch_secs"));
+        assert!(!err.contains("worker_threads"));
+    }
+
+    #[test]
+    fn parses_args() {
+        let args = |list: &[&str]| parse_args(list.iter().map(|arg| arg.to_string()));
+        assert_eq!(args(&[]).unwrap(), Args::default());
+        assert_eq!(
+            args(&["--config", "prod.toml", "--check-config"]).unwrap(),
+            Args { config_path: Some("prod.toml".to_string()), check_config: true }
+        );
+        assert!(args(&["--config"]).is_err());
+        assert!(args(&["--verbose"]).is_err());
+    }
+}
@@ -1,367 +1,386 @@
 use crate::api_reauests::{Warehouse, CoefficientResponse};
-use rusqlite::{params, Connection, Result};
+use crate::migrations;
+use crate::storage::Storage;
+use rusqlite::{Connection, Result};
 use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use teloxide::types::UserId;
+use std::time::Duration as StdDuration;
+use teloxide::types::{CallbackQuery, ChatId, Message, UserId};
 use tokio::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
-use chrono::{DateTime, Duration, TimeZone, Utc};
-
-#[derive(PartialEq)]
-pub enum State {
-    Idle = 0,
-    AwaitingToken = 1, //ожидание токена
-    TokenEntered = 2,  //токен введен
-    AwaitingNumber = 3, //ожидание номера телефона
-    AwaitingCaptcha = 4, //ожидание ввода капчи
-    AwaitingSMSCode = 5, //ожидаем код из смс
+use chrono::Duration;
+
+// Участник чата. Кабинеты, подписки, пресеты и избранное принадлежат чату, в группе они общие.
+// Состояние многошаговых диалогов и черновики хранятся для каждого участника отдельно,
+// чтобы несколько человек в группе могли одновременно работать с меню. В личном чате chat и user совпадают
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Member {
+    pub chat: ChatId,
+    pub user: UserId,
 }
 
-impl State {
-    fn from_i8(value: i8) -> Option<State> {
-        match value {
-            1 => Some(State::AwaitingToken),
-            2 => Some(State::TokenEntered),
-            3 => Some(State::AwaitingNumber),
-            4 => Some(State::AwaitingCaptcha),
-            5 => Some(State::AwaitingSMSCode),
-            _ => Some(State::Idle),
-        }
+impl Member {
+    pub fn from_callback(q: &CallbackQuery) -> Self {
+        // У callback из inline-сообщения нет чата, тогда отвечаем в личку
+        let chat = q.message.as_ref().map(|m| m.chat().id).unwrap_or(ChatId::from(q.from.id));
+        Member { chat, user: q.from.id }
     }
-}
 
-pub fn initialize_db() -> Result<()> {
-    let conn = Connection::open("bot.db")?;
-
-    let create_tables_query = "
-        CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY,
-            username TEXT NOT NULL
-        );
-        CREATE TABLE IF NOT EXISTS user_states (
-            id INTEGER PRIMARY KEY,
-            state INTEGER NOT NULL
-        );
-        CREATE TABLE IF NOT EXISTS user_tokens (
-            chat_id INTEGER PRIMARY KEY,
-            token TEXT NOT NULL
-        );
-        CREATE TABLE IF NOT EXISTS warehouses (
-            id INTEGER PRIMARY KEY,
-            name TEXT NOT NULL
-        );
-        CREATE TABLE IF NOT EXISTS warehouses_coefficients (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            date INTEGER NOT NULL,
-            coefficient INTEGER NOT NULL,
-            warehouse_id INTEGER NOT NULL,
-            warehouse_name TEXT NOT NULL,
-            box_type_name TEXT NOT NULL,
-            box_type_id INTEGER,
-            UNIQUE(date, warehouse_id, box_type_name)
-        );
-    ";
-
-    conn.execute_batch(create_tables_query)?;
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        msg.from.as_ref().map(|user| Member { chat: msg.chat.id, user: user.id })
+    }
 
-    Ok(())
+    pub fn is_group(&self) -> bool {
+        !self.chat.is_user()
+    }
 }
 
-pub async fn get_db_connection() -> Result<Arc<Mutex<Connection>>, Box<dyn Error + Send + Sync>> {
-    let conn = Arc::new(Mutex::new(Connection::open("bot.db")?));
-    Ok(conn)
+// Соединения с базой живут всё время работы бота, чтобы не открывать файл на каждый запрос
+pub struct DbPool {
+    path: String,
+    connections: Vec<Arc<Mutex<Connection>>>,
+    next: AtomicUsize,
 }
 
-pub async fn add_user_to_db(
-    id: UserId,
-    username: String,
-) -> Result<(), Box<dyn Error + Send + Sync>> {    
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    conn.execute(
-        "INSERT OR IGNORE INTO users (id, username) VALUES (?1, ?2)",
-        params![id.0, username],
-    )?;
-    Ok(())
-}
+// В режиме WAL чтение не блокирует запись, поэтому держим несколько соединений
+const POOL_SIZE: usize = 4;
+// Сколько ждать, пока другое соединение освободит базу на запись
+const BUSY_TIMEOUT: StdDuration = StdDuration::from_secs(5);
 
-pub async fn _user_exist(id: UserId) -> Result<bool, Box<dyn Error>> {
-    let conn = Connection::open("bot.db")?;
-    let mut stmt = conn.prepare("SELECT EXISTS(SELECT 1 FROM users WHERE is = ?1)")?;
-    let exists: bool = stmt.query_row([id.0], |row| row.get(0))?;
-    Ok(exists)
+fn open_connection(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    Ok(conn)
 }
 
-pub async fn get_user_ids() -> Result<Vec<i64>, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    let mut stmt = conn.prepare("SELECT id FROM users")?;
-    let user_ids_iter = stmt.query_map([], |row| row.get(0))?;
+impl DbPool {
+    // Открывает файл базы и применяет недостающие миграции
+    pub fn open(path: &str) -> Result<DbPool, Box<dyn Error + Send + Sync>> {
+        let mut conn = open_connection(path)?;
+        migrations::run_migrations(&mut conn)?;
 
-    let mut users_ids: Vec<i64> = Vec::new();
-    for user_id_result in user_ids_iter {
-        users_ids.push(user_id_result?);
+        let mut connections = vec![Arc::new(Mutex::new(conn))];
+        for _ in 1..POOL_SIZE {
+            connections.push(Arc::new(Mutex::new(open_connection(path)?)));
+        }
+        Ok(DbPool {
+            path: path.to_string(),
+            connections,
+            next: AtomicUsize::new(0),
+        })
     }
 
-    Ok(users_ids)
-}
+    pub fn connection(&self) -> Arc<Mutex<Connection>> {
+        // Берем свободное соединение, если такого нет - следующее по кругу
+        for conn in &self.connections {
+            if conn.try_lock().is_ok() {
+                return conn.clone();
+            }
+        }
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        self.connections[index].clone()
+    }
 
-pub async fn get_id_by_username(username: String) -> Result<i64, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    let mut stmt = conn.prepare("SELECT id FROM users WHERE username = ?1")?;
-    let id: i64 = stmt.query_row([username], |row| row.get(0))?;
-    Ok(id)
+    // Размер файла базы вместе с журналом WAL
+    pub fn size(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let mut size = std::fs::metadata(&self.path)?.len();
+        if let Ok(wal) = std::fs::metadata(format!("{}-wal", self.path)) {
+            size += wal.len();
+        }
+        Ok(size)
+    }
 }
 
-pub async fn set_user_state(
-    id: UserId,
-    state: State,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    conn.execute(
-        "INSERT OR REPLACE INTO user_states (id, state) VALUES (?1, ?2)",
-        params![id.0, state as i8],
-    )?;
-    Ok(())
+// Кабинет продавца WB: у пользователя может быть несколько кабинетов со своими токенами
+#[derive(Debug, Clone)]
+pub struct Cabinet {
+    pub id: i64,
+    pub chat_id: i64,
+    pub name: String,
+    pub token: String, // уже расшифрованный
 }
 
-pub async fn _del_user_state(id: UserId) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    conn.execute("DELETE FROM user_states WHERE id = ?1", params![id.0])?;
-    Ok(())
-}
+// Название кабинета, который создается, когда пользователь вводит токен без выбора кабинета
+pub const DEFAULT_CABINET_NAME: &str = "Основной";
 
-pub async fn get_user_state(id: UserId) -> Result<State, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
+// Сколько дней храним статистику использования для /stats
+pub const STATS_RETENTION_DAYS: i64 = 30;
 
-    let mut stmt = conn.prepare("SELECT state FROM user_states WHERE id = ?1")?;
-    let mut rows = stmt.query([id.0])?;
+// Удаляет прошедшие слоты из кэша коэффициентов и историю старше history_retention_days дней
+pub async fn delete_expired_records(storage: &dyn Storage, history_retention_days: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Получаем текущее время в формате Unix timestamp (UTC)
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
 
-    if let Some(row) = rows.next()? {
-        let state: i8 = row.get(0)?;
-        match State::from_i8(state) {
-            Some(s) => Ok(s),
-            None => Err("Неверное состояние пользователя".into()),
-        }
-    } else {
-        Ok(State::Idle)
-    }
-}
+    let history_border = now - Duration::days(history_retention_days).num_seconds();
+    storage.delete_expired_coefficients(now, history_border).await?;
+
+    // Отметки об уведомлениях о прошедших слотах больше не нужны для дедупликации
+    let stats_border = now - Duration::days(STATS_RETENTION_DAYS).num_seconds();
+    storage.delete_expired_events(now, stats_border).await?;
 
-pub async fn set_user_token(
-    id: UserId,
-    token: String,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    conn.execute(
-        "INSERT OR REPLACE INTO user_tokens (chat_id, token) VALUES (?1, ?2)",
-        params![id.0, token],
-    )?;
     Ok(())
 }
 
-pub async fn get_user_token(id: UserId) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    let mut stmt = conn.prepare("SELECT token FROM user_tokens WHERE chat_id = ?1")?;
-    //let token = stmt.query_row(params![id.0], |row| row.get(0))?;
-    let result: Result<String, rusqlite::Error> = stmt.query_row(params![id.0], |row| row.get(0));
-    match result {
-        Ok(token) => Ok(token),
-        Err(e) => {
-            if e == rusqlite::Error::QueryReturnedNoRows {
-                return Ok(String::new());
-            }
-            else {
-                Err(Box::new(e))
-            }
-        }
-    }
+// Изменение коэффициента на дату поставки
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoefficientChange {
+    pub date: i64,
+    pub coefficient: i32,
+    pub observed_at: i64,
 }
 
-pub async fn insert_warehouses(warehouses: Vec<Warehouse>) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
+// Состояние сессии кабинета в профиле браузера
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProfileHealth {
+    Unknown, // еще не проверяли или проверка не удалась
+    Active,  // вход в кабинет действует
+    Expired, // портал просит войти заново
+}
 
-    for warehouse in warehouses {
-        conn.execute(
-            "INSERT INTO warehouses (id, name) VALUES (?1, ?2)
-             ON CONFLICT(id) DO UPDATE SET name = excluded.name",
-            params![warehouse.id, warehouse.name],
-        )?;
+impl ProfileHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProfileHealth::Unknown => "unknown",
+            ProfileHealth::Active => "active",
+            ProfileHealth::Expired => "expired",
+        }
     }
 
-    Ok(())
+    pub fn parse(value: &str) -> ProfileHealth {
+        match value {
+            "active" => ProfileHealth::Active,
+            "expired" => ProfileHealth::Expired,
+            _ => ProfileHealth::Unknown,
+        }
+    }
 }
 
-pub async fn get_warehouses_page(page: i32, page_size: i32) -> Result<Vec<Warehouse>, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    let offset = page * page_size;
+pub struct BrowserProfile {
+    pub chat_id: i64,
+    pub phone: String, // 10 цифр без +7
+    pub last_login_at: Option<i64>,
+    pub health: ProfileHealth,
+    pub checked_at: Option<i64>,
+}
 
-    let mut stmt = conn
-        .prepare("SELECT id, name FROM warehouses ORDER BY name LIMIT ?1 OFFSET ?2")?;
+// Условия отбора слотов, общие для подписок, пресетов и поиска самой дешевой приемки
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlotLimits {
+    pub max_coefficient: i32,
+    pub date_from: Option<i64>, // unix время начала периода (включительно)
+    pub date_to: Option<i64>,   // unix время конца периода (включительно)
+}
 
-    let warehouse_iter = stmt
-        .query_map([page_size, offset], |row| {
-            Ok(Warehouse {
-                id: row.get(0)?,
-                name: row.get(1)?,
-            })
-        })
-        .unwrap();
+pub struct Subscription {
+    pub id: i64,
+    pub user_id: i64, // чат подписки: личный чат пользователя или группа
+    pub warehouse_id: i32,
+    pub warehouse_name: String,
+    pub box_type_name: String,
+    pub max_coefficient: i32,
+    pub date_from: Option<i64>, // unix время начала периода (включительно)
+    pub date_to: Option<i64>,   // unix время конца периода (включительно)
+    pub auto_book_phone: Option<String>, // профиль браузера для автобронирования, если включено
+}
 
-    let mut warehouses = vec![];
-    for warehouse in warehouse_iter {
-        if let Ok(w) = warehouse {
-            warehouses.push(w);
-        }
+impl Subscription {
+    // Подходит ли слот под условия подписки
+    pub fn matches(&self, coefficient: &CoefficientResponse, date: i64) -> bool {
+        coefficient.warehouse_id as i32 == self.warehouse_id
+            && coefficient.box_type_name == self.box_type_name
+            && coefficient.coefficient != -1
+            && coefficient.coefficient <= self.max_coefficient
+            && self.date_from.is_none_or(|from| date >= from)
+            && self.date_to.is_none_or(|to| date <= to)
     }
-    Ok(warehouses)
 }
 
-pub async fn count_warehouses() -> Result<i32, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM warehouses").unwrap();
-    
-    let count: i32 = stmt.query_row([], |row| row.get(0))?;
+pub struct Preset {
+    pub id: i64,
+    pub name: String,
+    pub box_type_name: String,
+    pub max_coefficient: i32,
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    pub warehouses: Vec<Warehouse>,
+}
 
-    Ok(count)
+impl Preset {
+    // Подходит ли слот под условия пресета
+    pub fn matches(&self, coefficient: &CoefficientResponse, date: i64) -> bool {
+        self.warehouses.iter().any(|w| w.id == coefficient.warehouse_id)
+            && coefficient.box_type_name == self.box_type_name
+            && coefficient.coefficient != -1
+            && coefficient.coefficient <= self.max_coefficient
+            && self.date_from.is_none_or(|from| date >= from)
+            && self.date_to.is_none_or(|to| date <= to)
+    }
 }
 
-pub async fn delete_expired_records() -> Result<(), Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    // Получаем текущее время в формате Unix timestamp (UTC)
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+// Поиск самой дешевой приемки: тип поставки, необязательный набор складов или фильтр по названию
+// (город/регион), максимальный коэффициент и период
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheapestSearch {
+    pub box_type_name: String,
+    pub warehouse_ids: Option<Vec<u32>>, // None - все склады
+    pub name_filter: Vec<String>,        // части названия склада в нижнем регистре, пусто - без фильтра
+    pub max_coefficient: Option<i32>,    // None - параметры еще не введены
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+}
 
-    // SQL-запрос для удаления записей с истекшей датой
-    let delete_query = "DELETE FROM warehouses_coefficients WHERE date < ?";
+// Закэшированный коэффициент склада на дату
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedSlot {
+    pub warehouse_id: u32,
+    pub warehouse_name: String,
+    pub date: i64,
+    pub coefficient: i32,
+}
 
-    // Выполняем запрос
-    conn.execute(delete_query, params![now])?;
+impl CheapestSearch {
+    // Подходит ли слот под условия поиска
+    pub fn matches(&self, slot: &CachedSlot) -> bool {
+        slot.coefficient != -1
+            && self.max_coefficient.is_none_or(|max| slot.coefficient <= max)
+            && self.date_from.is_none_or(|from| slot.date >= from)
+            && self.date_to.is_none_or(|to| slot.date <= to)
+            && self.warehouse_ids.as_ref().is_none_or(|ids| ids.contains(&slot.warehouse_id))
+            && (self.name_filter.is_empty() || {
+                let name = slot.warehouse_name.to_lowercase();
+                self.name_filter.iter().any(|part| name.contains(part.as_str()))
+            })
+    }
+}
 
-    Ok(())
+// Рассылка: draft -> sending -> done, отменить можно до завершения
+pub struct Broadcast {
+    pub id: i64,
+    pub admin_chat_id: i64,
+    pub text: String,
+    pub photo: Option<String>,
+    pub buttons: Vec<(String, String)>, // текст и ссылка кнопки
+    pub status: String,
+    pub progress_message_id: Option<i32>,
 }
 
-pub async fn add_or_update_warehouse_coefficents(coefficients: Vec<CoefficientResponse>) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-
-    for coefficient in coefficients {
-        // SQL-запрос для обновления или вставки записи
-        let upsert_query = "
-            INSERT INTO warehouses_coefficients (date, coefficient, warehouse_id, warehouse_name, box_type_name, box_type_id)
-            VALUES (?, ?, ?, ?, ?, ?)
-            ON CONFLICT(date, warehouse_id, box_type_name) DO UPDATE SET
-                coefficient = excluded.coefficient;
-        ";
-        let datetime: DateTime<Utc> = coefficient.date.parse().unwrap();
-        let unix_time = datetime.timestamp();
-        // Выполняем запрос
-        conn.execute(upsert_query, params![unix_time, coefficient.coefficient, coefficient.warehouse_id, coefficient.warehouse_name, coefficient.box_type_name, coefficient.box_type_id])?;
-    }
+#[derive(Debug, Default, PartialEq)]
+pub struct BroadcastStats {
+    pub total: i64,
+    pub sent: i64,
+    pub blocked: i64,
+    pub failed: i64,
+}
 
-    Ok(())
+// Кнопки рассылки хранятся построчно как "текст\tссылка"
+pub fn encode_broadcast_buttons(buttons: &[(String, String)]) -> String {
+    buttons
+        .iter()
+        .map(|(text, url)| format!("{}\t{}", text, url))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
-pub async fn get_unique_box_types(warehouse_id: i32) -> Result<Vec<String>> {
-    let conn = get_db_connection().await.unwrap();
-    let conn = conn.lock().await;
+pub fn decode_broadcast_buttons(buttons: &str) -> Vec<(String, String)> {
+    buttons
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(text, url)| (text.to_string(), url.to_string()))
+        .collect()
+}
 
-    let mut stmt = conn.prepare("SELECT DISTINCT box_type_name FROM warehouses_coefficients WHERE warehouse_id = ?1 ORDER BY box_type_name COLLATE NOCASE")?;
-    let box_type_iter = stmt.query_map([warehouse_id], |row| {
-        let box_type_name: String = row.get(0)?;
-        Ok(box_type_name)
-    })?;
+#[derive(Debug, PartialEq)]
+pub struct ApiCallStats {
+    pub endpoint: String,
+    pub calls: i64,
+    pub errors: i64,
+}
 
-    let mut box_types = Vec::new();
-    for box_type in box_type_iter {
-        box_types.push(box_type?);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coefficient(coefficient: i32) -> CoefficientResponse {
+        CoefficientResponse {
+            date: "2024-09-05T00:00:00Z".to_string(),
+            coefficient,
+            warehouse_id: 507,
+            warehouse_name: "Коледино".to_string(),
+            box_type_name: "Короба".to_string(),
+            box_type_id: Some(2),
+        }
     }
 
-    Ok(box_types)
-}
+    fn subscription(date_from: Option<i64>, date_to: Option<i64>) -> Subscription {
+        Subscription {
+            id: 1,
+            user_id: 42,
+            warehouse_id: 507,
+            warehouse_name: "Коледино".to_string(),
+            box_type_name: "Короба".to_string(),
+            max_coefficient: 2,
+            date_from,
+            date_to,
+            auto_book_phone: None,
+        }
+    }
 
-pub async fn get_warehouse_data(wid: i32, btype: String) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-
-    let mut stmt1 = conn.prepare("SELECT name FROM warehouses WHERE id = ?1")?;
-    let wname: String = stmt1.query_row(params![wid], |row| row.get(0))?;
-
-    let mut stmt = conn.prepare(
-        "SELECT date, coefficient, warehouse_name FROM warehouses_coefficients WHERE warehouse_id = ?1 AND box_type_name = ?2 AND coefficient != -1 ORDER BY coefficient"
-    )?;
-    
-    let mut rows = stmt.query(params![wid, btype])?;
-    let mut result = String::new();
-    let mut found = false; // Флаг для проверки наличия записей
-
-    result.push_str(&format!("📍Склад: {}\n📦Тип поставки: {}\n\n", wname, btype)); //w_name
-
-    while let Some(row) = rows.next()? {
-        found = true;
-        let date: i64 = row.get(0)?;
-        let coefficient: i32 = row.get(1)?;
-
-        // Преобразование unix времени в формат dd.mm.yyyy hh:mm:ss и перевод в московское время
-        let time = Utc.timestamp_opt(date, 0).unwrap();
-        let moscow_time = time + Duration::hours(3);
-        let moscow_time_string = moscow_time.format("%d.%m.%Y %H:%M:%S").to_string();
-
-        result.push_str(&format!(
-            "⌛️Дата: {}\n📈Коэффициент: {}\n\n",
-            moscow_time_string, coefficient
-        ));
+    #[test]
+    fn subscription_matches_coefficient_up_to_threshold() {
+        let subscription = subscription(None, None);
+        assert!(subscription.matches(&coefficient(0), 100));
+        assert!(subscription.matches(&coefficient(2), 100));
+        assert!(!subscription.matches(&coefficient(3), 100));
+        // -1 - приемка недоступна, хотя это меньше порога
+        assert!(!subscription.matches(&coefficient(-1), 100));
+
+        let other_box_type = CoefficientResponse { box_type_name: "Монопаллеты".to_string(), ..coefficient(0) };
+        assert!(!subscription.matches(&other_box_type, 100));
+        let other_warehouse = CoefficientResponse { warehouse_id: 117986, ..coefficient(0) };
+        assert!(!subscription.matches(&other_warehouse, 100));
     }
-    if !found {
-        result.push_str("⛔️Нет доступных поставок");
+
+    #[test]
+    fn subscription_period_bounds_are_inclusive_and_optional() {
+        let closed = subscription(Some(100), Some(300));
+        assert!(!closed.matches(&coefficient(1), 99));
+        assert!(closed.matches(&coefficient(1), 100));
+        assert!(closed.matches(&coefficient(1), 300));
+        assert!(!closed.matches(&coefficient(1), 301));
+
+        let from_only = subscription(Some(100), None);
+        assert!(!from_only.matches(&coefficient(1), 99));
+        assert!(from_only.matches(&coefficient(1), i64::MAX));
+
+        let to_only = subscription(None, Some(300));
+        assert!(to_only.matches(&coefficient(1), 0));
+        assert!(!to_only.matches(&coefficient(1), 301));
     }
-    Ok(result)
-}
 
-pub async fn get_user_browser_profiles_page(
-    id: UserId,
-    page: i32, 
-    page_size: i32
-) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    let offset = page * page_size;
-
-    let mut stmt = conn
-        .prepare("SELECT phone_number FROM chrome_profiles WHERE id = ?1 LIMIT ?2 OFFSET ?3")?;
-
-    let phones_iter = stmt
-        .query_map(params![id.0, page_size, offset], |row| {
-            let phone: String = row.get(0)?;
-            Ok(phone)
-        })?;
-
-    let mut phones = vec![];
-    for phone in phones_iter {
-        if let Ok(w) = phone {
-            phones.push(w);
+    fn preset(warehouse_ids: &[u32]) -> Preset {
+        Preset {
+            id: 1,
+            name: "Москва".to_string(),
+            box_type_name: "Короба".to_string(),
+            max_coefficient: 2,
+            date_from: Some(100),
+            date_to: Some(300),
+            warehouses: warehouse_ids.iter().map(|&id| Warehouse { id, name: format!("Склад {}", id) }).collect(),
         }
     }
-    Ok(phones)
-}
-
-pub async fn count_user_numbers(id: UserId) -> Result<i32, Box<dyn Error + Send + Sync>> {
-    let conn = get_db_connection().await?;
-    let conn = conn.lock().await;
-    
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM chrome_profiles WHERE id = ?1").unwrap();
-    
-    let count: i32 = stmt.query_row([id.0], |row| row.get(0))?;
 
-    Ok(count)
-}
\ No newline at end of file
+    #[test]
+    fn preset_matches_any_of_its_warehouses() {
+        let preset = preset(&[507, 117986]);
+        assert!(preset.matches(&coefficient(2), 100));
+        assert!(preset.matches(&CoefficientResponse { warehouse_id: 117986, ..coefficient(0) }, 300));
+        assert!(!preset.matches(&CoefficientResponse { warehouse_id: 1733, ..coefficient(0) }, 200));
+        assert!(!preset.matches(&coefficient(3), 200));
+        assert!(!preset.matches(&coefficient(-1), 200));
+        assert!(!preset.matches(&coefficient(0), 99));
+        assert!(!preset.matches(&coefficient(0), 301));
+        assert!(!self::preset(&[]).matches(&coefficient(0), 200));
+    }
+}
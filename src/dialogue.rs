@@ -0,0 +1,136 @@
+// Многошаговые диалоги участника чата. Состояние вместе с данными шага (выбранный склад,
+// черновик кабинета или пресета, номер телефона при входе) хранится в базе через Storage,
+// поэтому диалог переживает перезапуск бота и общий для экземпляров с одной базой PostgreSQL.
+// Сообщения разводятся по состояниям в дереве dptree в main
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::Arc;
+use teloxide::dispatching::dialogue::{self, Dialogue};
+use teloxide::types::{ChatId, UserId};
+
+use crate::database::Member;
+use crate::storage::Storage;
+
+// Черновик кабинета: новый кабинет (название вводится до токена) или уже существующий кабинет
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CabinetDraft {
+    New { name: Option<String> },
+    Edit { cabinet_id: i64 },
+}
+
+// Черновик пресета: либо новый пресет для склада и типа поставки, либо редактирование существующего
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PresetDraft {
+    New { warehouse_id: i32, box_type_name: String },
+    Edit { preset_id: i64 },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum DialogueState {
+    #[default]
+    Idle,
+    // Ожидание токена. Без черновика токен сохраняется в активный кабинет
+    AwaitingToken { cabinet: Option<CabinetDraft> },
+    AwaitingCabinetName { cabinet: CabinetDraft },
+    // Ожидание параметров подписки на коэффициент для выбранного склада и типа поставки
+    AwaitingSubscriptionParams { warehouse_id: i32, box_type_name: String },
+    // Склад добавляется в пресет: ждем выбора существующего пресета или создания нового
+    ChoosingPreset { warehouse_id: i32, box_type_name: String },
+    AwaitingPresetParams { preset: PresetDraft },
+    AwaitingNumber,
+    AwaitingCaptcha { phone: String },
+    AwaitingSmsCode { phone: String },
+    AwaitingSearchParams,
+    AwaitingWarehouseSearch,
+}
+
+// Диалоги teloxide привязаны к чату, а состояние у каждого участника группы свое.
+// Хранилище диалогов одного участника: чат передает Dialogue, пользователь задается при создании
+pub struct MemberStorage {
+    storage: Arc<dyn Storage>,
+    user: UserId,
+}
+
+pub type MemberDialogue = Dialogue<DialogueState, MemberStorage>;
+
+pub fn member_dialogue(storage: Arc<dyn Storage>, member: Member) -> MemberDialogue {
+    Dialogue::new(Arc::new(MemberStorage { storage, user: member.user }), member.chat)
+}
+
+pub async fn load_dialogue(storage: &dyn Storage, member: Member) -> Result<Option<DialogueState>, Box<dyn Error + Send + Sync>> {
+    match storage.get_dialogue(member).await? {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => Ok(None),
+    }
+}
+
+// Сохраняет состояние диалога. Idle не храним: нет записи - нет диалога
+pub async fn save_dialogue(storage: &dyn Storage, member: Member, state: &DialogueState) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match state {
+        DialogueState::Idle => storage.remove_dialogue(member).await,
+        state => storage.update_dialogue(member, serde_json::to_string(state)?).await,
+    }
+}
+
+impl dialogue::Storage<DialogueState> for MemberStorage {
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn remove_dialogue(self: Arc<Self>, chat_id: ChatId) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move { self.storage.remove_dialogue(Member { chat: chat_id, user: self.user }).await })
+    }
+
+    fn update_dialogue(self: Arc<Self>, chat_id: ChatId, dialogue: DialogueState) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move { save_dialogue(self.storage.as_ref(), Member { chat: chat_id, user: self.user }, &dialogue).await })
+    }
+
+    fn get_dialogue(self: Arc<Self>, chat_id: ChatId) -> BoxFuture<'static, Result<Option<DialogueState>, Self::Error>> {
+        Box::pin(async move { load_dialogue(self.storage.as_ref(), Member { chat: chat_id, user: self.user }).await })
+    }
+}
+
+// Текущее состояние для ветвления в dptree. Состояние, которое не удалось разобрать
+// (например, сохраненное старой версией бота), сбрасываем, чтобы участник не застрял в нем
+pub async fn current_state(dialogue: MemberDialogue) -> Option<DialogueState> {
+    match dialogue.get().await {
+        Ok(state) => Some(state.unwrap_or_default()),
+        Err(e) => {
+            eprintln!("Ошибка при чтении диалога в чате {}: {:?}", dialogue.chat_id(), e);
+            match dialogue.exit().await {
+                Ok(()) => Some(DialogueState::Idle),
+                Err(_) => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_storage::SqliteStorage;
+
+    #[tokio::test]
+    async fn dialogues_are_kept_per_member() {
+        let path = std::env::temp_dir().join(format!("dialogue_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::open(path.to_str().unwrap()).unwrap());
+
+        let first = Member { chat: ChatId(-100), user: UserId(1) };
+        let second = Member { chat: ChatId(-100), user: UserId(2) };
+        let state = DialogueState::AwaitingSubscriptionParams { warehouse_id: 507, box_type_name: "Короба".to_string() };
+        member_dialogue(storage.clone(), first).update(state.clone()).await.unwrap();
+        assert_eq!(current_state(member_dialogue(storage.clone(), first)).await, Some(state));
+        assert_eq!(current_state(member_dialogue(storage.clone(), second)).await, Some(DialogueState::Idle));
+
+        // Idle удаляет запись, нечитаемое состояние сбрасывается
+        member_dialogue(storage.clone(), first).update(DialogueState::Idle).await.unwrap();
+        assert_eq!(storage.get_dialogue(first).await.unwrap(), None);
+        storage.update_dialogue(second, r#"{"Unknown":{}}"#.to_string()).await.unwrap();
+        assert_eq!(current_state(member_dialogue(storage.clone(), second)).await, Some(DialogueState::Idle));
+        assert_eq!(storage.get_dialogue(second).await.unwrap(), None);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
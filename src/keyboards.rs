@@ -1,50 +1,82 @@
-use crate::database::{
-    count_user_numbers, count_warehouses, get_user_browser_profiles_page, get_warehouses_page,
-};
-use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, UserId};
+use std::error::Error;
+use crate::database::{CachedSlot, Cabinet, Preset, Subscription};
+use crate::api_reauests::Warehouse;
+use crate::callback_data::{callback_button, long_callback_button, CallbackAction};
+use crate::portal_login::format_phone;
+use crate::storage::Storage;
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup};
 
 // Функция для создания главного меню (клавиатуры)
 pub fn main_menu() -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(vec![
-        vec![InlineKeyboardButton::callback(
+        vec![callback_button(
             "📍Коэффиценты складов",
-            "warehouses_list_callback",
+            CallbackAction::WarehousesList,
+        )],
+        vec![callback_button(
+            "⭐Мои склады",
+            CallbackAction::FavouritesList,
+        )],
+        vec![callback_button(
+            "🔎Найти дешевую приемку",
+            CallbackAction::CheapestSearch,
+        )],
+        vec![callback_button(
+            "🔔Мои подписки",
+            CallbackAction::SubscriptionsList,
+        )],
+        vec![callback_button(
+            "📋Мои пресеты",
+            CallbackAction::PresetsPage(0),
+        )],
+        vec![callback_button(
+            "🗂Мои кабинеты",
+            CallbackAction::CabinetsList,
+        )],
+        vec![callback_button(
+            "📱Вход в кабинет по номеру",
+            CallbackAction::ProfilesList,
+        )],
+        vec![callback_button(
+            "⏳Срок действия токена",
+            CallbackAction::TokenLifetime,
         )],
     ])
 }
 
 pub fn to_main_menu_button() -> InlineKeyboardMarkup {
-    InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+    InlineKeyboardMarkup::new(vec![vec![callback_button(
         "🏠Главное меню",
-        "main_menu",
+        CallbackAction::MainMenu,
     )]])
 }
 
-pub async fn create_warehouse_keyboard(page: i32, page_size: i32) -> InlineKeyboardMarkup {
-    let warehouses = get_warehouses_page(page, page_size).await.unwrap();
-    let total_warehouses = count_warehouses().await.unwrap();
+pub async fn create_warehouse_keyboard(storage: &dyn Storage, id: ChatId, page: i32, page_size: i32) -> InlineKeyboardMarkup {
+    let warehouses = storage.get_warehouses_page(id, page, page_size).await.unwrap();
+    let total_warehouses = storage.count_warehouses().await.unwrap();
     let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
 
-    // Создаем кнопки для каждого склада
-    for w in warehouses {
-        buttons.push(vec![InlineKeyboardButton::callback(
-            w.name,
-            format!("whid:{}", w.id.to_string()),
+    // Создаем кнопки для каждого склада, избранные отмечаем звездочкой
+    for (w, is_favourite) in warehouses {
+        let text = if is_favourite { format!("⭐ {}", w.name) } else { w.name };
+        buttons.push(vec![callback_button(
+            text,
+            CallbackAction::Warehouse(w.id as i32),
         )]);
     }
 
     // Добавляем кнопки перелистывания
     let mut nav_buttons = vec![];
     if page > 0 {
-        nav_buttons.push(InlineKeyboardButton::callback(
+        nav_buttons.push(callback_button(
             "⬅️ Назад",
-            format!("w_page:{}", page - 1),
+            CallbackAction::WarehousesPage(page - 1),
         ));
     }
     if (page + 1) * page_size < total_warehouses {
-        nav_buttons.push(InlineKeyboardButton::callback(
+        nav_buttons.push(callback_button(
             "Вперед ➡️",
-            format!("w_page:{}", page + 1),
+            CallbackAction::WarehousesPage(page + 1),
         ));
     }
 
@@ -52,81 +84,193 @@ pub async fn create_warehouse_keyboard(page: i32, page_size: i32) -> InlineKeybo
     if !nav_buttons.is_empty() {
         buttons.push(nav_buttons);
     }
-    buttons.push(vec![InlineKeyboardButton::callback(
+    buttons.push(vec![callback_button(
+        "🔍 Найти склад по названию",
+        CallbackAction::WarehouseSearch,
+    )]);
+    buttons.push(vec![callback_button(
         "🏠 Главное меню",
-        "main_menu",
+        CallbackAction::MainMenu,
     )]);
 
     InlineKeyboardMarkup::new(buttons)
 }
 
-pub fn create_box_types_keyboard(
+pub async fn create_box_types_keyboard(
+    storage: &dyn Storage,
     box_types: Vec<String>,
     warehouse_id: i32,
-) -> InlineKeyboardMarkup {
+    is_favourite: bool,
+) -> Result<InlineKeyboardMarkup, Box<dyn Error + Send + Sync>> {
     let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
     for t in box_types {
-        buttons.push(vec![InlineKeyboardButton::callback(
+        buttons.push(vec![long_callback_button(
+            storage,
             t.clone(),
-            format!("boxtype:{} whid:{}", t, warehouse_id),
-        )]);
+            CallbackAction::BoxType { warehouse_id, box_type: t },
+        )
+        .await?]);
     }
-    buttons.push(vec![InlineKeyboardButton::callback(
+    buttons.push(vec![callback_button(
+        if is_favourite { "☆ Убрать из избранного" } else { "⭐ В избранное" },
+        CallbackAction::FavouriteToggle(warehouse_id),
+    )]);
+    buttons.push(vec![callback_button(
         "🏠 Главное меню",
-        "main_menu",
+        CallbackAction::MainMenu,
     )]);
 
-    InlineKeyboardMarkup::new(buttons)
+    Ok(InlineKeyboardMarkup::new(buttons))
 }
 
-pub fn create_coefficents_keyboard(btype: i32, _page: i32) -> InlineKeyboardMarkup {
-    InlineKeyboardMarkup::new(vec![
-        vec![InlineKeyboardButton::callback(
+pub async fn create_coefficents_keyboard(storage: &dyn Storage, warehouse_id: i32, btype: &str) -> Result<InlineKeyboardMarkup, Box<dyn Error + Send + Sync>> {
+    Ok(InlineKeyboardMarkup::new(vec![
+        vec![long_callback_button(
+            storage,
+            "🔔Уведомить о коэффициенте",
+            CallbackAction::Subscribe { warehouse_id, box_type: btype.to_string() },
+        )
+        .await?],
+        vec![long_callback_button(
+            storage,
+            "💾Сохранить в пресет",
+            CallbackAction::PresetAdd { warehouse_id, box_type: btype.to_string() },
+        )
+        .await?],
+        vec![
+            long_callback_button(
+                storage,
+                "📊График",
+                CallbackAction::CoefficientChart { warehouse_id, box_type: btype.to_string() },
+            )
+            .await?,
+            long_callback_button(
+                storage,
+                "📈История",
+                CallbackAction::CoefficientHistory { warehouse_id, box_type: btype.to_string() },
+            )
+            .await?,
+        ],
+        vec![callback_button(
             "📦Выбрать другой тип поставки",
-            format!("another_box_type_callback:{}", btype),
+            CallbackAction::AnotherBoxType { warehouse_id },
         )],
-        vec![InlineKeyboardButton::callback(
+        vec![callback_button(
             "📍Выбрать другой склад",
-            format!("another_warehouse_callback"),
+            CallbackAction::AnotherWarehouse,
         )],
-        vec![InlineKeyboardButton::callback(
+        vec![callback_button(
             "🏠Главное меню",
-            "main_menu",
+            CallbackAction::MainMenu,
         )],
-    ])
+    ]))
 }
 
 pub async fn create_user_profiles_keyboard(
-    id: UserId,
+    storage: &dyn Storage,
+    id: ChatId,
     page: i32,
     page_size: i32,
 ) -> InlineKeyboardMarkup {
-    let phones = get_user_browser_profiles_page(id, page, page_size)
+    let phones = storage
+        .get_user_browser_profiles_page(id, page, page_size)
         .await
         .unwrap();
-    let total_warehouses = count_user_numbers(id).await.unwrap();
+    let total_warehouses = storage.count_user_numbers(id).await.unwrap();
     let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
 
-    // Создаем кнопки для каждого склада
+    // Создаем кнопки для каждого профиля
     for p in phones {
-        buttons.push(vec![InlineKeyboardButton::callback(
-            format!("+7{}", p),
-            format!("phone:{}", p),
+        buttons.push(vec![callback_button(
+            format_phone(&p),
+            CallbackAction::Phone(p),
         )]);
     }
 
     // Добавляем кнопки перелистывания
     let mut nav_buttons = vec![];
     if page > 0 {
-        nav_buttons.push(InlineKeyboardButton::callback(
+        nav_buttons.push(callback_button(
             "⬅️ Назад",
-            format!("p_page:{}", page - 1),
+            CallbackAction::PhonesPage(page - 1),
         ));
     }
     if (page + 1) * page_size < total_warehouses {
-        nav_buttons.push(InlineKeyboardButton::callback(
+        nav_buttons.push(callback_button(
+            "Вперед ➡️",
+            CallbackAction::PhonesPage(page + 1),
+        ));
+    }
+
+    // Добавляем навигационные кнопки в конец клавиатуры
+    if !nav_buttons.is_empty() {
+        buttons.push(nav_buttons);
+    }
+    buttons.push(vec![callback_button(
+        "➕ Войти по номеру телефона",
+        CallbackAction::PhoneLogin,
+    )]);
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    InlineKeyboardMarkup::new(buttons)
+}
+
+
+pub fn create_subscriptions_keyboard(subscriptions: &[Subscription]) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    // Кнопка удаления и переключатель автобронирования для каждой подписки
+    for s in subscriptions {
+        let auto_book = if s.auto_book_phone.is_some() { "🤖✅" } else { "🤖" };
+        buttons.push(vec![
+            callback_button(
+                format!("❌ {} / {} (до {})", s.warehouse_name, s.box_type_name, s.max_coefficient),
+                CallbackAction::SubscriptionDelete(s.id),
+            ),
+            callback_button(auto_book, CallbackAction::SubscriptionAutoBook(s.id)),
+        ]);
+    }
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    InlineKeyboardMarkup::new(buttons)
+}
+
+pub async fn create_presets_keyboard(
+    storage: &dyn Storage,
+    id: ChatId,
+    page: i32,
+    page_size: i32,
+) -> Result<InlineKeyboardMarkup, Box<dyn Error + Send + Sync>> {
+    let presets = storage.get_user_presets_page(id, page, page_size).await?;
+    let total_presets = storage.count_user_presets(id).await?;
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    // Создаем кнопки для каждого пресета
+    for (preset_id, name) in presets {
+        buttons.push(vec![callback_button(
+            name,
+            CallbackAction::Preset(preset_id),
+        )]);
+    }
+
+    // Добавляем кнопки перелистывания
+    let mut nav_buttons = vec![];
+    if page > 0 {
+        nav_buttons.push(callback_button(
+            "⬅️ Назад",
+            CallbackAction::PresetsPage(page - 1),
+        ));
+    }
+    if (page + 1) * page_size < total_presets {
+        nav_buttons.push(callback_button(
             "Вперед ➡️",
-            format!("p_page:{}", page + 1),
+            CallbackAction::PresetsPage(page + 1),
         ));
     }
 
@@ -134,10 +278,354 @@ pub async fn create_user_profiles_keyboard(
     if !nav_buttons.is_empty() {
         buttons.push(nav_buttons);
     }
-    buttons.push(vec![InlineKeyboardButton::callback(
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    Ok(InlineKeyboardMarkup::new(buttons))
+}
+
+pub fn create_preset_keyboard(preset_id: i64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![callback_button(
+            "▶️Проверить",
+            CallbackAction::PresetRun(preset_id),
+        )],
+        vec![
+            callback_button("✏️Изменить", CallbackAction::PresetEdit(preset_id)),
+            callback_button("📄Дублировать", CallbackAction::PresetDuplicate(preset_id)),
+            callback_button("🗑Удалить", CallbackAction::PresetDelete(preset_id)),
+        ],
+        vec![callback_button(
+            "📋Мои пресеты",
+            CallbackAction::PresetsPage(0),
+        )],
+        vec![callback_button(
+            "🏠Главное меню",
+            CallbackAction::MainMenu,
+        )],
+    ])
+}
+
+pub fn create_preset_edit_keyboard(preset: &Preset) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    // Склады можно убрать из пресета, пока остается хотя бы один
+    if preset.warehouses.len() > 1 {
+        for w in &preset.warehouses {
+            buttons.push(vec![callback_button(
+                format!("❌ {}", w.name),
+                CallbackAction::PresetRemoveWarehouse { preset_id: preset.id, warehouse_id: w.id as i32 },
+            )]);
+        }
+    }
+    buttons.push(vec![callback_button(
+        "↩️ К пресету",
+        CallbackAction::Preset(preset.id),
+    )]);
+
+    InlineKeyboardMarkup::new(buttons)
+}
+
+pub fn create_preset_choice_keyboard(
+    presets: Vec<(i64, String)>,
+    warehouse_id: i32,
+) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+    for (preset_id, name) in presets {
+        buttons.push(vec![callback_button(
+            format!("➕ В «{}»", name),
+            CallbackAction::PresetPut { preset_id, warehouse_id },
+        )]);
+    }
+    buttons.push(vec![callback_button(
+        "🆕 Новый пресет",
+        CallbackAction::PresetNew,
+    )]);
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    InlineKeyboardMarkup::new(buttons)
+}
+
+pub fn enter_token_keyboard(cabinet_id: i64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![callback_button(
+            "🔑Ввести новый токен",
+            CallbackAction::CabinetToken(cabinet_id),
+        )],
+        vec![callback_button(
+            "🏠Главное меню",
+            CallbackAction::MainMenu,
+        )],
+    ])
+}
+
+pub fn create_cabinets_keyboard(cabinets: &[Cabinet], active_id: Option<i64>) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    // Активный кабинет отмечаем галочкой
+    for c in cabinets {
+        let text = if Some(c.id) == active_id {
+            format!("✅ {}", c.name)
+        } else {
+            c.name.clone()
+        };
+        buttons.push(vec![callback_button(text, CallbackAction::Cabinet(c.id))]);
+    }
+    buttons.push(vec![callback_button(
+        "➕ Добавить кабинет",
+        CallbackAction::CabinetAdd,
+    )]);
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    InlineKeyboardMarkup::new(buttons)
+}
+
+pub fn create_cabinet_keyboard(cabinet_id: i64, is_active: bool) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+    if !is_active {
+        buttons.push(vec![callback_button(
+            "✅Сделать активным",
+            CallbackAction::CabinetSelect(cabinet_id),
+        )]);
+    }
+    buttons.push(vec![
+        callback_button("✏️Переименовать", CallbackAction::CabinetRename(cabinet_id)),
+        callback_button("🔑Обновить токен", CallbackAction::CabinetToken(cabinet_id)),
+        callback_button("🗑Удалить", CallbackAction::CabinetDelete(cabinet_id)),
+    ]);
+    buttons.push(vec![callback_button(
+        "🗂Мои кабинеты",
+        CallbackAction::CabinetsList,
+    )]);
+    buttons.push(vec![callback_button(
+        "🏠Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    InlineKeyboardMarkup::new(buttons)
+}
+
+pub fn create_profile_keyboard(phone: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![
+            callback_button("🔄Войти заново", CallbackAction::ProfileRelogin(phone.to_string())),
+            callback_button("🗑Удалить", CallbackAction::ProfileDelete(phone.to_string())),
+        ],
+        vec![callback_button(
+            "📱Мои профили",
+            CallbackAction::ProfilesList,
+        )],
+        vec![callback_button(
+            "🏠Главное меню",
+            CallbackAction::MainMenu,
+        )],
+    ])
+}
+
+pub fn profile_relogin_keyboard(phone: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![callback_button(
+            "🔄Войти заново",
+            CallbackAction::ProfileRelogin(phone.to_string()),
+        )],
+        vec![callback_button(
+            "🏠Главное меню",
+            CallbackAction::MainMenu,
+        )],
+    ])
+}
+
+pub async fn create_cheapest_box_types_keyboard(storage: &dyn Storage, box_types: Vec<String>) -> Result<InlineKeyboardMarkup, Box<dyn Error + Send + Sync>> {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+    for t in box_types {
+        buttons.push(vec![long_callback_button(
+            storage,
+            t.clone(),
+            CallbackAction::CheapestBoxType(t),
+        )
+        .await?]);
+    }
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    Ok(InlineKeyboardMarkup::new(buttons))
+}
+
+pub async fn create_cheapest_scope_keyboard(
+    storage: &dyn Storage,
+    id: ChatId,
+    page: i32,
+    page_size: i32,
+) -> Result<InlineKeyboardMarkup, Box<dyn Error + Send + Sync>> {
+    let presets = storage.get_user_presets_page(id, page, page_size).await?;
+    let total_presets = storage.count_user_presets(id).await?;
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![vec![callback_button(
+        "🌍 Все склады",
+        CallbackAction::CheapestAllWarehouses,
+    )]];
+
+    // Склады из пресета можно использовать как готовый набор
+    for (preset_id, name) in presets {
+        buttons.push(vec![callback_button(
+            format!("📋 Склады из «{}»", name),
+            CallbackAction::CheapestPreset(preset_id),
+        )]);
+    }
+
+    // Пресетов может быть больше страницы, листаем их как в списке пресетов
+    let mut nav_buttons = vec![];
+    if page > 0 {
+        nav_buttons.push(callback_button(
+            "⬅️ Назад",
+            CallbackAction::CheapestScopePage(page - 1),
+        ));
+    }
+    if (page + 1) * page_size < total_presets {
+        nav_buttons.push(callback_button(
+            "Вперед ➡️",
+            CallbackAction::CheapestScopePage(page + 1),
+        ));
+    }
+    if !nav_buttons.is_empty() {
+        buttons.push(nav_buttons);
+    }
+    buttons.push(vec![callback_button(
         "🏠 Главное меню",
-        "main_menu",
+        CallbackAction::MainMenu,
+    )]);
+
+    Ok(InlineKeyboardMarkup::new(buttons))
+}
+
+pub async fn create_cheapest_results_keyboard(
+    storage: &dyn Storage,
+    slots: &[(String, CachedSlot)],
+    box_type: &str,
+    page: i32,
+    has_next: bool,
+) -> Result<InlineKeyboardMarkup, Box<dyn Error + Send + Sync>> {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    // Каждый вариант открывает коэффициенты склада
+    for (text, slot) in slots {
+        buttons.push(vec![long_callback_button(
+            storage,
+            text.clone(),
+            CallbackAction::BoxType { warehouse_id: slot.warehouse_id as i32, box_type: box_type.to_string() },
+        )
+        .await?]);
+    }
+
+    // Добавляем кнопки перелистывания
+    let mut nav_buttons = vec![];
+    if page > 0 {
+        nav_buttons.push(callback_button(
+            "⬅️ Назад",
+            CallbackAction::CheapestPage(page - 1),
+        ));
+    }
+    if has_next {
+        nav_buttons.push(callback_button(
+            "Вперед ➡️",
+            CallbackAction::CheapestPage(page + 1),
+        ));
+    }
+    if !nav_buttons.is_empty() {
+        buttons.push(nav_buttons);
+    }
+    buttons.push(vec![callback_button(
+        "🔁 Новый поиск",
+        CallbackAction::CheapestSearch,
+    )]);
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    Ok(InlineKeyboardMarkup::new(buttons))
+}
+
+pub fn create_favourites_keyboard(warehouses: &[Warehouse]) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+    for w in warehouses {
+        buttons.push(vec![callback_button(
+            format!("📍 {}", w.name),
+            CallbackAction::Warehouse(w.id as i32),
+        )]);
+    }
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
     )]);
 
     InlineKeyboardMarkup::new(buttons)
 }
+
+pub fn create_found_warehouses_keyboard(warehouses: &[Warehouse]) -> InlineKeyboardMarkup {
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
+    for w in warehouses {
+        buttons.push(vec![callback_button(
+            w.name.clone(),
+            CallbackAction::Warehouse(w.id as i32),
+        )]);
+    }
+    buttons.push(vec![callback_button(
+        "📍 Все склады",
+        CallbackAction::WarehousesPage(0),
+    )]);
+    buttons.push(vec![callback_button(
+        "🏠 Главное меню",
+        CallbackAction::MainMenu,
+    )]);
+
+    InlineKeyboardMarkup::new(buttons)
+}
+
+// Кнопка под результатом inline-режима: открывает склад в личном чате с ботом
+pub fn create_inline_warehouse_keyboard(bot_username: &str, warehouse_id: u32) -> InlineKeyboardMarkup {
+    let url = format!("https://t.me/{}?start=wh_{}", bot_username, warehouse_id);
+    match url.parse() {
+        Ok(url) => InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::url("📍Открыть в боте", url)]]),
+        Err(_) => InlineKeyboardMarkup::default(),
+    }
+}
+
+pub fn open_warehouse_keyboard(warehouse_id: u32) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![callback_button(
+            "📈Показать коэффициенты",
+            CallbackAction::Warehouse(warehouse_id as i32),
+        )],
+        vec![callback_button(
+            "🏠Главное меню",
+            CallbackAction::MainMenu,
+        )],
+    ])
+}
+
+pub fn broadcast_confirm_keyboard(broadcast_id: i64, recipients: i64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![callback_button(
+            format!("✅ Отправить ({})", recipients),
+            CallbackAction::BroadcastConfirm(broadcast_id),
+        )],
+        vec![callback_button("❌ Отменить", CallbackAction::BroadcastCancel(broadcast_id))],
+    ])
+}
+
+pub fn broadcast_progress_keyboard(broadcast_id: i64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![callback_button(
+        "⛔️ Остановить рассылку",
+        CallbackAction::BroadcastCancel(broadcast_id),
+    )]])
+}
@@ -1,16 +1,47 @@
 // src/main.rs
+mod admins;
 mod api_reauests;
+mod auto_booking;
 mod bot_commands;
+mod broadcast;
 mod bot_callbacks;
 mod database;
+mod dialogue;
+mod migrations;
+mod token_crypto;
 mod token_decoder;
 mod keyboards;
 mod commands_handlers;
 mod callback_handlers;
+mod callback_data;
+mod config;
+mod coefficients_watcher;
+mod coefficient_history;
+mod portal_login;
+mod profile_health;
+mod seller_portal;
+mod sqlite_storage;
+mod storage;
+#[cfg(feature = "postgres")]
+mod postgres_storage;
+mod charts;
+mod cheapest_search;
+mod stats;
+mod warehouse_search;
+mod token_expiry_watcher;
+#[cfg(test)]
+mod wb_mock;
+#[cfg(test)]
+mod webdriver_mock;
 
-use bot_commands::answer;
+use bot_commands::{answer, parse_command, record_command_usage, record_message_usage};
 use bot_callbacks::callback_handler;
-use commands_handlers::bot_started_msg;
+use commands_handlers::{
+    bot_started_msg, captcha_handler, cabinet_name_handler, inline_query_handler, phone_number_handler, preset_params_handler,
+    search_params_handler, sms_code_handler, subscription_params_handler, token_handler, warehouse_search_handler,
+};
+use database::Member;
+use dialogue::{current_state, member_dialogue, DialogueState};
 use log::info;
 use teloxide::prelude::*;
 use tokio::runtime::Builder;
@@ -23,44 +54,156 @@ use database::delete_expired_records;
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     dotenv::dotenv().ok();
     pretty_env_logger::init();
+
+    // Ошибки настроек выводим как есть, построчно по каждому ключу
+    let settings = config::parse_args(std::env::args().skip(1))
+        .and_then(|args| Ok((config::load(args.config_path)?, args.check_config)));
+    let (settings, check_config) = match settings {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if check_config {
+        print!("{}", config::to_toml(&settings)?);
+        return Ok(());
+    }
+    config::init(settings)?;
+    let settings = config::get();
     info!("Запуск бота для работы с WB");
 
-    database::initialize_db().map_err(|e| {
-        eprintln!("Ошибка при иницилизации базы данных: {}", e);
+    // Все данные бота - в SQLite или в общей базе PostgreSQL
+    let storage = storage::open_from_env(&settings.db_path).await.map_err(|e| {
+        eprintln!("Ошибка при подключении к хранилищу: {}", e);
         e
     })?;
+    api_reauests::WB_CLIENT.record_calls_to(storage.clone());
+
+    // Папки профилей браузера раньше были общими для всех чатов с номером
+    let profiles = storage.get_all_browser_profiles().await?;
+    if let Err(e) = seller_portal::move_legacy_profile_dirs(&seller_portal::PORTAL_CONFIG, &profiles).await {
+        eprintln!("Ошибка при переносе папок профилей браузера: {:?}", e);
+    }
+
+    token_crypto::init_from_env()?;
+    admins::init(&settings.owner_ids)?;
+    // Разовая миграция: шифруем токены, которые еще лежат в базе открытым текстом
+    let encrypted = storage.reencrypt_user_tokens().await?;
+    if encrypted > 0 {
+        info!("Зашифровано токенов: {}", encrypted);
+    }
 
     let bot = Bot::from_env();
 
-    let mut delete_interval = time::interval(Duration::from_secs(1*60*60));
+    let mut delete_interval = time::interval(Duration::from_secs(settings.intervals.cleanup_secs));
+    let history_retention_days = settings.coefficient_history_days;
 
     // Создаем задачу для автоудаления
+    let delete_storage = storage.clone();
     task::spawn(async move {
         loop {
             delete_interval.tick().await;
 
-            if let Err(e) = delete_expired_records().await {
+            if let Err(e) = delete_expired_records(delete_storage.as_ref(), history_retention_days).await {
                 eprintln!("Ошибка при удалении старых записей: {:?}", e);
             }
         }
     });
 
+    // Профили браузера лежат на диске экземпляра, а рассылку отправил бы каждый экземпляр:
+    // при общей PostgreSQL фоновые задачи включены только в одном из них
+    if settings.background_tasks {
+        // Создаем задачу для проверки подписок на коэффициенты
+        let watcher_bot = bot.clone();
+        let watcher_storage = storage.clone();
+        task::spawn(async move {
+            let mut watch_interval = time::interval(Duration::from_secs(settings.intervals.coefficients_watch_secs));
+            loop {
+                watch_interval.tick().await;
+
+                if let Err(e) = coefficients_watcher::check_subscriptions(&watcher_bot, &watcher_storage).await {
+                    eprintln!("Ошибка при проверке подписок: {:?}", e);
+                }
+            }
+        });
+
+        // Создаем задачу для напоминаний об окончании срока токенов
+        let reminder_bot = bot.clone();
+        let reminder_storage = storage.clone();
+        task::spawn(async move {
+            let mut reminder_interval = time::interval(Duration::from_secs(settings.intervals.token_check_secs));
+            loop {
+                reminder_interval.tick().await;
+
+                if let Err(e) = token_expiry_watcher::check_token_expiry(&reminder_bot, reminder_storage.as_ref()).await {
+                    eprintln!("Ошибка при проверке сроков токенов: {:?}", e);
+                }
+            }
+        });
+
+        // Создаем задачу для проверки входа в кабинет в профилях браузера
+        let profiles_bot = bot.clone();
+        let profiles_storage = storage.clone();
+        task::spawn(async move {
+            let mut profiles_interval = time::interval(Duration::from_secs(settings.intervals.profile_check_secs));
+            loop {
+                profiles_interval.tick().await;
+
+                if let Err(e) = profile_health::check_profiles(&profiles_bot, profiles_storage.as_ref()).await {
+                    eprintln!("Ошибка при проверке профилей браузера: {:?}", e);
+                }
+            }
+        });
+
+        // Создаем задачу для отправки рассылок, незавершенные продолжаются после перезапуска
+        task::spawn(broadcast::run_broadcasts(bot.clone(), storage.clone()));
+    }
+
     bot_started_msg(bot.clone()).await?;
 
     let runtime = Builder::new_multi_thread()
-        .worker_threads(4) // Количество потоков в рантайме
+        .worker_threads(settings.worker_threads) // Количество потоков в рантайме
         .thread_stack_size(8 * 1024 * 1024) // Размер стека в байтах (например, 3 MB)
         .enable_all()
         .build()?;
 
+    // Команды обрабатываются в любом состоянии, остальной текст - по текущему шагу диалога участника
+    let dialogue_handler = dptree::filter(|msg: Message| msg.text().is_some())
+        .filter_map(|msg: Message| Member::from_message(&msg))
+        .map(member_dialogue)
+        .filter_map_async(current_state)
+        .branch(dptree::case![DialogueState::AwaitingToken { cabinet }].endpoint(token_handler))
+        .branch(dptree::case![DialogueState::AwaitingCabinetName { cabinet }].endpoint(cabinet_name_handler))
+        .branch(
+            dptree::case![DialogueState::AwaitingSubscriptionParams { warehouse_id, box_type_name }]
+                .endpoint(subscription_params_handler),
+        )
+        .branch(dptree::case![DialogueState::AwaitingPresetParams { preset }].endpoint(preset_params_handler))
+        .branch(dptree::case![DialogueState::AwaitingNumber].endpoint(phone_number_handler))
+        .branch(dptree::case![DialogueState::AwaitingCaptcha { phone }].endpoint(captcha_handler))
+        .branch(dptree::case![DialogueState::AwaitingSmsCode { phone }].endpoint(sms_code_handler))
+        .branch(dptree::case![DialogueState::AwaitingSearchParams].endpoint(search_params_handler))
+        .branch(dptree::case![DialogueState::AwaitingWarehouseSearch].endpoint(warehouse_search_handler));
+
     let handler = dptree::entry()
-        //.branch(Update::filter_message().endpoint(answer));
-        .branch(Update::filter_message().endpoint(|bot, msg| async move { answer(bot, msg).await }))
-        .branch(Update::filter_callback_query().endpoint(callback_handler));
+        .branch(
+            Update::filter_message()
+                .branch(dptree::filter_map(parse_command).inspect_async(record_command_usage).endpoint(answer))
+                .branch(dptree::entry().inspect_async(record_message_usage).chain(dialogue_handler)),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .map(|q: CallbackQuery| Member::from_callback(&q))
+                .map(member_dialogue)
+                .endpoint(callback_handler),
+        )
+        .branch(Update::filter_inline_query().endpoint(inline_query_handler));
 
     runtime
         .spawn(async {
             Dispatcher::builder(bot, handler)
+                .dependencies(dptree::deps![storage])
                 .enable_ctrlc_handler()
                 .build()
                 .dispatch()
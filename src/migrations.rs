@@ -0,0 +1,452 @@
+use rusqlite::{params, Connection, Result};
+
+// Миграции схемы по порядку: номер версии = позиция в списке + 1.
+// Уже примененные миграции не меняем, новые таблицы и колонки добавляем новой миграцией в конец
+const MIGRATIONS: &[&str] = &[
+    // 1: исходная схема
+    "
+        CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY,
+            username TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS user_states (
+            id INTEGER PRIMARY KEY,
+            state INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS user_tokens (
+            chat_id INTEGER PRIMARY KEY,
+            token TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS warehouses (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS warehouses_coefficients (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            date INTEGER NOT NULL,
+            coefficient INTEGER NOT NULL,
+            warehouse_id INTEGER NOT NULL,
+            warehouse_name TEXT NOT NULL,
+            box_type_name TEXT NOT NULL,
+            box_type_id INTEGER,
+            UNIQUE(date, warehouse_id, box_type_name)
+        );
+        CREATE TABLE IF NOT EXISTS subscriptions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            warehouse_id INTEGER NOT NULL,
+            warehouse_name TEXT NOT NULL,
+            box_type_name TEXT NOT NULL,
+            max_coefficient INTEGER NOT NULL,
+            date_from INTEGER,
+            date_to INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS subscription_drafts (
+            user_id INTEGER PRIMARY KEY,
+            warehouse_id INTEGER NOT NULL,
+            box_type_name TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS subscription_notifications (
+            subscription_id INTEGER NOT NULL,
+            date INTEGER NOT NULL,
+            UNIQUE(subscription_id, date)
+        );
+        CREATE TABLE IF NOT EXISTS presets (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            box_type_name TEXT NOT NULL,
+            max_coefficient INTEGER NOT NULL,
+            date_from INTEGER,
+            date_to INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS preset_warehouses (
+            preset_id INTEGER NOT NULL,
+            warehouse_id INTEGER NOT NULL,
+            UNIQUE(preset_id, warehouse_id)
+        );
+        CREATE TABLE IF NOT EXISTS callback_payloads (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            payload TEXT NOT NULL UNIQUE
+        );
+        CREATE TABLE IF NOT EXISTS preset_drafts (
+            user_id INTEGER PRIMARY KEY,
+            preset_id INTEGER,
+            warehouse_id INTEGER,
+            box_type_name TEXT
+        );
+    ",
+    // 2: профили браузера, которые уже читают get_user_browser_profiles_page и count_user_numbers
+    "
+        CREATE TABLE IF NOT EXISTS chrome_profiles (
+            id INTEGER NOT NULL,
+            phone_number TEXT NOT NULL,
+            UNIQUE(id, phone_number)
+        );
+    ",
+    // 3: отправленные напоминания об окончании срока токена
+    "
+        CREATE TABLE IF NOT EXISTS token_reminders (
+            chat_id INTEGER NOT NULL,
+            exp INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            UNIQUE(chat_id, exp, kind)
+        );
+    ",
+    // 4: несколько кабинетов продавца на пользователя, токены переносятся из user_tokens в кабинет "Основной"
+    "
+        CREATE TABLE IF NOT EXISTS cabinets (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            chat_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            token TEXT NOT NULL,
+            UNIQUE(chat_id, name)
+        );
+        CREATE TABLE IF NOT EXISTS active_cabinets (
+            chat_id INTEGER PRIMARY KEY,
+            cabinet_id INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS cabinet_drafts (
+            chat_id INTEGER PRIMARY KEY,
+            cabinet_id INTEGER,
+            name TEXT
+        );
+        INSERT INTO cabinets (chat_id, name, token) SELECT chat_id, 'Основной', token FROM user_tokens;
+        INSERT INTO active_cabinets (chat_id, cabinet_id) SELECT chat_id, id FROM cabinets;
+        DROP TABLE user_tokens;
+    ",
+    // 5: история коэффициентов, в отличие от warehouses_coefficients не перезаписывается
+    "
+        CREATE TABLE IF NOT EXISTS coefficient_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            date INTEGER NOT NULL,
+            warehouse_id INTEGER NOT NULL,
+            warehouse_name TEXT NOT NULL,
+            box_type_name TEXT NOT NULL,
+            coefficient INTEGER NOT NULL,
+            observed_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS coefficient_history_slot
+            ON coefficient_history (warehouse_id, box_type_name, date, observed_at);
+        CREATE INDEX IF NOT EXISTS coefficient_history_observed_at
+            ON coefficient_history (observed_at);
+    ",
+    // 6: параметры поиска самой дешевой приемки по всем складам (последний поиск пользователя)
+    "
+        CREATE TABLE IF NOT EXISTS cheapest_searches (
+            user_id INTEGER PRIMARY KEY,
+            box_type_name TEXT NOT NULL,
+            warehouse_ids TEXT,
+            name_filter TEXT,
+            max_coefficient INTEGER,
+            date_from INTEGER,
+            date_to INTEGER
+        );
+    ",
+    // 7: избранные склады пользователя
+    "
+        CREATE TABLE IF NOT EXISTS favourite_warehouses (
+            user_id INTEGER NOT NULL,
+            warehouse_id INTEGER NOT NULL,
+            UNIQUE(user_id, warehouse_id)
+        );
+    ",
+    // 8: групповые чаты, состояние диалогов и черновики хранятся для пары (чат, участник).
+    // В личном чате id чата совпадает с id пользователя, поэтому старые записи переносятся как (id, id)
+    "
+        ALTER TABLE user_states RENAME TO user_states_old;
+        CREATE TABLE user_states (
+            chat_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            state INTEGER NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        );
+        INSERT INTO user_states (chat_id, user_id, state) SELECT id, id, state FROM user_states_old;
+        DROP TABLE user_states_old;
+
+        ALTER TABLE subscription_drafts RENAME TO subscription_drafts_old;
+        CREATE TABLE subscription_drafts (
+            chat_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            warehouse_id INTEGER NOT NULL,
+            box_type_name TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        );
+        INSERT INTO subscription_drafts (chat_id, user_id, warehouse_id, box_type_name)
+            SELECT user_id, user_id, warehouse_id, box_type_name FROM subscription_drafts_old;
+        DROP TABLE subscription_drafts_old;
+
+        ALTER TABLE preset_drafts RENAME TO preset_drafts_old;
+        CREATE TABLE preset_drafts (
+            chat_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            preset_id INTEGER,
+            warehouse_id INTEGER,
+            box_type_name TEXT,
+            PRIMARY KEY (chat_id, user_id)
+        );
+        INSERT INTO preset_drafts (chat_id, user_id, preset_id, warehouse_id, box_type_name)
+            SELECT user_id, user_id, preset_id, warehouse_id, box_type_name FROM preset_drafts_old;
+        DROP TABLE preset_drafts_old;
+
+        ALTER TABLE cabinet_drafts RENAME TO cabinet_drafts_old;
+        CREATE TABLE cabinet_drafts (
+            chat_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            cabinet_id INTEGER,
+            name TEXT,
+            PRIMARY KEY (chat_id, user_id)
+        );
+        INSERT INTO cabinet_drafts (chat_id, user_id, cabinet_id, name)
+            SELECT chat_id, chat_id, cabinet_id, name FROM cabinet_drafts_old;
+        DROP TABLE cabinet_drafts_old;
+
+        ALTER TABLE cheapest_searches RENAME TO cheapest_searches_old;
+        CREATE TABLE cheapest_searches (
+            chat_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            box_type_name TEXT NOT NULL,
+            warehouse_ids TEXT,
+            name_filter TEXT,
+            max_coefficient INTEGER,
+            date_from INTEGER,
+            date_to INTEGER,
+            PRIMARY KEY (chat_id, user_id)
+        );
+        INSERT INTO cheapest_searches (chat_id, user_id, box_type_name, warehouse_ids, name_filter, max_coefficient, date_from, date_to)
+            SELECT user_id, user_id, box_type_name, warehouse_ids, name_filter, max_coefficient, date_from, date_to FROM cheapest_searches_old;
+        DROP TABLE cheapest_searches_old;
+    ",
+    // 9: роли администраторов бота. Владельцы из BOT_OWNER_IDS сюда не пишутся
+    "
+        CREATE TABLE IF NOT EXISTS admins (
+            user_id INTEGER PRIMARY KEY,
+            role TEXT NOT NULL,
+            granted_by INTEGER NOT NULL,
+            granted_at INTEGER NOT NULL
+        );
+    ",
+    // 10: очередь рассылок. Получатели со статусом pending досылаются после перезапуска,
+    // пользователи, заблокировавшие бота, помечаются неактивными и в новые рассылки не попадают
+    "
+        ALTER TABLE users ADD COLUMN active INTEGER NOT NULL DEFAULT 1;
+        CREATE TABLE IF NOT EXISTS broadcasts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            admin_chat_id INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            photo TEXT,
+            buttons TEXT NOT NULL,
+            status TEXT NOT NULL,
+            progress_message_id INTEGER,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS broadcast_recipients (
+            broadcast_id INTEGER NOT NULL,
+            chat_id INTEGER NOT NULL,
+            status TEXT NOT NULL,
+            PRIMARY KEY (broadcast_id, chat_id)
+        );
+    ",
+    // 11: статистика использования для /stats: действия пользователей, уведомления и запросы к WB API
+    "
+        CREATE TABLE IF NOT EXISTS usage_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            chat_id INTEGER NOT NULL,
+            user_id INTEGER,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS usage_events_kind_created ON usage_events (kind, created_at);
+        CREATE TABLE IF NOT EXISTS api_calls (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            endpoint TEXT NOT NULL,
+            status INTEGER,
+            ok INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS api_calls_created ON api_calls (created_at);
+    ",
+    // 12: автобронирование слотов: номер, под которым выполнен вход в профиле браузера
+    "
+        ALTER TABLE subscriptions ADD COLUMN auto_book_phone TEXT;
+    ",
+    // 13: время последнего входа в кабинет по номеру
+    "
+        ALTER TABLE chrome_profiles ADD COLUMN last_login_at INTEGER;
+    ",
+    // 14: состояние сессии кабинета в профиле по результатам периодической проверки
+    "
+        ALTER TABLE chrome_profiles ADD COLUMN health TEXT NOT NULL DEFAULT 'unknown';
+        ALTER TABLE chrome_profiles ADD COLUMN checked_at INTEGER;
+    ",
+    // 15: диалоги вместо номера состояния и отдельных черновиков: состояние вместе с данными шага в JSON
+    // (сериализованный dialogue::DialogueState). Капча и код из СМС в памяти и после перезапуска не нужны
+    "
+        CREATE TABLE IF NOT EXISTS dialogues (
+            chat_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            dialogue TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        );
+        INSERT INTO dialogues (chat_id, user_id, dialogue)
+        SELECT chat_id, user_id, dialogue FROM (
+            SELECT s.chat_id, s.user_id, CASE s.state
+                WHEN 1 THEN json_object('AwaitingToken', json_object('cabinet', json(CASE
+                    WHEN c.cabinet_id IS NOT NULL THEN json_object('Edit', json_object('cabinet_id', c.cabinet_id))
+                    WHEN c.chat_id IS NOT NULL THEN json_object('New', json_object('name', c.name))
+                END)))
+                WHEN 3 THEN json_quote('AwaitingNumber')
+                WHEN 6 THEN CASE WHEN d.chat_id IS NOT NULL THEN json_object('AwaitingSubscriptionParams',
+                    json_object('warehouse_id', d.warehouse_id, 'box_type_name', d.box_type_name)) END
+                WHEN 7 THEN CASE
+                    WHEN p.preset_id IS NOT NULL THEN json_object('AwaitingPresetParams',
+                        json_object('preset', json_object('Edit', json_object('preset_id', p.preset_id))))
+                    WHEN p.warehouse_id IS NOT NULL AND p.box_type_name IS NOT NULL THEN json_object('AwaitingPresetParams',
+                        json_object('preset', json_object('New', json_object('warehouse_id', p.warehouse_id, 'box_type_name', p.box_type_name))))
+                END
+                WHEN 8 THEN CASE
+                    WHEN c.cabinet_id IS NOT NULL THEN json_object('AwaitingCabinetName',
+                        json_object('cabinet', json_object('Edit', json_object('cabinet_id', c.cabinet_id))))
+                    WHEN c.chat_id IS NOT NULL THEN json_object('AwaitingCabinetName',
+                        json_object('cabinet', json_object('New', json_object('name', c.name))))
+                END
+                WHEN 9 THEN json_quote('AwaitingSearchParams')
+                WHEN 10 THEN json_quote('AwaitingWarehouseSearch')
+            END AS dialogue
+            FROM user_states s
+            LEFT JOIN cabinet_drafts c ON c.chat_id = s.chat_id AND c.user_id = s.user_id
+            LEFT JOIN subscription_drafts d ON d.chat_id = s.chat_id AND d.user_id = s.user_id
+            LEFT JOIN preset_drafts p ON p.chat_id = s.chat_id AND p.user_id = s.user_id
+        )
+        WHERE dialogue IS NOT NULL;
+        DROP TABLE user_states;
+        DROP TABLE subscription_drafts;
+        DROP TABLE preset_drafts;
+        DROP TABLE cabinet_drafts;
+    ",
+];
+
+pub fn current_version(conn: &Connection) -> Result<usize> {
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);")?;
+    let version: Option<i64> = conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| row.get(0))?;
+    Ok(version.unwrap_or(0) as usize)
+}
+
+// Применяет недостающие миграции, каждую в своей транзакции
+pub fn run_migrations(conn: &mut Connection) -> Result<()> {
+    let version = current_version(conn)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute("DELETE FROM schema_version", [])?;
+        tx.execute("INSERT INTO schema_version (version) VALUES (?1)", params![index as i64 + 1])?;
+        tx.commit()?;
+        log::info!("Применена миграция базы данных {}", index + 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialogue::{CabinetDraft, DialogueState, PresetDraft};
+    use rusqlite::OptionalExtension;
+
+    #[test]
+    fn migrations_apply_once_and_reach_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), MIGRATIONS.len());
+        // повторный запуск ничего не делает
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), MIGRATIONS.len());
+        let count: i32 = conn
+            .query_row("SELECT COUNT(*) FROM chrome_profiles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn existing_tokens_move_to_default_cabinet() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        current_version(&conn).unwrap();
+        for migration in &MIGRATIONS[..3] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.execute("INSERT INTO schema_version (version) VALUES (3)", []).unwrap();
+        conn.execute("INSERT INTO user_tokens (chat_id, token) VALUES (42, 'enc1:token')", []).unwrap();
+
+        run_migrations(&mut conn).unwrap();
+        let (name, token, active): (String, String, i64) = conn
+            .query_row(
+                "SELECT c.name, c.token, a.cabinet_id FROM cabinets c JOIN active_cabinets a ON a.chat_id = c.chat_id WHERE c.chat_id = 42",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((name.as_str(), token.as_str(), active), ("Основной", "enc1:token", 1));
+    }
+
+    #[test]
+    fn private_chat_states_become_member_states() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        current_version(&conn).unwrap();
+        for migration in &MIGRATIONS[..7] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.execute("INSERT INTO schema_version (version) VALUES (7)", []).unwrap();
+        conn.execute("INSERT INTO user_states (id, state) VALUES (42, 1)", []).unwrap();
+        conn.execute("INSERT INTO cabinet_drafts (chat_id, cabinet_id, name) VALUES (42, 3, NULL)", []).unwrap();
+
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(
+            migrated_dialogue(&conn, 42),
+            Some(DialogueState::AwaitingToken { cabinet: Some(CabinetDraft::Edit { cabinet_id: 3 }) })
+        );
+    }
+
+    fn migrated_dialogue(conn: &Connection, chat_id: i64) -> Option<DialogueState> {
+        let json: Option<String> = conn
+            .query_row("SELECT dialogue FROM dialogues WHERE chat_id = ?1 AND user_id = ?1", params![chat_id], |row| row.get(0))
+            .optional()
+            .unwrap();
+        json.map(|json| serde_json::from_str(&json).unwrap())
+    }
+
+    #[test]
+    fn states_and_drafts_become_dialogues() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        current_version(&conn).unwrap();
+        for migration in &MIGRATIONS[..14] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.execute("INSERT INTO schema_version (version) VALUES (14)", []).unwrap();
+        conn.execute_batch(
+            "INSERT INTO user_states (chat_id, user_id, state) VALUES (1, 1, 6), (2, 2, 7), (3, 3, 8), (4, 4, 3), (5, 5, 5), (6, 6, 6);
+             INSERT INTO subscription_drafts (chat_id, user_id, warehouse_id, box_type_name) VALUES (1, 1, 507, 'Короба');
+             INSERT INTO preset_drafts (chat_id, user_id, preset_id, warehouse_id, box_type_name) VALUES (2, 2, NULL, 117986, 'Монопаллеты');
+             INSERT INTO cabinet_drafts (chat_id, user_id, cabinet_id, name) VALUES (3, 3, NULL, NULL);",
+        )
+        .unwrap();
+
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(
+            migrated_dialogue(&conn, 1),
+            Some(DialogueState::AwaitingSubscriptionParams { warehouse_id: 507, box_type_name: "Короба".to_string() })
+        );
+        assert_eq!(
+            migrated_dialogue(&conn, 2),
+            Some(DialogueState::AwaitingPresetParams {
+                preset: PresetDraft::New { warehouse_id: 117986, box_type_name: "Монопаллеты".to_string() }
+            })
+        );
+        assert_eq!(
+            migrated_dialogue(&conn, 3),
+            Some(DialogueState::AwaitingCabinetName { cabinet: CabinetDraft::New { name: None } })
+        );
+        assert_eq!(migrated_dialogue(&conn, 4), Some(DialogueState::AwaitingNumber));
+        // Вход по номеру не переживает перезапуск, подписка без черновика склада продолжиться не может
+        assert_eq!(migrated_dialogue(&conn, 5), None);
+        assert_eq!(migrated_dialogue(&conn, 6), None);
+    }
+}
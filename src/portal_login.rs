@@ -0,0 +1,293 @@
+// Вход в кабинет продавца по номеру телефона: номер -> капча (если портал ее покажет) -> код из СМС.
+// Браузер живет между сообщениями пользователя, поэтому незавершенные входы хранятся в памяти
+// по участнику чата и закрываются через LOGIN_TIMEOUT после начала
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thirtyfour::prelude::*;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::database::Member;
+use crate::seller_portal::{
+    click_step, fill_step, lock_profile, selectors, start_browser, wait_for_any, PortalConfig, PortalError, PORTAL_CONFIG,
+};
+use crate::storage::Storage;
+
+// Страница входа в кабинет продавца
+const LOGIN_PATH: &str = "/login";
+// Сколько ждем, пока пользователь введет капчу и код из СМС
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+lazy_static! {
+    static ref LOGINS: Mutex<HashMap<(i64, u64), LoginSession>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_LOGIN_ID: AtomicU64 = AtomicU64::new(1);
+
+struct LoginSession {
+    id: u64,
+    started: Instant,
+    phone: String,
+    driver: WebDriver,
+    _profile: OwnedMutexGuard<()>, // пока идет вход, профиль номера не откроет автобронирование
+}
+
+#[derive(Debug)]
+pub enum LoginStep {
+    Captcha(Vec<u8>),      // нужно ввести текст с картинки
+    WrongCaptcha(Vec<u8>), // текст не подошел, портал показал новую картинку
+    SmsCode,               // портал отправил код в СМС
+    WrongCode,             // код не подошел, можно ввести еще раз
+    LoggedIn,              // вход выполнен, профиль сохранен
+}
+
+// Российский номер мобильного в любом привычном виде -> 10 цифр без кода страны
+pub fn normalize_phone(input: &str) -> Option<String> {
+    if input.chars().any(|c| !(c.is_ascii_digit() || " +-()".contains(c))) {
+        return None;
+    }
+    let digits: String = input.chars().filter(|c| c.is_ascii_digit()).collect();
+    let national = match digits.len() {
+        11 if digits.starts_with('7') || digits.starts_with('8') => &digits[1..],
+        10 => &digits[..],
+        _ => return None,
+    };
+    national.starts_with('9').then(|| national.to_string())
+}
+
+pub fn format_phone(phone: &str) -> String {
+    format!("+7{}", phone)
+}
+
+async fn captcha_or_code(driver: &WebDriver, config: &PortalConfig) -> Result<LoginStep, PortalError> {
+    let wanted = [selectors::CAPTCHA_IMAGE, selectors::CODE_INPUT, selectors::LOGIN_ERROR];
+    match wait_for_any(driver, &wanted, config.result_timeout).await? {
+        Some((0, image)) => Ok(LoginStep::Captcha(image.screenshot_as_png().await?)),
+        Some((1, _)) => Ok(LoginStep::SmsCode),
+        Some((_, error)) => Err(PortalError::Rejected(error.text().await?)),
+        None => Err(PortalError::NoResponse),
+    }
+}
+
+async fn submit_phone(driver: &WebDriver, config: &PortalConfig, phone: &str) -> Result<LoginStep, PortalError> {
+    driver.goto(config.page_url(LOGIN_PATH)).await?;
+    // В профиле чата уже может быть действующая сессия: ее оставил прошлый вход с этим номером в этом же чате
+    match wait_for_any(driver, &[selectors::PHONE_INPUT, selectors::SELLER_PROFILE], config.step_timeout).await? {
+        Some((0, _)) => {}
+        Some(_) => return Ok(LoginStep::LoggedIn),
+        None => return Err(PortalError::ElementMissing("поле номера телефона")),
+    }
+    fill_step(driver, config, selectors::PHONE_INPUT, &format_phone(phone), "поле номера телефона").await?;
+    click_step(driver, config, selectors::PHONE_SUBMIT, PortalError::ElementMissing("кнопка получения кода")).await?;
+    captcha_or_code(driver, config).await
+}
+
+async fn submit_captcha(driver: &WebDriver, config: &PortalConfig, text: &str) -> Result<LoginStep, PortalError> {
+    fill_step(driver, config, selectors::CAPTCHA_INPUT, text, "поле капчи").await?;
+    click_step(driver, config, selectors::CAPTCHA_SUBMIT, PortalError::ElementMissing("кнопка отправки капчи")).await?;
+    match wait_for_any(driver, &[selectors::CODE_INPUT, selectors::LOGIN_ERROR], config.result_timeout).await? {
+        Some((0, _)) => Ok(LoginStep::SmsCode),
+        Some(_) => match wait_for_any(driver, &[selectors::CAPTCHA_IMAGE], config.step_timeout).await? {
+            Some((_, image)) => Ok(LoginStep::WrongCaptcha(image.screenshot_as_png().await?)),
+            None => Err(PortalError::ElementMissing("капча")),
+        },
+        None => Err(PortalError::NoResponse),
+    }
+}
+
+async fn submit_code(driver: &WebDriver, config: &PortalConfig, code: &str) -> Result<LoginStep, PortalError> {
+    fill_step(driver, config, selectors::CODE_INPUT, code, "поле кода из СМС").await?;
+    click_step(driver, config, selectors::CODE_SUBMIT, PortalError::ElementMissing("кнопка входа")).await?;
+    match wait_for_any(driver, &[selectors::SELLER_PROFILE, selectors::LOGIN_ERROR], config.result_timeout).await? {
+        Some((0, _)) => Ok(LoginStep::LoggedIn),
+        Some(_) => Ok(LoginStep::WrongCode),
+        None => Err(PortalError::NoResponse),
+    }
+}
+
+fn key(member: Member) -> (i64, u64) {
+    (member.chat.0, member.user.0)
+}
+
+async fn close(session: LoginSession) {
+    if let Err(e) = session.driver.quit().await {
+        log::warn!("Не удалось закрыть сессию WebDriver входа {}: {}", session.phone, e);
+    }
+}
+
+// Сохраняет сессию до следующего сообщения пользователя или закрывает браузер, если вход закончен
+async fn keep_or_close(
+    storage: &dyn Storage,
+    member: Member,
+    session: LoginSession,
+    step: Result<LoginStep, PortalError>,
+) -> Result<LoginStep, PortalError> {
+    match &step {
+        Ok(LoginStep::LoggedIn) => {
+            let phone = session.phone.clone();
+            close(session).await;
+            storage
+                .save_browser_profile(member.chat, &phone)
+                .await
+                .map_err(|e| PortalError::Rejected(format!("не удалось сохранить профиль: {}", e)))?;
+        }
+        // Таймер мог сработать, пока шаг выполнялся и сессии не было в списке
+        Ok(_) if session.started.elapsed() >= LOGIN_TIMEOUT => {
+            close(session).await;
+            return Err(PortalError::LoginNotStarted);
+        }
+        Ok(_) => {
+            LOGINS.lock().await.insert(key(member), session);
+        }
+        Err(_) => close(session).await,
+    }
+    step
+}
+
+// Закрывает незавершенный вход участника, если он есть
+pub async fn cancel_login(member: Member) {
+    let session = LOGINS.lock().await.remove(&key(member));
+    if let Some(session) = session {
+        close(session).await;
+    }
+}
+
+pub async fn start_login(storage: &dyn Storage, member: Member, phone: String) -> Result<LoginStep, PortalError> {
+    cancel_login(member).await;
+    let profile = lock_profile(member.chat, &phone).await;
+    let driver = start_browser(&PORTAL_CONFIG, member.chat, &phone).await?;
+    let session = LoginSession {
+        id: NEXT_LOGIN_ID.fetch_add(1, Ordering::Relaxed),
+        started: Instant::now(),
+        phone,
+        driver,
+        _profile: profile,
+    };
+
+    // Брошенный вход не должен держать Chrome и профиль бесконечно
+    let login_id = session.id;
+    tokio::spawn(async move {
+        sleep(LOGIN_TIMEOUT).await;
+        let mut logins = LOGINS.lock().await;
+        if logins.get(&key(member)).is_some_and(|s| s.id == login_id) {
+            let session = logins.remove(&key(member));
+            drop(logins);
+            if let Some(session) = session {
+                close(session).await;
+            }
+        }
+    });
+
+    let step = submit_phone(&session.driver, &PORTAL_CONFIG, &session.phone).await;
+    keep_or_close(storage, member, session, step).await
+}
+
+async fn take_session(member: Member) -> Result<LoginSession, PortalError> {
+    LOGINS.lock().await.remove(&key(member)).ok_or(PortalError::LoginNotStarted)
+}
+
+pub async fn enter_captcha(storage: &dyn Storage, member: Member, text: &str) -> Result<LoginStep, PortalError> {
+    let session = take_session(member).await?;
+    let step = submit_captcha(&session.driver, &PORTAL_CONFIG, text).await;
+    keep_or_close(storage, member, session, step).await
+}
+
+pub async fn enter_sms_code(storage: &dyn Storage, member: Member, code: &str) -> Result<LoginStep, PortalError> {
+    let session = take_session(member).await?;
+    let step = submit_code(&session.driver, &PORTAL_CONFIG, code).await;
+    keep_or_close(storage, member, session, step).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webdriver_mock::{MockWebDriver, MOCK_LOGIN, SCREENSHOT};
+    use teloxide::types::ChatId;
+
+    fn config(webdriver: &MockWebDriver) -> PortalConfig {
+        PortalConfig {
+            webdriver_url: webdriver.url(),
+            portal_url: "http://portal.test".to_string(),
+            profiles_dir: "/tmp/profiles".into(),
+            step_timeout: Duration::from_millis(300),
+            result_timeout: Duration::from_millis(300),
+        }
+    }
+
+    #[test]
+    fn normalizes_russian_mobile_numbers() {
+        for input in ["+7 (999) 123-45-67", "89991234567", "79991234567", "999 123 45 67"] {
+            assert_eq!(normalize_phone(input).as_deref(), Some("9991234567"), "{}", input);
+        }
+        assert_eq!(normalize_phone("+7 (495) 123-45-67"), None); // городской
+        assert_eq!(normalize_phone("+1 999 123 45 67 8"), None);
+        assert_eq!(normalize_phone("999-12"), None);
+        assert_eq!(normalize_phone("тел. 89991234567"), None);
+        assert_eq!(format_phone("9991234567"), "+79991234567");
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_captcha_and_sms_code() {
+        let webdriver = MockWebDriver::start(MOCK_LOGIN).await;
+        let config = config(&webdriver);
+        let driver = start_browser(&config, ChatId(42), "9991234567").await.unwrap();
+
+        let step = submit_phone(&driver, &config, "9991234567").await.unwrap();
+        assert!(matches!(step, LoginStep::Captcha(ref image) if image == SCREENSHOT));
+        assert!(matches!(submit_captcha(&driver, &config, "x7kq").await.unwrap(), LoginStep::SmsCode));
+        assert!(matches!(submit_code(&driver, &config, "123456").await.unwrap(), LoginStep::LoggedIn));
+        driver.quit().await.unwrap();
+
+        assert_eq!(webdriver.visited(), vec!["http://portal.test/login"]);
+        assert_eq!(
+            webdriver.typed(),
+            vec![
+                ("phone-input".to_string(), "+79991234567".to_string()),
+                ("captcha-input".to_string(), "x7kq".to_string()),
+                ("code-input".to_string(), "123456".to_string()),
+            ]
+        );
+        assert!(webdriver.chrome_args().contains(&"--user-data-dir=/tmp/profiles/chat_42/9991234567".to_string()));
+    }
+
+    #[tokio::test]
+    async fn goes_straight_to_sms_code_without_captcha() {
+        let page = MOCK_LOGIN
+            .replace(r#"data-testid="captcha-image""#, r#"data-testid="no-captcha""#)
+            .replace(r#"data-after="captcha-submit""#, r#"data-after="phone-submit""#);
+        let webdriver = MockWebDriver::start(&page).await;
+        let config = config(&webdriver);
+        let driver = start_browser(&config, ChatId(42), "9991234567").await.unwrap();
+
+        assert!(matches!(submit_phone(&driver, &config, "9991234567").await.unwrap(), LoginStep::SmsCode));
+        driver.quit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_sms_code_can_be_retried() {
+        let page = MOCK_LOGIN.replace(
+            r#"<div data-testid="seller-profile" data-after="code-submit" hidden>ИП Иванов</div>"#,
+            r#"<div data-testid="login-error" data-after="code-submit" hidden>Неверный код</div>"#,
+        );
+        let webdriver = MockWebDriver::start(&page).await;
+        let config = config(&webdriver);
+        let driver = start_browser(&config, ChatId(42), "9991234567").await.unwrap();
+
+        submit_phone(&driver, &config, "9991234567").await.unwrap();
+        submit_captcha(&driver, &config, "x7kq").await.unwrap();
+        assert!(matches!(submit_code(&driver, &config, "000000").await.unwrap(), LoginStep::WrongCode));
+        driver.quit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn authorized_profile_skips_login() {
+        let webdriver = MockWebDriver::start(r#"<div data-testid="seller-profile">ИП Иванов</div>"#).await;
+        let config = config(&webdriver);
+        let driver = start_browser(&config, ChatId(42), "9991234567").await.unwrap();
+
+        assert!(matches!(submit_phone(&driver, &config, "9991234567").await.unwrap(), LoginStep::LoggedIn));
+        assert!(webdriver.typed().is_empty());
+        driver.quit().await.unwrap();
+    }
+}
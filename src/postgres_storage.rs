@@ -0,0 +1,1540 @@
+// Хранилище в PostgreSQL: общая база для нескольких экземпляров бота (фича postgres, DATABASE_URL)
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Config, Pool, Runtime};
+use std::error::Error;
+use teloxide::types::{ChatId, UserId};
+use tokio_postgres::{NoTls, Row};
+
+use crate::api_reauests::{CoefficientResponse, Warehouse};
+use crate::database::{
+    decode_broadcast_buttons, encode_broadcast_buttons, ApiCallStats, BrowserProfile, Broadcast, BroadcastStats, CachedSlot,
+    Cabinet, CheapestSearch, CoefficientChange, Member, Preset, ProfileHealth, SlotLimits, Subscription,
+};
+use crate::storage::{
+    broadcast_stats, decrypt_cabinet, decrypt_cabinets_skipping_broken, name_preset_warehouses, Storage, StoredCabinet,
+};
+use crate::token_crypto::token_cipher;
+
+// Миграции схемы по порядку, как и для SQLite: уже примененные не меняем, новые добавляем в конец
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE users (
+        id BIGINT PRIMARY KEY,
+        username TEXT NOT NULL,
+        active BOOLEAN NOT NULL DEFAULT TRUE
+    );
+    CREATE TABLE user_states (
+        chat_id BIGINT NOT NULL,
+        user_id BIGINT NOT NULL,
+        state SMALLINT NOT NULL,
+        PRIMARY KEY (chat_id, user_id)
+    );
+    CREATE TABLE cabinets (
+        id BIGSERIAL PRIMARY KEY,
+        chat_id BIGINT NOT NULL,
+        name TEXT NOT NULL,
+        token TEXT NOT NULL,
+        UNIQUE (chat_id, name)
+    );
+    CREATE TABLE active_cabinets (
+        chat_id BIGINT PRIMARY KEY,
+        cabinet_id BIGINT NOT NULL
+    );
+    CREATE TABLE token_reminders (
+        chat_id BIGINT NOT NULL,
+        exp BIGINT NOT NULL,
+        kind TEXT NOT NULL,
+        PRIMARY KEY (chat_id, exp, kind)
+    );
+    CREATE TABLE warehouses (
+        id BIGINT PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE favourite_warehouses (
+        user_id BIGINT NOT NULL,
+        warehouse_id BIGINT NOT NULL,
+        PRIMARY KEY (user_id, warehouse_id)
+    );
+    CREATE TABLE warehouses_coefficients (
+        id BIGSERIAL PRIMARY KEY,
+        date BIGINT NOT NULL,
+        coefficient INTEGER NOT NULL,
+        warehouse_id BIGINT NOT NULL,
+        warehouse_name TEXT NOT NULL,
+        box_type_name TEXT NOT NULL,
+        box_type_id BIGINT,
+        UNIQUE (date, warehouse_id, box_type_name)
+    );
+    CREATE INDEX warehouses_coefficients_box_type ON warehouses_coefficients (box_type_name);
+    CREATE TABLE coefficient_history (
+        id BIGSERIAL PRIMARY KEY,
+        date BIGINT NOT NULL,
+        warehouse_id BIGINT NOT NULL,
+        warehouse_name TEXT NOT NULL,
+        box_type_name TEXT NOT NULL,
+        coefficient INTEGER NOT NULL,
+        observed_at BIGINT NOT NULL
+    );
+    CREATE INDEX coefficient_history_slot ON coefficient_history (warehouse_id, box_type_name, date, observed_at);
+    CREATE INDEX coefficient_history_observed_at ON coefficient_history (observed_at);",
+    // Диалоги вместо номера состояния: состояние вместе с данными шага в JSON (dialogue::DialogueState).
+    // Черновики жили в SQLite, поэтому переносятся только состояния без данных
+    "CREATE TABLE dialogues (
+        chat_id BIGINT NOT NULL,
+        user_id BIGINT NOT NULL,
+        dialogue TEXT NOT NULL,
+        PRIMARY KEY (chat_id, user_id)
+    );
+    INSERT INTO dialogues (chat_id, user_id, dialogue)
+    SELECT chat_id, user_id, CASE state
+        WHEN 1 THEN '{\"AwaitingToken\":{\"cabinet\":null}}'
+        WHEN 3 THEN '\"AwaitingNumber\"'
+        WHEN 9 THEN '\"AwaitingSearchParams\"'
+        WHEN 10 THEN '\"AwaitingWarehouseSearch\"'
+    END
+    FROM user_states WHERE state IN (1, 3, 9, 10);
+    DROP TABLE user_states;",
+    // Остальные данные, которые раньше жили только в SQLite: с общей базой подписки, роли, рассылки
+    // и длинные callback данные видны всем экземплярам
+    "CREATE TABLE admins (
+        user_id BIGINT PRIMARY KEY,
+        role TEXT NOT NULL,
+        granted_by BIGINT NOT NULL,
+        granted_at BIGINT NOT NULL
+    );
+    CREATE TABLE browser_profiles (
+        chat_id BIGINT NOT NULL,
+        phone_number TEXT NOT NULL,
+        last_login_at BIGINT,
+        health TEXT NOT NULL DEFAULT 'unknown',
+        checked_at BIGINT,
+        PRIMARY KEY (chat_id, phone_number)
+    );
+    CREATE TABLE subscriptions (
+        id BIGSERIAL PRIMARY KEY,
+        user_id BIGINT NOT NULL,
+        warehouse_id BIGINT NOT NULL,
+        warehouse_name TEXT NOT NULL,
+        box_type_name TEXT NOT NULL,
+        max_coefficient INTEGER NOT NULL,
+        date_from BIGINT,
+        date_to BIGINT,
+        auto_book_phone TEXT
+    );
+    CREATE INDEX subscriptions_user ON subscriptions (user_id);
+    CREATE TABLE subscription_notifications (
+        subscription_id BIGINT NOT NULL,
+        date BIGINT NOT NULL,
+        PRIMARY KEY (subscription_id, date)
+    );
+    CREATE TABLE presets (
+        id BIGSERIAL PRIMARY KEY,
+        user_id BIGINT NOT NULL,
+        name TEXT NOT NULL,
+        box_type_name TEXT NOT NULL,
+        max_coefficient INTEGER NOT NULL,
+        date_from BIGINT,
+        date_to BIGINT
+    );
+    CREATE INDEX presets_user ON presets (user_id);
+    CREATE TABLE preset_warehouses (
+        preset_id BIGINT NOT NULL,
+        warehouse_id BIGINT NOT NULL,
+        PRIMARY KEY (preset_id, warehouse_id)
+    );
+    CREATE TABLE cheapest_searches (
+        chat_id BIGINT NOT NULL,
+        user_id BIGINT NOT NULL,
+        box_type_name TEXT NOT NULL,
+        warehouse_ids BIGINT[],
+        name_filter TEXT[] NOT NULL DEFAULT '{}',
+        max_coefficient INTEGER,
+        date_from BIGINT,
+        date_to BIGINT,
+        PRIMARY KEY (chat_id, user_id)
+    );
+    CREATE TABLE callback_payloads (
+        id BIGSERIAL PRIMARY KEY,
+        payload TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE broadcasts (
+        id BIGSERIAL PRIMARY KEY,
+        admin_chat_id BIGINT NOT NULL,
+        text TEXT NOT NULL,
+        photo TEXT,
+        buttons TEXT NOT NULL,
+        status TEXT NOT NULL,
+        progress_message_id INTEGER,
+        created_at BIGINT NOT NULL
+    );
+    CREATE TABLE broadcast_recipients (
+        broadcast_id BIGINT NOT NULL,
+        chat_id BIGINT NOT NULL,
+        status TEXT NOT NULL,
+        PRIMARY KEY (broadcast_id, chat_id)
+    );
+    CREATE TABLE usage_events (
+        id BIGSERIAL PRIMARY KEY,
+        kind TEXT NOT NULL,
+        chat_id BIGINT NOT NULL,
+        user_id BIGINT,
+        created_at BIGINT NOT NULL
+    );
+    CREATE INDEX usage_events_kind_created ON usage_events (kind, created_at);
+    CREATE TABLE api_calls (
+        id BIGSERIAL PRIMARY KEY,
+        endpoint TEXT NOT NULL,
+        status INTEGER,
+        ok BOOLEAN NOT NULL,
+        created_at BIGINT NOT NULL
+    );
+    CREATE INDEX api_calls_created ON api_calls (created_at);",
+];
+
+// Ключ блокировки на время миграций, чтобы экземпляры, запущенные одновременно, не применяли их дважды
+const MIGRATIONS_LOCK: i64 = 0x77625f626f74;
+
+pub struct PostgresStorage {
+    pool: Pool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str) -> Result<PostgresStorage, Box<dyn Error + Send + Sync>> {
+        let mut config = Config::new();
+        config.url = Some(url.to_string());
+        let pool = config.create_pool(Some(Runtime::Tokio1), NoTls)?;
+        let storage = PostgresStorage { pool };
+        storage.migrate().await?;
+        Ok(storage)
+    }
+
+    async fn migrate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATIONS_LOCK]).await?;
+        tx.batch_execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)").await?;
+        let version: i32 = tx
+            .query_opt("SELECT version FROM schema_version", &[])
+            .await?
+            .map(|row| row.get(0))
+            .unwrap_or(0);
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            tx.batch_execute(migration).await?;
+            log::info!("Применена миграция PostgreSQL {}", i + 1);
+        }
+        tx.execute("DELETE FROM schema_version", &[]).await?;
+        tx.execute("INSERT INTO schema_version (version) VALUES ($1)", &[&(MIGRATIONS.len() as i32)])
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+fn cabinet_from_row(row: &Row) -> Result<StoredCabinet, tokio_postgres::Error> {
+    Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?, row.try_get(3)?))
+}
+
+// Id складов от WB - u32, в базе они хранятся как BIGINT
+fn warehouse_id_from_row(row: &Row, idx: usize) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    let id: i64 = row.try_get(idx)?;
+    Ok(u32::try_from(id)?)
+}
+
+fn warehouse_from_row(row: &Row) -> Result<Warehouse, Box<dyn Error + Send + Sync>> {
+    Ok(Warehouse {
+        id: warehouse_id_from_row(row, 0)?,
+        name: row.try_get(1)?,
+    })
+}
+
+fn date_and_coefficient(row: &Row) -> Result<(i64, i32), tokio_postgres::Error> {
+    Ok((row.try_get(0)?, row.try_get(1)?))
+}
+
+fn id_and_name(row: &Row) -> Result<(i64, String), tokio_postgres::Error> {
+    Ok((row.try_get(0)?, row.try_get(1)?))
+}
+
+const BROWSER_PROFILE_COLUMNS: &str = "chat_id, phone_number, last_login_at, health, checked_at";
+
+fn browser_profile_from_row(row: &Row) -> Result<BrowserProfile, tokio_postgres::Error> {
+    Ok(BrowserProfile {
+        chat_id: row.try_get(0)?,
+        phone: row.try_get(1)?,
+        last_login_at: row.try_get(2)?,
+        health: ProfileHealth::parse(row.try_get(3)?),
+        checked_at: row.try_get(4)?,
+    })
+}
+
+const SUBSCRIPTION_COLUMNS: &str =
+    "id, user_id, warehouse_id, warehouse_name, box_type_name, max_coefficient, date_from, date_to, auto_book_phone";
+
+fn subscription_from_row(row: &Row) -> Result<Subscription, Box<dyn Error + Send + Sync>> {
+    let warehouse_id: i64 = row.try_get(2)?;
+    Ok(Subscription {
+        id: row.try_get(0)?,
+        user_id: row.try_get(1)?,
+        warehouse_id: i32::try_from(warehouse_id)?,
+        warehouse_name: row.try_get(3)?,
+        box_type_name: row.try_get(4)?,
+        max_coefficient: row.try_get(5)?,
+        date_from: row.try_get(6)?,
+        date_to: row.try_get(7)?,
+        auto_book_phone: row.try_get(8)?,
+    })
+}
+
+const BROADCAST_COLUMNS: &str = "id, admin_chat_id, text, photo, buttons, status, progress_message_id";
+
+fn broadcast_from_row(row: &Row) -> Result<Broadcast, tokio_postgres::Error> {
+    Ok(Broadcast {
+        id: row.try_get(0)?,
+        admin_chat_id: row.try_get(1)?,
+        text: row.try_get(2)?,
+        photo: row.try_get(3)?,
+        buttons: decode_broadcast_buttons(row.try_get(4)?),
+        status: row.try_get(5)?,
+        progress_message_id: row.try_get(6)?,
+    })
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn add_user(&self, id: ChatId, username: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        // Повторный /start возвращает в рассылки пользователя, который раньше блокировал бота
+        client
+            .execute(
+                "INSERT INTO users (id, username) VALUES ($1, $2) ON CONFLICT (id) DO UPDATE SET active = TRUE",
+                &[&id.0, &username],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_user_active(&self, id: ChatId, active: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client.execute("UPDATE users SET active = $1 WHERE id = $2", &[&active, &id.0]).await?;
+        Ok(())
+    }
+
+    async fn get_active_users(&self, exclude: ChatId) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query("SELECT id FROM users WHERE active AND id != $1 ORDER BY id", &[&exclude.0])
+            .await?;
+        let users = rows.iter().map(|row| row.try_get(0)).collect::<Result<Vec<i64>, _>>()?;
+        Ok(users)
+    }
+
+    async fn count_users(&self) -> Result<(i64, i64), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_one("SELECT COUNT(*), COUNT(*) FILTER (WHERE active) FROM users", &[])
+            .await?;
+        Ok((row.try_get(0)?, row.try_get(1)?))
+    }
+
+    async fn get_id_by_username(&self, username: String) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client.query_one("SELECT id FROM users WHERE username = $1", &[&username]).await?;
+        Ok(row.try_get(0)?)
+    }
+
+    async fn get_dialogue(&self, member: Member) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT dialogue FROM dialogues WHERE chat_id = $1 AND user_id = $2",
+                &[&member.chat.0, &(member.user.0 as i64)],
+            )
+            .await?;
+        Ok(row.map(|row| row.try_get(0)).transpose()?)
+    }
+
+    async fn update_dialogue(&self, member: Member, dialogue: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO dialogues (chat_id, user_id, dialogue) VALUES ($1, $2, $3)
+                 ON CONFLICT (chat_id, user_id) DO UPDATE SET dialogue = excluded.dialogue",
+                &[&member.chat.0, &(member.user.0 as i64), &dialogue],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_dialogue(&self, member: Member) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "DELETE FROM dialogues WHERE chat_id = $1 AND user_id = $2",
+                &[&member.chat.0, &(member.user.0 as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn create_cabinet(&self, id: ChatId, name: String, token: String) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let encrypted = token_cipher()?.encrypt(&token)?;
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let row = tx
+            .query_one(
+                "INSERT INTO cabinets (chat_id, name, token) VALUES ($1, $2, $3) RETURNING id",
+                &[&id.0, &name, &encrypted],
+            )
+            .await?;
+        let cabinet_id: i64 = row.try_get(0)?;
+        tx.execute(
+            "INSERT INTO active_cabinets (chat_id, cabinet_id) VALUES ($1, $2)
+             ON CONFLICT (chat_id) DO UPDATE SET cabinet_id = excluded.cabinet_id",
+            &[&id.0, &cabinet_id],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(cabinet_id)
+    }
+
+    async fn rename_cabinet(&self, id: ChatId, cabinet_id: i64, name: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "UPDATE cabinets SET name = $1 WHERE id = $2 AND chat_id = $3",
+                &[&name, &cabinet_id, &id.0],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn update_cabinet_token(&self, id: ChatId, cabinet_id: i64, token: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let encrypted = token_cipher()?.encrypt(&token)?;
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "UPDATE cabinets SET token = $1 WHERE id = $2 AND chat_id = $3",
+                &[&encrypted, &cabinet_id, &id.0],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<Option<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, chat_id, name, token FROM cabinets WHERE id = $1 AND chat_id = $2",
+                &[&cabinet_id, &id.0],
+            )
+            .await?;
+        row.as_ref().map(cabinet_from_row).transpose()?.map(decrypt_cabinet).transpose()
+    }
+
+    async fn get_user_cabinets(&self, id: ChatId) -> Result<Vec<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query("SELECT id, chat_id, name, token FROM cabinets WHERE chat_id = $1 ORDER BY id", &[&id.0])
+            .await?;
+        let rows = rows.iter().map(cabinet_from_row).collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter().map(decrypt_cabinet).collect()
+    }
+
+    async fn get_all_cabinets(&self) -> Result<Vec<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client.query("SELECT id, chat_id, name, token FROM cabinets", &[]).await?;
+        let rows = rows.iter().map(cabinet_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(decrypt_cabinets_skipping_broken(rows))
+    }
+
+    async fn get_active_cabinet(&self, id: ChatId) -> Result<Option<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT c.id, c.chat_id, c.name, c.token FROM cabinets c
+                 LEFT JOIN active_cabinets a ON a.chat_id = c.chat_id AND a.cabinet_id = c.id
+                 WHERE c.chat_id = $1
+                 ORDER BY a.cabinet_id IS NULL, c.id
+                 LIMIT 1",
+                &[&id.0],
+            )
+            .await?;
+        row.as_ref().map(cabinet_from_row).transpose()?.map(decrypt_cabinet).transpose()
+    }
+
+    async fn set_active_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO active_cabinets (chat_id, cabinet_id)
+                 SELECT chat_id, id FROM cabinets WHERE id = $1 AND chat_id = $2
+                 ON CONFLICT (chat_id) DO UPDATE SET cabinet_id = excluded.cabinet_id",
+                &[&cabinet_id, &id.0],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.execute("DELETE FROM cabinets WHERE id = $1 AND chat_id = $2", &[&cabinet_id, &id.0])
+            .await?;
+        tx.execute(
+            "DELETE FROM active_cabinets WHERE chat_id = $1 AND cabinet_id = $2",
+            &[&id.0, &cabinet_id],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn reencrypt_user_tokens(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let cipher = token_cipher()?;
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+
+        // FOR UPDATE: другой экземпляр, запущенный одновременно, дождется конца перешифровки
+        let rows = tx.query("SELECT id, token FROM cabinets FOR UPDATE", &[]).await?;
+        let mut updated = 0;
+        for row in rows {
+            let cabinet_id: i64 = row.try_get(0)?;
+            let stored: String = row.try_get(1)?;
+            if !cipher.needs_reencryption(&stored) {
+                continue;
+            }
+            let token = cipher.decrypt(&stored)?;
+            tx.execute(
+                "UPDATE cabinets SET token = $1 WHERE id = $2",
+                &[&cipher.encrypt(&token)?, &cabinet_id],
+            )
+            .await?;
+            updated += 1;
+        }
+        tx.commit().await?;
+
+        Ok(updated)
+    }
+
+    async fn mark_token_reminder_sent(&self, chat_id: i64, exp: i64, kind: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let inserted = client
+            .execute(
+                "INSERT INTO token_reminders (chat_id, exp, kind) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[&chat_id, &exp, &kind],
+            )
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    async fn unmark_token_reminder_sent(&self, chat_id: i64, exp: i64, kind: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "DELETE FROM token_reminders WHERE chat_id = $1 AND exp = $2 AND kind = $3",
+                &[&chat_id, &exp, &kind],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_warehouses(&self, warehouses: Vec<Warehouse>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let stmt = tx
+            .prepare(
+                "INSERT INTO warehouses (id, name) VALUES ($1, $2)
+                 ON CONFLICT (id) DO UPDATE SET name = excluded.name",
+            )
+            .await?;
+        for warehouse in warehouses {
+            tx.execute(&stmt, &[&i64::from(warehouse.id), &warehouse.name]).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_warehouses_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<(Warehouse, bool)>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let offset = i64::from(page) * i64::from(page_size);
+        let rows = client
+            .query(
+                "SELECT w.id, w.name, f.warehouse_id IS NOT NULL FROM warehouses w
+                 LEFT JOIN favourite_warehouses f ON f.warehouse_id = w.id AND f.user_id = $1
+                 ORDER BY f.warehouse_id IS NULL, w.name LIMIT $2 OFFSET $3",
+                &[&id.0, &i64::from(page_size), &offset],
+            )
+            .await?;
+        rows.iter().map(|row| Ok((warehouse_from_row(row)?, row.try_get(2)?))).collect()
+    }
+
+    async fn get_all_warehouses(&self) -> Result<Vec<Warehouse>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client.query("SELECT id, name FROM warehouses ORDER BY name", &[]).await?;
+        rows.iter().map(warehouse_from_row).collect()
+    }
+
+    async fn count_warehouses(&self) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let count: i64 = client.query_one("SELECT COUNT(*) FROM warehouses", &[]).await?.try_get(0)?;
+        Ok(i32::try_from(count)?)
+    }
+
+    async fn get_warehouse_name(&self, warehouse_id: i32) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt("SELECT name FROM warehouses WHERE id = $1", &[&i64::from(warehouse_id)])
+            .await?;
+        Ok(row.map(|row| row.try_get(0)).transpose()?)
+    }
+
+    async fn toggle_favourite_warehouse(&self, id: ChatId, warehouse_id: i32) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let removed = client
+            .execute(
+                "DELETE FROM favourite_warehouses WHERE user_id = $1 AND warehouse_id = $2",
+                &[&id.0, &i64::from(warehouse_id)],
+            )
+            .await?;
+        if removed > 0 {
+            return Ok(false);
+        }
+        client
+            .execute(
+                "INSERT INTO favourite_warehouses (user_id, warehouse_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                &[&id.0, &i64::from(warehouse_id)],
+            )
+            .await?;
+        Ok(true)
+    }
+
+    async fn is_favourite_warehouse(&self, id: ChatId, warehouse_id: i32) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_one(
+                "SELECT EXISTS (SELECT 1 FROM favourite_warehouses WHERE user_id = $1 AND warehouse_id = $2)",
+                &[&id.0, &i64::from(warehouse_id)],
+            )
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    async fn get_favourite_warehouses(&self, id: ChatId) -> Result<Vec<Warehouse>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT w.id, w.name FROM favourite_warehouses f
+                 JOIN warehouses w ON w.id = f.warehouse_id
+                 WHERE f.user_id = $1 ORDER BY w.name",
+                &[&id.0],
+            )
+            .await?;
+        rows.iter().map(warehouse_from_row).collect()
+    }
+
+    async fn add_or_update_warehouse_coefficents(&self, coefficients: Vec<CoefficientResponse>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let observed_at = Utc::now().timestamp();
+
+        let upsert = tx
+            .prepare(
+                "INSERT INTO warehouses_coefficients (date, coefficient, warehouse_id, warehouse_name, box_type_name, box_type_id)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (date, warehouse_id, box_type_name) DO UPDATE SET coefficient = excluded.coefficient",
+            )
+            .await?;
+        // В историю пишем только изменения: коэффициенты опрашиваются каждые несколько минут
+        // и одинаковые наблюдения подряд ничего не добавляют
+        let record_change = tx
+            .prepare(
+                "INSERT INTO coefficient_history (date, warehouse_id, warehouse_name, box_type_name, coefficient, observed_at)
+                 SELECT $1::BIGINT, $2::BIGINT, $3::TEXT, $4::TEXT, $5::INTEGER, $6::BIGINT
+                 WHERE (SELECT coefficient FROM coefficient_history
+                        WHERE date = $1 AND warehouse_id = $2 AND box_type_name = $4
+                        ORDER BY observed_at DESC, id DESC LIMIT 1) IS DISTINCT FROM $5",
+            )
+            .await?;
+
+        for coefficient in coefficients {
+            let datetime: DateTime<Utc> = coefficient.date.parse()?;
+            let unix_time = datetime.timestamp();
+            let warehouse_id = i64::from(coefficient.warehouse_id);
+            let box_type_id = coefficient.box_type_id.map(i64::from);
+            tx.execute(
+                &upsert,
+                &[&unix_time, &coefficient.coefficient, &warehouse_id, &coefficient.warehouse_name, &coefficient.box_type_name, &box_type_id],
+            )
+            .await?;
+            tx.execute(
+                &record_change,
+                &[&unix_time, &warehouse_id, &coefficient.warehouse_name, &coefficient.box_type_name, &coefficient.coefficient, &observed_at],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_coefficient_history(
+        &self,
+        warehouse_id: i32,
+        box_type_name: &str,
+        date_from: i64,
+    ) -> Result<Vec<CoefficientChange>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT date, coefficient, observed_at FROM coefficient_history
+                 WHERE warehouse_id = $1 AND box_type_name = $2 AND date >= $3
+                 ORDER BY date, observed_at, id",
+                &[&i64::from(warehouse_id), &box_type_name, &date_from],
+            )
+            .await?;
+        let changes = rows
+            .iter()
+            .map(|row| {
+                Ok(CoefficientChange {
+                    date: row.try_get(0)?,
+                    coefficient: row.try_get(1)?,
+                    observed_at: row.try_get(2)?,
+                })
+            })
+            .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+        Ok(changes)
+    }
+
+    async fn get_warehouse_coefficients(
+        &self,
+        warehouse_id: i32,
+        box_type_name: &str,
+        date_from: i64,
+        date_to: i64,
+    ) -> Result<Vec<(i64, i32)>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT date, coefficient FROM warehouses_coefficients
+                 WHERE warehouse_id = $1 AND box_type_name = $2 AND date >= $3 AND date < $4
+                 ORDER BY date",
+                &[&i64::from(warehouse_id), &box_type_name, &date_from, &date_to],
+            )
+            .await?;
+        Ok(rows.iter().map(date_and_coefficient).collect::<Result<Vec<_>, _>>()?)
+    }
+
+    async fn get_available_coefficients(&self, warehouse_id: i32, box_type_name: &str) -> Result<Vec<(i64, i32)>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT date, coefficient FROM warehouses_coefficients
+                 WHERE warehouse_id = $1 AND box_type_name = $2 AND coefficient != -1
+                 ORDER BY coefficient, date",
+                &[&i64::from(warehouse_id), &box_type_name],
+            )
+            .await?;
+        Ok(rows.iter().map(date_and_coefficient).collect::<Result<Vec<_>, _>>()?)
+    }
+
+    async fn get_unique_box_types(&self, warehouse_id: i32) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT box_type_name FROM (
+                     SELECT DISTINCT box_type_name FROM warehouses_coefficients WHERE warehouse_id = $1
+                 ) box_types ORDER BY LOWER(box_type_name)",
+                &[&i64::from(warehouse_id)],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.try_get(0)).collect::<Result<Vec<String>, _>>()?)
+    }
+
+    async fn get_box_type_slots(&self, box_type_name: &str) -> Result<Vec<CachedSlot>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT warehouse_id, warehouse_name, date, coefficient FROM warehouses_coefficients WHERE box_type_name = $1",
+                &[&box_type_name],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(CachedSlot {
+                    warehouse_id: warehouse_id_from_row(row, 0)?,
+                    warehouse_name: row.try_get(1)?,
+                    date: row.try_get(2)?,
+                    coefficient: row.try_get(3)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn get_all_box_types(&self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query("SELECT DISTINCT box_type_name FROM warehouses_coefficients ORDER BY box_type_name", &[])
+            .await?;
+        Ok(rows.iter().map(|row| row.try_get(0)).collect::<Result<Vec<String>, _>>()?)
+    }
+
+    async fn delete_expired_coefficients(&self, now: i64, history_border: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client.execute("DELETE FROM warehouses_coefficients WHERE date < $1", &[&now]).await?;
+        client
+            .execute("DELETE FROM coefficient_history WHERE observed_at < $1", &[&history_border])
+            .await?;
+        Ok(())
+    }
+
+    async fn set_admin_role(&self, id: UserId, role: &str, granted_by: UserId) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO admins (user_id, role, granted_by, granted_at) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (user_id) DO UPDATE SET role = excluded.role, granted_by = excluded.granted_by, granted_at = excluded.granted_at",
+                &[&(id.0 as i64), &role, &(granted_by.0 as i64), &Utc::now().timestamp()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_admin_role(&self, id: UserId) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let deleted = client.execute("DELETE FROM admins WHERE user_id = $1", &[&(id.0 as i64)]).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn get_admin_role(&self, id: UserId) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt("SELECT role FROM admins WHERE user_id = $1", &[&(id.0 as i64)])
+            .await?;
+        Ok(row.map(|row| row.try_get(0)).transpose()?)
+    }
+
+    async fn get_admins(&self) -> Result<Vec<(i64, String, Option<String>)>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT a.user_id, a.role, u.username FROM admins a LEFT JOIN users u ON u.id = a.user_id ORDER BY a.role, a.user_id",
+                &[],
+            )
+            .await?;
+        let admins = rows
+            .iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?)))
+            .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+        Ok(admins)
+    }
+
+    async fn save_browser_profile(&self, id: ChatId, phone: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO browser_profiles (chat_id, phone_number, last_login_at, health, checked_at) VALUES ($1, $2, $3, 'active', $3)
+                 ON CONFLICT (chat_id, phone_number) DO UPDATE SET last_login_at = excluded.last_login_at, health = 'active', checked_at = excluded.checked_at",
+                &[&id.0, &phone, &Utc::now().timestamp()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_browser_profile(&self, id: ChatId, phone: &str) -> Result<Option<BrowserProfile>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM browser_profiles WHERE chat_id = $1 AND phone_number = $2", BROWSER_PROFILE_COLUMNS),
+                &[&id.0, &phone],
+            )
+            .await?;
+        Ok(row.as_ref().map(browser_profile_from_row).transpose()?)
+    }
+
+    async fn get_user_browser_profiles_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let offset = i64::from(page) * i64::from(page_size);
+        let rows = client
+            .query(
+                "SELECT phone_number FROM browser_profiles WHERE chat_id = $1 ORDER BY phone_number LIMIT $2 OFFSET $3",
+                &[&id.0, &i64::from(page_size), &offset],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.try_get(0)).collect::<Result<Vec<String>, _>>()?)
+    }
+
+    async fn count_user_numbers(&self, id: ChatId) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let count: i64 = client
+            .query_one("SELECT COUNT(*) FROM browser_profiles WHERE chat_id = $1", &[&id.0])
+            .await?
+            .try_get(0)?;
+        Ok(i32::try_from(count)?)
+    }
+
+    async fn get_all_browser_profiles(&self) -> Result<Vec<BrowserProfile>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                &format!("SELECT {} FROM browser_profiles ORDER BY chat_id, phone_number", BROWSER_PROFILE_COLUMNS),
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().map(browser_profile_from_row).collect::<Result<Vec<_>, _>>()?)
+    }
+
+    async fn set_profile_health(&self, id: ChatId, phone: &str, health: ProfileHealth) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        // Прежнее состояние берем из той же строки, которую обновляем
+        let row = client
+            .query_opt(
+                "UPDATE browser_profiles p SET health = $1, checked_at = $2
+                 FROM (SELECT health FROM browser_profiles WHERE chat_id = $3 AND phone_number = $4 FOR UPDATE) previous
+                 WHERE p.chat_id = $3 AND p.phone_number = $4
+                 RETURNING previous.health",
+                &[&health.as_str(), &Utc::now().timestamp(), &id.0, &phone],
+            )
+            .await?;
+        let previous: Option<String> = row.map(|row| row.try_get(0)).transpose()?;
+        Ok(previous.is_some_and(|previous| previous != health.as_str()))
+    }
+
+    async fn delete_browser_profile(&self, id: ChatId, phone: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let deleted = tx
+            .execute(
+                "DELETE FROM browser_profiles WHERE chat_id = $1 AND phone_number = $2",
+                &[&id.0, &phone],
+            )
+            .await?;
+        tx.execute(
+            "UPDATE subscriptions SET auto_book_phone = NULL WHERE user_id = $1 AND auto_book_phone = $2",
+            &[&id.0, &phone],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(deleted > 0)
+    }
+
+    async fn add_subscription(
+        &self,
+        id: ChatId,
+        warehouse_id: i32,
+        warehouse_name: String,
+        box_type_name: String,
+        limits: SlotLimits,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO subscriptions (user_id, warehouse_id, warehouse_name, box_type_name, max_coefficient, date_from, date_to)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[&id.0, &i64::from(warehouse_id), &warehouse_name, &box_type_name, &limits.max_coefficient, &limits.date_from, &limits.date_to],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_user_subscriptions(&self, id: ChatId) -> Result<Vec<Subscription>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                &format!("SELECT {} FROM subscriptions WHERE user_id = $1 ORDER BY warehouse_name, id", SUBSCRIPTION_COLUMNS),
+                &[&id.0],
+            )
+            .await?;
+        rows.iter().map(subscription_from_row).collect()
+    }
+
+    async fn get_all_subscriptions(&self) -> Result<Vec<Subscription>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM subscriptions WHERE user_id NOT IN (SELECT id FROM users WHERE NOT active) ORDER BY user_id, id",
+                    SUBSCRIPTION_COLUMNS
+                ),
+                &[],
+            )
+            .await?;
+        rows.iter().map(subscription_from_row).collect()
+    }
+
+    async fn count_subscriptions(&self) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        Ok(client.query_one("SELECT COUNT(*) FROM subscriptions", &[]).await?.try_get(0)?)
+    }
+
+    async fn delete_subscription(&self, id: ChatId, subscription_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let deleted = tx
+            .execute(
+                "DELETE FROM subscriptions WHERE id = $1 AND user_id = $2",
+                &[&subscription_id, &id.0],
+            )
+            .await?;
+        // Подписка чужая или уже удалена: ее отметки об уведомлениях не трогаем
+        if deleted == 0 {
+            return Ok(());
+        }
+        tx.execute(
+            "DELETE FROM subscription_notifications WHERE subscription_id = $1",
+            &[&subscription_id],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_subscription_auto_book(&self, id: ChatId, subscription_id: i64, phone: Option<&str>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "UPDATE subscriptions SET auto_book_phone = $1 WHERE id = $2 AND user_id = $3",
+                &[&phone, &subscription_id, &id.0],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_subscription_auto_book(&self, subscription_id: i64) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt("SELECT auto_book_phone FROM subscriptions WHERE id = $1", &[&subscription_id])
+            .await?;
+        let phone: Option<Option<String>> = row.map(|row| row.try_get(0)).transpose()?;
+        Ok(phone.flatten())
+    }
+
+    async fn mark_slot_notified(&self, subscription_id: i64, date: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let inserted = client
+            .execute(
+                "INSERT INTO subscription_notifications (subscription_id, date) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                &[&subscription_id, &date],
+            )
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    async fn unmark_slot_notified(&self, subscription_id: i64, date: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "DELETE FROM subscription_notifications WHERE subscription_id = $1 AND date = $2",
+                &[&subscription_id, &date],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn create_preset(
+        &self,
+        id: ChatId,
+        name: String,
+        warehouse_id: i32,
+        box_type_name: String,
+        limits: SlotLimits,
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let preset_id: i64 = tx
+            .query_one(
+                "INSERT INTO presets (user_id, name, box_type_name, max_coefficient, date_from, date_to)
+                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                &[&id.0, &name, &box_type_name, &limits.max_coefficient, &limits.date_from, &limits.date_to],
+            )
+            .await?
+            .try_get(0)?;
+        tx.execute(
+            "INSERT INTO preset_warehouses (preset_id, warehouse_id) VALUES ($1, $2)",
+            &[&preset_id, &i64::from(warehouse_id)],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(preset_id)
+    }
+
+    async fn update_preset(&self, id: ChatId, preset_id: i64, name: String, limits: SlotLimits) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "UPDATE presets SET name = $1, max_coefficient = $2, date_from = $3, date_to = $4 WHERE id = $5 AND user_id = $6",
+                &[&name, &limits.max_coefficient, &limits.date_from, &limits.date_to, &preset_id, &id.0],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn add_warehouse_to_preset(&self, id: ChatId, preset_id: i64, warehouse_id: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO preset_warehouses (preset_id, warehouse_id)
+                 SELECT id, $1 FROM presets WHERE id = $2 AND user_id = $3
+                 ON CONFLICT DO NOTHING",
+                &[&i64::from(warehouse_id), &preset_id, &id.0],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_warehouse_from_preset(&self, id: ChatId, preset_id: i64, warehouse_id: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "DELETE FROM preset_warehouses WHERE preset_id = $1 AND warehouse_id = $2
+                 AND EXISTS (SELECT 1 FROM presets WHERE id = $1 AND user_id = $3)",
+                &[&preset_id, &i64::from(warehouse_id), &id.0],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_preset(&self, id: ChatId, preset_id: i64) -> Result<Option<Preset>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let Some(row) = client
+            .query_opt(
+                "SELECT id, name, box_type_name, max_coefficient, date_from, date_to FROM presets WHERE id = $1 AND user_id = $2",
+                &[&preset_id, &id.0],
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+        let rows = client
+            .query(
+                "SELECT p.warehouse_id, w.name FROM preset_warehouses p
+                 LEFT JOIN warehouses w ON w.id = p.warehouse_id
+                 WHERE p.preset_id = $1",
+                &[&preset_id],
+            )
+            .await?;
+        let warehouses = rows
+            .iter()
+            .map(|row| Ok((warehouse_id_from_row(row, 0)?, row.try_get(1)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+        Ok(Some(Preset {
+            id: row.try_get(0)?,
+            name: row.try_get(1)?,
+            box_type_name: row.try_get(2)?,
+            max_coefficient: row.try_get(3)?,
+            date_from: row.try_get(4)?,
+            date_to: row.try_get(5)?,
+            warehouses: name_preset_warehouses(warehouses),
+        }))
+    }
+
+    async fn get_user_presets_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let offset = i64::from(page) * i64::from(page_size);
+        let rows = client
+            .query(
+                "SELECT id, name FROM presets WHERE user_id = $1 ORDER BY name, id LIMIT $2 OFFSET $3",
+                &[&id.0, &i64::from(page_size), &offset],
+            )
+            .await?;
+        Ok(rows.iter().map(id_and_name).collect::<Result<Vec<_>, _>>()?)
+    }
+
+    async fn count_user_presets(&self, id: ChatId) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let count: i64 = client
+            .query_one("SELECT COUNT(*) FROM presets WHERE user_id = $1", &[&id.0])
+            .await?
+            .try_get(0)?;
+        Ok(i32::try_from(count)?)
+    }
+
+    async fn get_user_presets_by_box_type(&self, id: ChatId, box_type_name: &str) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT id, name FROM presets WHERE user_id = $1 AND box_type_name = $2 ORDER BY name, id",
+                &[&id.0, &box_type_name],
+            )
+            .await?;
+        Ok(rows.iter().map(id_and_name).collect::<Result<Vec<_>, _>>()?)
+    }
+
+    async fn duplicate_preset(&self, id: ChatId, preset_id: i64) -> Result<Option<i64>, Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let Some(row) = tx
+            .query_opt(
+                "INSERT INTO presets (user_id, name, box_type_name, max_coefficient, date_from, date_to)
+                 SELECT user_id, name || ' (копия)', box_type_name, max_coefficient, date_from, date_to
+                 FROM presets WHERE id = $1 AND user_id = $2
+                 RETURNING id",
+                &[&preset_id, &id.0],
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+        let new_id: i64 = row.try_get(0)?;
+        tx.execute(
+            "INSERT INTO preset_warehouses (preset_id, warehouse_id)
+             SELECT $1, warehouse_id FROM preset_warehouses WHERE preset_id = $2",
+            &[&new_id, &preset_id],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(Some(new_id))
+    }
+
+    async fn delete_preset(&self, id: ChatId, preset_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let deleted = tx
+            .execute("DELETE FROM presets WHERE id = $1 AND user_id = $2", &[&preset_id, &id.0])
+            .await?;
+        // Пресет чужой или уже удален: его склады не трогаем
+        if deleted == 0 {
+            return Ok(());
+        }
+        tx.execute("DELETE FROM preset_warehouses WHERE preset_id = $1", &[&preset_id])
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn start_cheapest_search(&self, member: Member, box_type_name: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO cheapest_searches (chat_id, user_id, box_type_name) VALUES ($1, $2, $3)
+                 ON CONFLICT (chat_id, user_id) DO UPDATE SET box_type_name = excluded.box_type_name,
+                     warehouse_ids = NULL, name_filter = '{}', max_coefficient = NULL, date_from = NULL, date_to = NULL",
+                &[&member.chat.0, &(member.user.0 as i64), &box_type_name],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_cheapest_search_warehouses(&self, member: Member, warehouse_ids: Option<Vec<u32>>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let warehouse_ids: Option<Vec<i64>> = warehouse_ids.map(|ids| ids.into_iter().map(i64::from).collect());
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "UPDATE cheapest_searches SET warehouse_ids = $1 WHERE chat_id = $2 AND user_id = $3",
+                &[&warehouse_ids, &member.chat.0, &(member.user.0 as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_cheapest_search_params(
+        &self,
+        member: Member,
+        limits: SlotLimits,
+        name_filter: Vec<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "UPDATE cheapest_searches SET max_coefficient = $1, date_from = $2, date_to = $3, name_filter = $4
+                 WHERE chat_id = $5 AND user_id = $6",
+                &[&limits.max_coefficient, &limits.date_from, &limits.date_to, &name_filter, &member.chat.0, &(member.user.0 as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_cheapest_search(&self, member: Member) -> Result<Option<CheapestSearch>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let Some(row) = client
+            .query_opt(
+                "SELECT box_type_name, warehouse_ids, name_filter, max_coefficient, date_from, date_to
+                 FROM cheapest_searches WHERE chat_id = $1 AND user_id = $2",
+                &[&member.chat.0, &(member.user.0 as i64)],
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+        let warehouse_ids: Option<Vec<i64>> = row.try_get(1)?;
+        let warehouse_ids = warehouse_ids
+            .map(|ids| ids.into_iter().map(u32::try_from).collect::<Result<Vec<_>, _>>())
+            .transpose()?;
+        Ok(Some(CheapestSearch {
+            box_type_name: row.try_get(0)?,
+            warehouse_ids,
+            name_filter: row.try_get(2)?,
+            max_coefficient: row.try_get(3)?,
+            date_from: row.try_get(4)?,
+            date_to: row.try_get(5)?,
+        }))
+    }
+
+    async fn store_callback_payload(&self, payload: &str) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        // DO UPDATE вместо DO NOTHING, чтобы RETURNING вернул id и для уже сохраненных данных
+        let row = client
+            .query_one(
+                "INSERT INTO callback_payloads (payload) VALUES ($1)
+                 ON CONFLICT (payload) DO UPDATE SET payload = excluded.payload
+                 RETURNING id",
+                &[&payload],
+            )
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    async fn load_callback_payload(&self, id: i64) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt("SELECT payload FROM callback_payloads WHERE id = $1", &[&id])
+            .await?;
+        Ok(row.map(|row| row.try_get(0)).transpose()?)
+    }
+
+    async fn create_broadcast(
+        &self,
+        admin_chat_id: ChatId,
+        text: &str,
+        photo: Option<String>,
+        buttons: &[(String, String)],
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_one(
+                "INSERT INTO broadcasts (admin_chat_id, text, photo, buttons, status, created_at)
+                 VALUES ($1, $2, $3, $4, 'draft', $5) RETURNING id",
+                &[&admin_chat_id.0, &text, &photo, &encode_broadcast_buttons(buttons), &Utc::now().timestamp()],
+            )
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    async fn get_broadcast(&self, id: i64) -> Result<Option<Broadcast>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(&format!("SELECT {} FROM broadcasts WHERE id = $1", BROADCAST_COLUMNS), &[&id])
+            .await?;
+        Ok(row.as_ref().map(broadcast_from_row).transpose()?)
+    }
+
+    async fn get_sending_broadcast(&self) -> Result<Option<Broadcast>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM broadcasts WHERE status = 'sending' ORDER BY id LIMIT 1", BROADCAST_COLUMNS),
+                &[],
+            )
+            .await?;
+        Ok(row.as_ref().map(broadcast_from_row).transpose()?)
+    }
+
+    async fn start_broadcast(&self, id: i64, progress_message_id: i32, recipients: &[i64]) -> Result<Option<usize>, Box<dyn Error + Send + Sync>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let started = tx
+            .execute(
+                "UPDATE broadcasts SET status = 'sending', progress_message_id = $1 WHERE id = $2 AND status = 'draft'",
+                &[&progress_message_id, &id],
+            )
+            .await?;
+        if started == 0 {
+            return Ok(None);
+        }
+        tx.execute(
+            "INSERT INTO broadcast_recipients (broadcast_id, chat_id, status)
+             SELECT $1, chat_id, 'pending' FROM UNNEST($2::BIGINT[]) chat_id
+             ON CONFLICT DO NOTHING",
+            &[&id, &recipients],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(Some(recipients.len()))
+    }
+
+    async fn cancel_broadcast(&self, id: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let cancelled = client
+            .execute(
+                "UPDATE broadcasts SET status = 'cancelled' WHERE id = $1 AND status IN ('draft', 'sending')",
+                &[&id],
+            )
+            .await?;
+        Ok(cancelled > 0)
+    }
+
+    async fn finish_broadcast(&self, id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute("UPDATE broadcasts SET status = 'done' WHERE id = $1 AND status = 'sending'", &[&id])
+            .await?;
+        Ok(())
+    }
+
+    async fn get_pending_recipients(&self, id: i64, limit: i64) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT chat_id FROM broadcast_recipients WHERE broadcast_id = $1 AND status = 'pending' ORDER BY chat_id LIMIT $2",
+                &[&id, &limit],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.try_get(0)).collect::<Result<Vec<i64>, _>>()?)
+    }
+
+    async fn set_recipient_status(&self, id: i64, chat_id: i64, status: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "UPDATE broadcast_recipients SET status = $1 WHERE broadcast_id = $2 AND chat_id = $3",
+                &[&status, &id, &chat_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_broadcast_stats(&self, id: i64) -> Result<BroadcastStats, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT status, COUNT(*) FROM broadcast_recipients WHERE broadcast_id = $1 GROUP BY status",
+                &[&id],
+            )
+            .await?;
+        let counts = rows
+            .iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+            .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+        Ok(broadcast_stats(counts))
+    }
+
+    async fn record_usage_event(&self, kind: &str, chat_id: ChatId, user_id: Option<UserId>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO usage_events (kind, chat_id, user_id, created_at) VALUES ($1, $2, $3, $4)",
+                &[&kind, &chat_id.0, &user_id.map(|id| id.0 as i64), &Utc::now().timestamp()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn record_api_call(&self, endpoint: &str, status: Option<u16>, ok: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO api_calls (endpoint, status, ok, created_at) VALUES ($1, $2, $3, $4)",
+                &[&endpoint, &status.map(i32::from), &ok, &Utc::now().timestamp()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn count_active_users_since(&self, since: i64) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_one(
+                "SELECT COUNT(DISTINCT user_id) FROM usage_events WHERE kind != 'alert' AND created_at >= $1",
+                &[&since],
+            )
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    async fn count_usage_events(&self, kind: &str, since: i64) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_one(
+                "SELECT COUNT(*) FROM usage_events WHERE kind = $1 AND created_at >= $2",
+                &[&kind, &since],
+            )
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    async fn get_api_call_stats(&self, since: i64) -> Result<Vec<ApiCallStats>, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT endpoint, COUNT(*), COUNT(*) FILTER (WHERE NOT ok) FROM api_calls
+                 WHERE created_at >= $1 GROUP BY endpoint ORDER BY endpoint",
+                &[&since],
+            )
+            .await?;
+        let stats = rows
+            .iter()
+            .map(|row| Ok(ApiCallStats { endpoint: row.try_get(0)?, calls: row.try_get(1)?, errors: row.try_get(2)? }))
+            .collect::<Result<Vec<_>, tokio_postgres::Error>>()?;
+        Ok(stats)
+    }
+
+    async fn database_size(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        let size: i64 = client
+            .query_one("SELECT pg_database_size(current_database())", &[])
+            .await?
+            .try_get(0)?;
+        Ok(u64::try_from(size)?)
+    }
+
+    async fn delete_expired_events(&self, now: i64, stats_border: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.pool.get().await?;
+        client.execute("DELETE FROM subscription_notifications WHERE date < $1", &[&now]).await?;
+        client.execute("DELETE FROM usage_events WHERE created_at < $1", &[&stats_border]).await?;
+        client.execute("DELETE FROM api_calls WHERE created_at < $1", &[&stats_border]).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{
+        check_broadcasts_and_stats, check_presets_and_cheapest_search, check_profiles_and_roles, check_subscriptions,
+        check_users_and_states, check_warehouses_and_coefficients,
+    };
+
+    // Тесты идут только с тестовой базой: POSTGRES_TEST_URL=postgres://... cargo test --features postgres.
+    // Таблицы в ней очищаются, поэтому рабочую базу сюда не указывать
+    async fn connect_test_db() -> Option<PostgresStorage> {
+        let url = std::env::var("POSTGRES_TEST_URL").ok()?;
+        let storage = PostgresStorage::connect(&url).await.unwrap();
+        Some(storage)
+    }
+
+    async fn truncate(storage: &PostgresStorage, tables: &str) {
+        let client = storage.pool.get().await.unwrap();
+        client.batch_execute(&format!("TRUNCATE {}", tables)).await.unwrap();
+    }
+
+    // Все проверки в одном тесте: параллельные тесты очищали бы таблицы друг другу
+    #[tokio::test]
+    async fn shared_storage_checks() {
+        let Some(storage) = connect_test_db().await else {
+            return;
+        };
+        truncate(&storage, "users, dialogues, token_reminders").await;
+        check_users_and_states(&storage).await;
+        truncate(&storage, "warehouses, favourite_warehouses, warehouses_coefficients, coefficient_history").await;
+        check_warehouses_and_coefficients(&storage).await;
+        truncate(&storage, "browser_profiles, admins").await;
+        check_profiles_and_roles(&storage).await;
+        truncate(&storage, "subscriptions, subscription_notifications").await;
+        check_subscriptions(&storage).await;
+        truncate(&storage, "warehouses, presets, preset_warehouses, cheapest_searches").await;
+        check_presets_and_cheapest_search(&storage).await;
+        truncate(&storage, "callback_payloads, broadcasts, broadcast_recipients, usage_events, api_calls").await;
+        check_broadcasts_and_stats(&storage).await;
+    }
+
+    #[tokio::test]
+    async fn migrations_are_idempotent() {
+        let Some(storage) = connect_test_db().await else {
+            return;
+        };
+        storage.migrate().await.unwrap();
+        let client = storage.pool.get().await.unwrap();
+        let version: i32 = client.query_one("SELECT version FROM schema_version", &[]).await.unwrap().get(0);
+        assert_eq!(version, MIGRATIONS.len() as i32);
+    }
+}
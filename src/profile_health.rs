@@ -0,0 +1,104 @@
+use std::error::Error;
+use teloxide::prelude::*;
+use thirtyfour::prelude::*;
+
+use crate::database::ProfileHealth;
+use crate::keyboards::profile_relogin_keyboard;
+use crate::portal_login::format_phone;
+use crate::seller_portal::{selectors, start_browser, try_lock_profile, wait_for_any, PortalConfig, PortalError, PORTAL_CONFIG};
+use crate::storage::Storage;
+
+// Главная страница кабинета: без действующего входа портал показывает форму входа
+const HOME_PATH: &str = "/";
+
+async fn session_health(driver: &WebDriver, config: &PortalConfig) -> Result<ProfileHealth, PortalError> {
+    driver.goto(config.page_url(HOME_PATH)).await?;
+    match wait_for_any(driver, &[selectors::SELLER_PROFILE, selectors::LOGIN_FORM], config.step_timeout).await? {
+        Some((0, _)) => Ok(ProfileHealth::Active),
+        Some(_) => Ok(ProfileHealth::Expired),
+        None => Err(PortalError::NoResponse),
+    }
+}
+
+// Открывает кабинет с профилем номера в чате и смотрит, пускает ли портал без входа
+pub async fn check_session(config: &PortalConfig, chat: ChatId, phone: &str) -> Result<ProfileHealth, PortalError> {
+    let driver = start_browser(config, chat, phone).await?;
+    let health = session_health(&driver, config).await;
+    if let Err(e) = driver.quit().await {
+        log::warn!("Не удалось закрыть сессию WebDriver: {}", e);
+    }
+    health
+}
+
+// Проверяет все сохраненные профили. О истекшем входе сообщаем один раз, когда состояние сменилось
+pub async fn check_profiles(bot: &Bot, storage: &dyn Storage) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for profile in storage.get_all_browser_profiles().await? {
+        let (chat_id, phone) = (ChatId(profile.chat_id), profile.phone);
+        // Профиль сейчас занят входом или бронированием, проверим в следующий раз
+        let Some(_profile) = try_lock_profile(chat_id, &phone).await else {
+            continue;
+        };
+        let health = match check_session(&PORTAL_CONFIG, chat_id, &phone).await {
+            Ok(health) => health,
+            Err(e) => {
+                eprintln!("Ошибка при проверке профиля {}: {}", format_phone(&phone), e);
+                continue;
+            }
+        };
+
+        let changed = storage.set_profile_health(chat_id, &phone, health).await?;
+        if !changed || health != ProfileHealth::Expired {
+            continue;
+        }
+        let msg_to_user = format!(
+            "⚠️ Вход в кабинет WB для номера {} больше не действует.\n\nАвтобронирование с этим профилем не сработает, пока вы не войдете заново",
+            format_phone(&phone)
+        );
+        if let Err(e) = bot
+            .send_message(chat_id, msg_to_user)
+            .reply_markup(profile_relogin_keyboard(&phone))
+            .await
+        {
+            eprintln!("Ошибка при уведомлении об истекшем входе в чат {}: {:?}", chat_id, e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webdriver_mock::{MockWebDriver, MOCK_LOGIN};
+    use tokio::time::Duration;
+
+    fn config(webdriver: &MockWebDriver) -> PortalConfig {
+        PortalConfig {
+            webdriver_url: webdriver.url(),
+            portal_url: "http://portal.test".to_string(),
+            profiles_dir: "/tmp/profiles".into(),
+            step_timeout: Duration::from_millis(300),
+            result_timeout: Duration::from_millis(300),
+        }
+    }
+
+    #[tokio::test]
+    async fn login_form_means_expired_session() {
+        let webdriver = MockWebDriver::start(MOCK_LOGIN).await;
+        assert_eq!(check_session(&config(&webdriver), ChatId(42), "9991234567").await.unwrap(), ProfileHealth::Expired);
+        assert_eq!(webdriver.visited(), vec!["http://portal.test/"]);
+        assert!(webdriver.quit());
+    }
+
+    #[tokio::test]
+    async fn seller_profile_means_active_session() {
+        let webdriver = MockWebDriver::start(r#"<div data-testid="seller-profile">ИП Иванов</div>"#).await;
+        assert_eq!(check_session(&config(&webdriver), ChatId(42), "9991234567").await.unwrap(), ProfileHealth::Active);
+    }
+
+    #[tokio::test]
+    async fn unknown_page_is_not_a_verdict() {
+        let webdriver = MockWebDriver::start("<p>Технические работы</p>").await;
+        assert!(matches!(check_session(&config(&webdriver), ChatId(42), "9991234567").await, Err(PortalError::NoResponse)));
+        assert!(webdriver.quit());
+    }
+}
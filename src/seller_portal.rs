@@ -0,0 +1,309 @@
+// Общее для работы с кабинетом продавца через браузер: настройки WebDriver, селекторы страниц,
+// запуск Chrome с профилем номера и ожидание элементов. Используют auto_booking и portal_login
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use teloxide::types::ChatId;
+use thirtyfour::prelude::*;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::database::BrowserProfile;
+use crate::portal_login::normalize_phone;
+
+pub const DEFAULT_WEBDRIVER_URL: &str = "http://localhost:9515";
+pub const DEFAULT_SELLER_PORTAL_URL: &str = "https://seller.wildberries.ru";
+pub const DEFAULT_BROWSER_PROFILES_DIR: &str = "browser_profiles";
+
+// Как часто проверяем, появился ли нужный элемент
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+lazy_static! {
+    pub static ref PORTAL_CONFIG: PortalConfig = PortalConfig::from_config();
+    // Chrome не откроет один профиль в двух сессиях сразу, поэтому работа с одним профилем идет по очереди
+    static ref PROFILE_LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+// Селекторы страниц кабинета. Если портал поменяет верстку, править нужно только здесь
+pub mod selectors {
+    // Вход по номеру телефона
+    pub const LOGIN_FORM: &str = r#"[data-testid="login-form"]"#;
+    pub const PHONE_INPUT: &str = r#"[data-testid="phone-input"]"#;
+    pub const PHONE_SUBMIT: &str = r#"[data-testid="phone-submit"]"#;
+    pub const CAPTCHA_IMAGE: &str = r#"[data-testid="captcha-image"]"#;
+    pub const CAPTCHA_INPUT: &str = r#"[data-testid="captcha-input"]"#;
+    pub const CAPTCHA_SUBMIT: &str = r#"[data-testid="captcha-submit"]"#;
+    pub const CODE_INPUT: &str = r#"[data-testid="code-input"]"#;
+    pub const CODE_SUBMIT: &str = r#"[data-testid="code-submit"]"#;
+    pub const LOGIN_ERROR: &str = r#"[data-testid="login-error"]"#;
+    pub const SELLER_PROFILE: &str = r#"[data-testid="seller-profile"]"#;
+
+    // Планирование поставки
+    pub const PLAN_SUPPLY: &str = r#"[data-testid="plan-supply"]"#;
+    pub const CONFIRM: &str = r#"[data-testid="confirm-booking"]"#;
+    pub const SUCCESS: &str = r#"[data-testid="booking-success"]"#;
+    pub const ERROR: &str = r#"[data-testid="booking-error"]"#;
+
+    fn escape(value: &str) -> String {
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+
+    pub fn warehouse(id: i32) -> String {
+        format!(r#"[data-testid="warehouse-option"][data-warehouse-id="{}"]"#, id)
+    }
+
+    pub fn box_type(name: &str) -> String {
+        format!(r#"[data-testid="box-type-option"][data-box-type="{}"]"#, escape(name))
+    }
+
+    pub fn date(date: &str) -> String {
+        format!(r#"[data-testid="date-cell"][data-date="{}"]"#, date)
+    }
+}
+
+pub struct PortalConfig {
+    pub webdriver_url: String,
+    pub portal_url: String,
+    pub profiles_dir: PathBuf,    // профиль каждого номера лежит в отдельной папке
+    pub step_timeout: Duration,   // сколько ждем элемент после перехода или клика
+    pub result_timeout: Duration, // сколько ждем ответа портала на бронирование или вход
+}
+
+impl PortalConfig {
+    pub fn from_config() -> PortalConfig {
+        let settings = &crate::config::get().portal;
+        PortalConfig {
+            webdriver_url: settings.webdriver_url.clone(),
+            portal_url: settings.url.clone(),
+            profiles_dir: settings.profiles_dir.clone().into(),
+            step_timeout: Duration::from_secs(15),
+            result_timeout: Duration::from_secs(30),
+        }
+    }
+
+    // Папка профиля Chrome для номера телефона в чате. У каждого чата своя папка, поэтому вход,
+    // выполненный в одном чате, не достается другому чату, который ввел тот же номер.
+    // Без номера из 10 цифр путь указал бы на общую папку, поэтому такие номера не принимаем
+    pub fn profile_dir(&self, chat: ChatId, phone: &str) -> Option<PathBuf> {
+        let name = normalize_phone(phone)?;
+        let dir = self.profiles_dir.join(format!("chat_{}", chat.0)).join(name);
+        // chromedriver может быть запущен из другой папки, поэтому путь передаем абсолютный
+        Some(std::path::absolute(&dir).unwrap_or(dir))
+    }
+
+    pub fn page_url(&self, path: &str) -> String {
+        format!("{}{}", self.portal_url.trim_end_matches('/'), path)
+    }
+}
+
+#[derive(Debug)]
+pub enum PortalError {
+    NotLoggedIn,                  // сессия в профиле истекла, портал просит войти заново
+    ElementMissing(&'static str), // не нашли элемент на шаге
+    SlotUnavailable,              // слот успели занять
+    Rejected(String),             // портал показал ошибку
+    NoResponse,                   // портал не ответил за отведенное время
+    LoginNotStarted,              // вход по номеру не начат или закрыт по таймауту
+    InvalidPhone,                 // номер профиля не похож на мобильный номер из 10 цифр
+    WebDriver(Box<WebDriverError>),
+}
+
+impl fmt::Display for PortalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortalError::NotLoggedIn => write!(f, "Сессия в кабинете WB истекла, нужно войти по номеру телефона заново"),
+            PortalError::ElementMissing(step) => write!(f, "На странице кабинета не найден элемент «{}»", step),
+            PortalError::SlotUnavailable => write!(f, "Слот на эту дату уже недоступен"),
+            PortalError::Rejected(reason) => write!(f, "Портал отклонил запрос: {}", reason),
+            PortalError::NoResponse => write!(f, "Портал не ответил вовремя"),
+            PortalError::LoginNotStarted => write!(f, "Вход по номеру прерван, начните заново"),
+            PortalError::InvalidPhone => write!(f, "Некорректный номер телефона профиля"),
+            PortalError::WebDriver(e) => write!(f, "Ошибка браузера: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PortalError {}
+
+impl From<WebDriverError> for PortalError {
+    fn from(error: WebDriverError) -> Self {
+        PortalError::WebDriver(Box::new(error))
+    }
+}
+
+pub async fn start_browser(config: &PortalConfig, chat: ChatId, phone: &str) -> Result<WebDriver, PortalError> {
+    let profile_dir = config.profile_dir(chat, phone).ok_or(PortalError::InvalidPhone)?;
+    let mut caps = DesiredCapabilities::chrome();
+    caps.add_arg("--headless=new")?;
+    caps.add_arg("--window-size=1280,1024")?;
+    caps.add_arg(&format!("--user-data-dir={}", profile_dir.display()))?;
+    Ok(WebDriver::new(config.webdriver_url.clone(), caps).await?)
+}
+
+// Ждет, пока на странице появится видимый элемент по одному из селекторов. Возвращает номер селектора
+pub async fn wait_for_any(
+    driver: &WebDriver,
+    selectors: &[&str],
+    timeout: Duration,
+) -> Result<Option<(usize, WebElement)>, PortalError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        for (i, selector) in selectors.iter().enumerate() {
+            for element in driver.find_all(By::Css(*selector)).await? {
+                if element.is_displayed().await? {
+                    return Ok(Some((i, element)));
+                }
+            }
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+pub async fn click_step(driver: &WebDriver, config: &PortalConfig, selector: &str, missing: PortalError) -> Result<(), PortalError> {
+    match wait_for_any(driver, &[selector], config.step_timeout).await? {
+        Some((_, element)) => Ok(element.click().await?),
+        None => Err(missing),
+    }
+}
+
+// Вводит текст в поле, дождавшись его появления
+pub async fn fill_step(driver: &WebDriver, config: &PortalConfig, selector: &str, text: &str, step: &'static str) -> Result<(), PortalError> {
+    match wait_for_any(driver, &[selector], config.step_timeout).await? {
+        Some((_, element)) => {
+            element.clear().await?;
+            Ok(element.send_keys(text).await?)
+        }
+        None => Err(PortalError::ElementMissing(step)),
+    }
+}
+
+async fn profile_mutex(chat: ChatId, phone: &str) -> Arc<Mutex<()>> {
+    PROFILE_LOCKS.lock().await.entry(format!("{}/{}", chat.0, phone)).or_default().clone()
+}
+
+// Захватывает профиль номера в чате. Пока guard жив, другие сессии с этим профилем ждут
+pub async fn lock_profile(chat: ChatId, phone: &str) -> OwnedMutexGuard<()> {
+    profile_mutex(chat, phone).await.lock_owned().await
+}
+
+// Захватывает профиль, только если он сейчас свободен
+pub async fn try_lock_profile(chat: ChatId, phone: &str) -> Option<OwnedMutexGuard<()>> {
+    profile_mutex(chat, phone).await.try_lock_owned().ok()
+}
+
+async fn remove_dir(dir: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_dir_all(dir).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Удаляет папку профиля с диска вместе с сохраненным входом
+pub async fn remove_profile_dir(config: &PortalConfig, chat: ChatId, phone: &str) -> std::io::Result<()> {
+    match config.profile_dir(chat, phone) {
+        Some(dir) => remove_dir(&dir).await,
+        None => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "некорректный номер телефона профиля")),
+    }
+}
+
+// Раньше папка профиля (<папка профилей>/<цифры номера>) была общей для всех чатов с номером.
+// Ее получает чат, только если номер сохранен в одном чате. Иначе неизвестно, чей это вход,
+// поэтому папку удаляем и чаты входят заново
+pub async fn move_legacy_profile_dirs(config: &PortalConfig, profiles: &[BrowserProfile]) -> std::io::Result<()> {
+    let mut chats_by_phone: HashMap<&str, Vec<i64>> = HashMap::new();
+    for profile in profiles {
+        chats_by_phone.entry(&profile.phone).or_default().push(profile.chat_id);
+    }
+    for (phone, chats) in chats_by_phone {
+        if normalize_phone(phone).as_deref() != Some(phone) {
+            continue;
+        }
+        let legacy = config.profiles_dir.join(phone);
+        if !tokio::fs::try_exists(&legacy).await? {
+            continue;
+        }
+        match chats[..] {
+            [chat] => {
+                let target = config.profile_dir(ChatId(chat), phone).ok_or(std::io::ErrorKind::InvalidInput)?;
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::rename(&legacy, &target).await?;
+            }
+            _ => remove_dir(&legacy).await?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::ProfileHealth;
+
+    fn test_config(name: &str) -> PortalConfig {
+        PortalConfig {
+            profiles_dir: std::env::temp_dir().join(format!("{}_{}", name, std::process::id())),
+            ..PortalConfig::from_config()
+        }
+    }
+
+    fn profile(chat_id: i64, phone: &str) -> BrowserProfile {
+        BrowserProfile { chat_id, phone: phone.to_string(), last_login_at: None, health: ProfileHealth::Unknown, checked_at: None }
+    }
+
+    #[tokio::test]
+    async fn removes_profile_dir_of_the_number_only() {
+        let config = test_config("profiles_test");
+        let kept = config.profile_dir(ChatId(1), "9990000000").unwrap();
+        let same_number_other_chat = config.profile_dir(ChatId(2), "9991234567").unwrap();
+        std::fs::create_dir_all(config.profile_dir(ChatId(1), "9991234567").unwrap().join("Default")).unwrap();
+        std::fs::create_dir_all(&kept).unwrap();
+        std::fs::create_dir_all(&same_number_other_chat).unwrap();
+
+        remove_profile_dir(&config, ChatId(1), "9991234567").await.unwrap();
+        assert!(!config.profile_dir(ChatId(1), "9991234567").unwrap().exists());
+        assert!(kept.exists());
+        assert!(same_number_other_chat.exists());
+        // Повторное удаление не ошибка
+        remove_profile_dir(&config, ChatId(1), "9991234567").await.unwrap();
+        std::fs::remove_dir_all(&config.profiles_dir).unwrap();
+    }
+
+    #[test]
+    fn profile_dir_needs_a_mobile_number() {
+        let config = test_config("profiles_test");
+        let dir = config.profile_dir(ChatId(1), "+7 (999) 123-45-67").unwrap();
+        assert!(dir.ends_with("chat_1/9991234567"));
+        for phone in ["", "abc", "12345", "../9991234567"] {
+            assert_eq!(config.profile_dir(ChatId(1), phone), None, "{}", phone);
+        }
+    }
+
+    #[tokio::test]
+    async fn shared_legacy_profile_goes_only_to_its_single_chat() {
+        let config = test_config("legacy_profiles_test");
+        std::fs::create_dir_all(config.profiles_dir.join("9991234567").join("Default")).unwrap();
+        std::fs::create_dir_all(config.profiles_dir.join("9990000000").join("Default")).unwrap();
+
+        let profiles = [profile(1, "9991234567"), profile(1, "9990000000"), profile(2, "9990000000")];
+        move_legacy_profile_dirs(&config, &profiles).await.unwrap();
+        assert!(config.profile_dir(ChatId(1), "9991234567").unwrap().join("Default").exists());
+        assert!(!config.profiles_dir.join("9991234567").exists());
+        // Номер был в двух чатах: общий вход не достается ни одному из них
+        assert!(!config.profiles_dir.join("9990000000").exists());
+        assert!(!config.profile_dir(ChatId(1), "9990000000").unwrap().exists());
+        assert!(!config.profile_dir(ChatId(2), "9990000000").unwrap().exists());
+
+        // Повторный запуск ничего не меняет
+        move_legacy_profile_dirs(&config, &profiles).await.unwrap();
+        assert!(config.profile_dir(ChatId(1), "9991234567").unwrap().exists());
+        std::fs::remove_dir_all(&config.profiles_dir).unwrap();
+    }
+}
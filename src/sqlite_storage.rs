@@ -0,0 +1,1283 @@
+// Хранилище в файле SQLite: реализация по умолчанию для одного экземпляра бота
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension};
+use std::error::Error;
+use teloxide::types::{ChatId, UserId};
+
+use crate::api_reauests::{CoefficientResponse, Warehouse};
+use crate::database::{
+    encode_broadcast_buttons, decode_broadcast_buttons, ApiCallStats, BrowserProfile, Broadcast, BroadcastStats, CachedSlot,
+    Cabinet, CheapestSearch, CoefficientChange, DbPool, Member, Preset, ProfileHealth, SlotLimits, Subscription,
+};
+use crate::storage::{
+    broadcast_stats, decrypt_cabinet, decrypt_cabinets_skipping_broken, name_preset_warehouses, Storage, StoredCabinet,
+};
+use crate::token_crypto::token_cipher;
+
+pub struct SqliteStorage {
+    pool: DbPool,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<SqliteStorage, Box<dyn Error + Send + Sync>> {
+        Ok(SqliteStorage { pool: DbPool::open(path)? })
+    }
+}
+
+fn cabinet_from_row(row: &rusqlite::Row) -> rusqlite::Result<StoredCabinet> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+}
+
+fn warehouse_from_row(row: &rusqlite::Row) -> rusqlite::Result<Warehouse> {
+    Ok(Warehouse {
+        id: row.get(0)?,
+        name: row.get(1)?,
+    })
+}
+
+const BROWSER_PROFILE_COLUMNS: &str = "id, phone_number, last_login_at, health, checked_at";
+
+fn browser_profile_from_row(row: &rusqlite::Row) -> rusqlite::Result<BrowserProfile> {
+    Ok(BrowserProfile {
+        chat_id: row.get(0)?,
+        phone: row.get(1)?,
+        last_login_at: row.get(2)?,
+        health: ProfileHealth::parse(&row.get::<_, String>(3)?),
+        checked_at: row.get(4)?,
+    })
+}
+
+const SUBSCRIPTION_COLUMNS: &str =
+    "id, user_id, warehouse_id, warehouse_name, box_type_name, max_coefficient, date_from, date_to, auto_book_phone";
+
+fn subscription_from_row(row: &rusqlite::Row) -> rusqlite::Result<Subscription> {
+    Ok(Subscription {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        warehouse_id: row.get(2)?,
+        warehouse_name: row.get(3)?,
+        box_type_name: row.get(4)?,
+        max_coefficient: row.get(5)?,
+        date_from: row.get(6)?,
+        date_to: row.get(7)?,
+        auto_book_phone: row.get(8)?,
+    })
+}
+
+const BROADCAST_COLUMNS: &str = "id, admin_chat_id, text, photo, buttons, status, progress_message_id";
+
+fn broadcast_from_row(row: &rusqlite::Row) -> rusqlite::Result<Broadcast> {
+    Ok(Broadcast {
+        id: row.get(0)?,
+        admin_chat_id: row.get(1)?,
+        text: row.get(2)?,
+        photo: row.get(3)?,
+        buttons: decode_broadcast_buttons(&row.get::<_, String>(4)?),
+        status: row.get(5)?,
+        progress_message_id: row.get(6)?,
+    })
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn add_user(&self, id: ChatId, username: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        // Повторный /start возвращает в рассылки пользователя, который раньше блокировал бота
+        conn.execute(
+            "INSERT INTO users (id, username) VALUES (?1, ?2) ON CONFLICT(id) DO UPDATE SET active = 1",
+            params![id.0, username],
+        )?;
+        Ok(())
+    }
+
+    async fn set_user_active(&self, id: ChatId, active: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute("UPDATE users SET active = ?1 WHERE id = ?2", params![active, id.0])?;
+        Ok(())
+    }
+
+    async fn get_active_users(&self, exclude: ChatId) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT id FROM users WHERE active = 1 AND id != ?1 ORDER BY id")?;
+        let users = stmt
+            .query_map(params![exclude.0], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        Ok(users)
+    }
+
+    async fn count_users(&self) -> Result<(i64, i64), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let counts = conn.query_row("SELECT COUNT(*), COALESCE(SUM(active), 0) FROM users", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(counts)
+    }
+
+    async fn get_id_by_username(&self, username: String) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT id FROM users WHERE username = ?1")?;
+        let id: i64 = stmt.query_row([username], |row| row.get(0))?;
+        Ok(id)
+    }
+
+    async fn get_dialogue(&self, member: Member) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let dialogue = conn
+            .query_row(
+                "SELECT dialogue FROM dialogues WHERE chat_id = ?1 AND user_id = ?2",
+                params![member.chat.0, member.user.0],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(dialogue)
+    }
+
+    async fn update_dialogue(&self, member: Member, dialogue: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO dialogues (chat_id, user_id, dialogue) VALUES (?1, ?2, ?3)",
+            params![member.chat.0, member.user.0, dialogue],
+        )?;
+        Ok(())
+    }
+
+    async fn remove_dialogue(&self, member: Member) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "DELETE FROM dialogues WHERE chat_id = ?1 AND user_id = ?2",
+            params![member.chat.0, member.user.0],
+        )?;
+        Ok(())
+    }
+
+    async fn create_cabinet(&self, id: ChatId, name: String, token: String) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let encrypted = token_cipher()?.encrypt(&token)?;
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT INTO cabinets (chat_id, name, token) VALUES (?1, ?2, ?3)",
+            params![id.0, name, encrypted],
+        )?;
+        let cabinet_id = conn.last_insert_rowid();
+        conn.execute(
+            "INSERT OR REPLACE INTO active_cabinets (chat_id, cabinet_id) VALUES (?1, ?2)",
+            params![id.0, cabinet_id],
+        )?;
+        Ok(cabinet_id)
+    }
+
+    async fn rename_cabinet(&self, id: ChatId, cabinet_id: i64, name: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "UPDATE cabinets SET name = ?1 WHERE id = ?2 AND chat_id = ?3",
+            params![name, cabinet_id, id.0],
+        )?;
+        Ok(())
+    }
+
+    async fn update_cabinet_token(&self, id: ChatId, cabinet_id: i64, token: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let encrypted = token_cipher()?.encrypt(&token)?;
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "UPDATE cabinets SET token = ?1 WHERE id = ?2 AND chat_id = ?3",
+            params![encrypted, cabinet_id, id.0],
+        )?;
+        Ok(())
+    }
+
+    async fn get_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<Option<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let row = conn
+            .query_row(
+                "SELECT id, chat_id, name, token FROM cabinets WHERE id = ?1 AND chat_id = ?2",
+                params![cabinet_id, id.0],
+                cabinet_from_row,
+            )
+            .optional()?;
+        row.map(decrypt_cabinet).transpose()
+    }
+
+    async fn get_user_cabinets(&self, id: ChatId) -> Result<Vec<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT id, chat_id, name, token FROM cabinets WHERE chat_id = ?1 ORDER BY id")?;
+        let rows = stmt
+            .query_map(params![id.0], cabinet_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter().map(decrypt_cabinet).collect()
+    }
+
+    async fn get_all_cabinets(&self) -> Result<Vec<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT id, chat_id, name, token FROM cabinets")?;
+        let rows = stmt
+            .query_map([], cabinet_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(decrypt_cabinets_skipping_broken(rows))
+    }
+
+    async fn get_active_cabinet(&self, id: ChatId) -> Result<Option<Cabinet>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let row = conn
+            .query_row(
+                "SELECT c.id, c.chat_id, c.name, c.token FROM cabinets c
+                 LEFT JOIN active_cabinets a ON a.chat_id = c.chat_id AND a.cabinet_id = c.id
+                 WHERE c.chat_id = ?1
+                 ORDER BY a.cabinet_id IS NULL, c.id
+                 LIMIT 1",
+                params![id.0],
+                cabinet_from_row,
+            )
+            .optional()?;
+        row.map(decrypt_cabinet).transpose()
+    }
+
+    async fn set_active_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO active_cabinets (chat_id, cabinet_id)
+             SELECT chat_id, id FROM cabinets WHERE id = ?1 AND chat_id = ?2",
+            params![cabinet_id, id.0],
+        )?;
+        Ok(())
+    }
+
+    async fn delete_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute("DELETE FROM cabinets WHERE id = ?1 AND chat_id = ?2", params![cabinet_id, id.0])?;
+        conn.execute(
+            "DELETE FROM active_cabinets WHERE chat_id = ?1 AND cabinet_id = ?2",
+            params![id.0, cabinet_id],
+        )?;
+        Ok(())
+    }
+
+    async fn reencrypt_user_tokens(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let cipher = token_cipher()?;
+        let conn = self.pool.connection();
+        let mut conn = conn.lock().await;
+        let tx = conn.transaction()?;
+
+        let rows: Vec<(i64, String)> = {
+            let mut stmt = tx.prepare("SELECT id, token FROM cabinets")?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            rows
+        };
+
+        let mut updated = 0;
+        for (cabinet_id, stored) in rows {
+            if !cipher.needs_reencryption(&stored) {
+                continue;
+            }
+            let token = cipher.decrypt(&stored)?;
+            tx.execute(
+                "UPDATE cabinets SET token = ?1 WHERE id = ?2",
+                params![cipher.encrypt(&token)?, cabinet_id],
+            )?;
+            updated += 1;
+        }
+        tx.commit()?;
+
+        Ok(updated)
+    }
+
+    async fn mark_token_reminder_sent(&self, chat_id: i64, exp: i64, kind: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO token_reminders (chat_id, exp, kind) VALUES (?1, ?2, ?3)",
+            params![chat_id, exp, kind],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    async fn unmark_token_reminder_sent(&self, chat_id: i64, exp: i64, kind: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "DELETE FROM token_reminders WHERE chat_id = ?1 AND exp = ?2 AND kind = ?3",
+            params![chat_id, exp, kind],
+        )?;
+        Ok(())
+    }
+
+    async fn insert_warehouses(&self, warehouses: Vec<Warehouse>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        for warehouse in warehouses {
+            conn.execute(
+                "INSERT INTO warehouses (id, name) VALUES (?1, ?2)
+                 ON CONFLICT(id) DO UPDATE SET name = excluded.name",
+                params![warehouse.id, warehouse.name],
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn get_warehouses_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<(Warehouse, bool)>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let offset = page * page_size;
+        let mut stmt = conn.prepare(
+            "SELECT w.id, w.name, f.warehouse_id IS NOT NULL FROM warehouses w
+             LEFT JOIN favourite_warehouses f ON f.warehouse_id = w.id AND f.user_id = ?1
+             ORDER BY f.warehouse_id IS NULL, w.name LIMIT ?2 OFFSET ?3",
+        )?;
+        let warehouses = stmt
+            .query_map(params![id.0, page_size, offset], |row| Ok((warehouse_from_row(row)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(warehouses)
+    }
+
+    async fn get_all_warehouses(&self) -> Result<Vec<Warehouse>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT id, name FROM warehouses ORDER BY name")?;
+        let warehouses = stmt
+            .query_map([], warehouse_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(warehouses)
+    }
+
+    async fn count_warehouses(&self) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let count: i32 = conn.query_row("SELECT COUNT(*) FROM warehouses", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    async fn get_warehouse_name(&self, warehouse_id: i32) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let name = conn
+            .query_row("SELECT name FROM warehouses WHERE id = ?1", params![warehouse_id], |row| row.get(0))
+            .optional()?;
+        Ok(name)
+    }
+
+    async fn toggle_favourite_warehouse(&self, id: ChatId, warehouse_id: i32) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let removed = conn.execute(
+            "DELETE FROM favourite_warehouses WHERE user_id = ?1 AND warehouse_id = ?2",
+            params![id.0, warehouse_id],
+        )?;
+        if removed > 0 {
+            return Ok(false);
+        }
+        conn.execute(
+            "INSERT INTO favourite_warehouses (user_id, warehouse_id) VALUES (?1, ?2)",
+            params![id.0, warehouse_id],
+        )?;
+        Ok(true)
+    }
+
+    async fn is_favourite_warehouse(&self, id: ChatId, warehouse_id: i32) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let count: i32 = conn.query_row(
+            "SELECT COUNT(*) FROM favourite_warehouses WHERE user_id = ?1 AND warehouse_id = ?2",
+            params![id.0, warehouse_id],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    async fn get_favourite_warehouses(&self, id: ChatId) -> Result<Vec<Warehouse>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT w.id, w.name FROM favourite_warehouses f
+             JOIN warehouses w ON w.id = f.warehouse_id
+             WHERE f.user_id = ?1 ORDER BY w.name",
+        )?;
+        let warehouses = stmt
+            .query_map(params![id.0], warehouse_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(warehouses)
+    }
+
+    async fn add_or_update_warehouse_coefficents(&self, coefficients: Vec<CoefficientResponse>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let observed_at = Utc::now().timestamp();
+
+        for coefficient in coefficients {
+            let datetime: DateTime<Utc> = coefficient.date.parse()?;
+            let unix_time = datetime.timestamp();
+            conn.execute(
+                "INSERT INTO warehouses_coefficients (date, coefficient, warehouse_id, warehouse_name, box_type_name, box_type_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(date, warehouse_id, box_type_name) DO UPDATE SET coefficient = excluded.coefficient",
+                params![unix_time, coefficient.coefficient, coefficient.warehouse_id, coefficient.warehouse_name, coefficient.box_type_name, coefficient.box_type_id],
+            )?;
+
+            // В историю пишем только изменения: коэффициенты опрашиваются каждые несколько минут
+            // и одинаковые наблюдения подряд ничего не добавляют
+            conn.execute(
+                "INSERT INTO coefficient_history (date, warehouse_id, warehouse_name, box_type_name, coefficient, observed_at)
+                 SELECT ?1, ?2, ?3, ?4, ?5, ?6
+                 WHERE (SELECT coefficient FROM coefficient_history
+                        WHERE date = ?1 AND warehouse_id = ?2 AND box_type_name = ?4
+                        ORDER BY observed_at DESC, id DESC LIMIT 1) IS NOT ?5",
+                params![unix_time, coefficient.warehouse_id, coefficient.warehouse_name, coefficient.box_type_name, coefficient.coefficient, observed_at],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    async fn get_coefficient_history(
+        &self,
+        warehouse_id: i32,
+        box_type_name: &str,
+        date_from: i64,
+    ) -> Result<Vec<CoefficientChange>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT date, coefficient, observed_at FROM coefficient_history
+             WHERE warehouse_id = ?1 AND box_type_name = ?2 AND date >= ?3
+             ORDER BY date, observed_at, id",
+        )?;
+        let changes = stmt
+            .query_map(params![warehouse_id, box_type_name, date_from], |row| {
+                Ok(CoefficientChange {
+                    date: row.get(0)?,
+                    coefficient: row.get(1)?,
+                    observed_at: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(changes)
+    }
+
+    async fn get_warehouse_coefficients(
+        &self,
+        warehouse_id: i32,
+        box_type_name: &str,
+        date_from: i64,
+        date_to: i64,
+    ) -> Result<Vec<(i64, i32)>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT date, coefficient FROM warehouses_coefficients
+             WHERE warehouse_id = ?1 AND box_type_name = ?2 AND date >= ?3 AND date < ?4
+             ORDER BY date",
+        )?;
+        let rows = stmt
+            .query_map(params![warehouse_id, box_type_name, date_from, date_to], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    async fn get_available_coefficients(&self, warehouse_id: i32, box_type_name: &str) -> Result<Vec<(i64, i32)>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT date, coefficient FROM warehouses_coefficients
+             WHERE warehouse_id = ?1 AND box_type_name = ?2 AND coefficient != -1
+             ORDER BY coefficient, date",
+        )?;
+        let rows = stmt
+            .query_map(params![warehouse_id, box_type_name], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    async fn get_unique_box_types(&self, warehouse_id: i32) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT box_type_name FROM warehouses_coefficients WHERE warehouse_id = ?1 ORDER BY box_type_name COLLATE NOCASE",
+        )?;
+        let box_types = stmt
+            .query_map([warehouse_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(box_types)
+    }
+
+    async fn get_box_type_slots(&self, box_type_name: &str) -> Result<Vec<CachedSlot>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT warehouse_id, warehouse_name, date, coefficient FROM warehouses_coefficients WHERE box_type_name = ?1",
+        )?;
+        let slots = stmt
+            .query_map(params![box_type_name], |row| {
+                Ok(CachedSlot {
+                    warehouse_id: row.get(0)?,
+                    warehouse_name: row.get(1)?,
+                    date: row.get(2)?,
+                    coefficient: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(slots)
+    }
+
+    async fn get_all_box_types(&self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT DISTINCT box_type_name FROM warehouses_coefficients ORDER BY box_type_name")?;
+        let box_types = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(box_types)
+    }
+
+    async fn delete_expired_coefficients(&self, now: i64, history_border: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute("DELETE FROM warehouses_coefficients WHERE date < ?1", params![now])?;
+        conn.execute("DELETE FROM coefficient_history WHERE observed_at < ?1", params![history_border])?;
+        Ok(())
+    }
+
+    async fn set_admin_role(&self, id: UserId, role: &str, granted_by: UserId) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO admins (user_id, role, granted_by, granted_at) VALUES (?1, ?2, ?3, ?4)",
+            params![id.0, role, granted_by.0, Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    async fn delete_admin_role(&self, id: UserId) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let deleted = conn.execute("DELETE FROM admins WHERE user_id = ?1", params![id.0])?;
+        Ok(deleted > 0)
+    }
+
+    async fn get_admin_role(&self, id: UserId) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let role = conn
+            .query_row("SELECT role FROM admins WHERE user_id = ?1", params![id.0], |row| row.get(0))
+            .optional()?;
+        Ok(role)
+    }
+
+    async fn get_admins(&self) -> Result<Vec<(i64, String, Option<String>)>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT a.user_id, a.role, u.username FROM admins a LEFT JOIN users u ON u.id = a.user_id ORDER BY a.role, a.user_id",
+        )?;
+        let admins = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(admins)
+    }
+
+    async fn save_browser_profile(&self, id: ChatId, phone: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT INTO chrome_profiles (id, phone_number, last_login_at, health, checked_at) VALUES (?1, ?2, ?3, 'active', ?3)
+             ON CONFLICT(id, phone_number) DO UPDATE SET last_login_at = excluded.last_login_at, health = 'active', checked_at = excluded.checked_at",
+            params![id.0, phone, Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    async fn get_browser_profile(&self, id: ChatId, phone: &str) -> Result<Option<BrowserProfile>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let profile = conn
+            .query_row(
+                &format!("SELECT {} FROM chrome_profiles WHERE id = ?1 AND phone_number = ?2", BROWSER_PROFILE_COLUMNS),
+                params![id.0, phone],
+                browser_profile_from_row,
+            )
+            .optional()?;
+        Ok(profile)
+    }
+
+    async fn get_user_browser_profiles_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let offset = page * page_size;
+        let mut stmt = conn.prepare(
+            "SELECT phone_number FROM chrome_profiles WHERE id = ?1 ORDER BY phone_number LIMIT ?2 OFFSET ?3",
+        )?;
+        let phones = stmt
+            .query_map(params![id.0, page_size, offset], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(phones)
+    }
+
+    async fn count_user_numbers(&self, id: ChatId) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let count: i32 = conn.query_row("SELECT COUNT(*) FROM chrome_profiles WHERE id = ?1", params![id.0], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    async fn get_all_browser_profiles(&self) -> Result<Vec<BrowserProfile>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM chrome_profiles ORDER BY id, phone_number",
+            BROWSER_PROFILE_COLUMNS
+        ))?;
+        let profiles = stmt
+            .query_map([], browser_profile_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(profiles)
+    }
+
+    async fn set_profile_health(&self, id: ChatId, phone: &str, health: ProfileHealth) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let previous: Option<String> = conn
+            .query_row(
+                "SELECT health FROM chrome_profiles WHERE id = ?1 AND phone_number = ?2",
+                params![id.0, phone],
+                |row| row.get(0),
+            )
+            .optional()?;
+        conn.execute(
+            "UPDATE chrome_profiles SET health = ?1, checked_at = ?2 WHERE id = ?3 AND phone_number = ?4",
+            params![health.as_str(), Utc::now().timestamp(), id.0, phone],
+        )?;
+        Ok(previous.is_some_and(|previous| previous != health.as_str()))
+    }
+
+    async fn delete_browser_profile(&self, id: ChatId, phone: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let deleted = conn.execute(
+            "DELETE FROM chrome_profiles WHERE id = ?1 AND phone_number = ?2",
+            params![id.0, phone],
+        )?;
+        conn.execute(
+            "UPDATE subscriptions SET auto_book_phone = NULL WHERE user_id = ?1 AND auto_book_phone = ?2",
+            params![id.0, phone],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    async fn add_subscription(
+        &self,
+        id: ChatId,
+        warehouse_id: i32,
+        warehouse_name: String,
+        box_type_name: String,
+        limits: SlotLimits,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT INTO subscriptions (user_id, warehouse_id, warehouse_name, box_type_name, max_coefficient, date_from, date_to)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![id.0, warehouse_id, warehouse_name, box_type_name, limits.max_coefficient, limits.date_from, limits.date_to],
+        )?;
+        Ok(())
+    }
+
+    async fn get_user_subscriptions(&self, id: ChatId) -> Result<Vec<Subscription>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM subscriptions WHERE user_id = ?1 ORDER BY warehouse_name",
+            SUBSCRIPTION_COLUMNS
+        ))?;
+        let subscriptions = stmt
+            .query_map(params![id.0], subscription_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(subscriptions)
+    }
+
+    async fn get_all_subscriptions(&self) -> Result<Vec<Subscription>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM subscriptions WHERE user_id NOT IN (SELECT id FROM users WHERE active = 0) ORDER BY user_id",
+            SUBSCRIPTION_COLUMNS
+        ))?;
+        let subscriptions = stmt
+            .query_map([], subscription_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(subscriptions)
+    }
+
+    async fn count_subscriptions(&self) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let count = conn.query_row("SELECT COUNT(*) FROM subscriptions", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    async fn delete_subscription(&self, id: ChatId, subscription_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let mut conn = conn.lock().await;
+        let tx = conn.transaction()?;
+        let deleted = tx.execute(
+            "DELETE FROM subscriptions WHERE id = ?1 AND user_id = ?2",
+            params![subscription_id, id.0],
+        )?;
+        // Подписка чужая или уже удалена: ее отметки об уведомлениях не трогаем
+        if deleted == 0 {
+            return Ok(());
+        }
+        tx.execute(
+            "DELETE FROM subscription_notifications WHERE subscription_id = ?1",
+            params![subscription_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn set_subscription_auto_book(&self, id: ChatId, subscription_id: i64, phone: Option<&str>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "UPDATE subscriptions SET auto_book_phone = ?1 WHERE id = ?2 AND user_id = ?3",
+            params![phone, subscription_id, id.0],
+        )?;
+        Ok(())
+    }
+
+    async fn get_subscription_auto_book(&self, subscription_id: i64) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let phone = conn
+            .query_row(
+                "SELECT auto_book_phone FROM subscriptions WHERE id = ?1",
+                params![subscription_id],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .optional()?;
+        Ok(phone.flatten())
+    }
+
+    async fn mark_slot_notified(&self, subscription_id: i64, date: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO subscription_notifications (subscription_id, date) VALUES (?1, ?2)",
+            params![subscription_id, date],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    async fn unmark_slot_notified(&self, subscription_id: i64, date: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "DELETE FROM subscription_notifications WHERE subscription_id = ?1 AND date = ?2",
+            params![subscription_id, date],
+        )?;
+        Ok(())
+    }
+
+    async fn create_preset(
+        &self,
+        id: ChatId,
+        name: String,
+        warehouse_id: i32,
+        box_type_name: String,
+        limits: SlotLimits,
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let mut conn = conn.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO presets (user_id, name, box_type_name, max_coefficient, date_from, date_to) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.0, name, box_type_name, limits.max_coefficient, limits.date_from, limits.date_to],
+        )?;
+        let preset_id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO preset_warehouses (preset_id, warehouse_id) VALUES (?1, ?2)",
+            params![preset_id, warehouse_id],
+        )?;
+        tx.commit()?;
+        Ok(preset_id)
+    }
+
+    async fn update_preset(&self, id: ChatId, preset_id: i64, name: String, limits: SlotLimits) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "UPDATE presets SET name = ?1, max_coefficient = ?2, date_from = ?3, date_to = ?4 WHERE id = ?5 AND user_id = ?6",
+            params![name, limits.max_coefficient, limits.date_from, limits.date_to, preset_id, id.0],
+        )?;
+        Ok(())
+    }
+
+    async fn add_warehouse_to_preset(&self, id: ChatId, preset_id: i64, warehouse_id: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT OR IGNORE INTO preset_warehouses (preset_id, warehouse_id)
+             SELECT id, ?1 FROM presets WHERE id = ?2 AND user_id = ?3",
+            params![warehouse_id, preset_id, id.0],
+        )?;
+        Ok(())
+    }
+
+    async fn remove_warehouse_from_preset(&self, id: ChatId, preset_id: i64, warehouse_id: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "DELETE FROM preset_warehouses WHERE preset_id = ?1 AND warehouse_id = ?2
+             AND EXISTS(SELECT 1 FROM presets WHERE id = ?1 AND user_id = ?3)",
+            params![preset_id, warehouse_id, id.0],
+        )?;
+        Ok(())
+    }
+
+    async fn get_preset(&self, id: ChatId, preset_id: i64) -> Result<Option<Preset>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let preset = conn
+            .query_row(
+                "SELECT id, name, box_type_name, max_coefficient, date_from, date_to FROM presets WHERE id = ?1 AND user_id = ?2",
+                params![preset_id, id.0],
+                |row| {
+                    Ok(Preset {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                        box_type_name: row.get(2)?,
+                        max_coefficient: row.get(3)?,
+                        date_from: row.get(4)?,
+                        date_to: row.get(5)?,
+                        warehouses: Vec::new(),
+                    })
+                },
+            )
+            .optional()?;
+        let Some(mut preset) = preset else {
+            return Ok(None);
+        };
+
+        let mut stmt = conn.prepare(
+            "SELECT p.warehouse_id, w.name FROM preset_warehouses p
+             LEFT JOIN warehouses w ON w.id = p.warehouse_id
+             WHERE p.preset_id = ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![preset_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        preset.warehouses = name_preset_warehouses(rows);
+        Ok(Some(preset))
+    }
+
+    async fn get_user_presets_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let offset = page * page_size;
+        let mut stmt = conn.prepare("SELECT id, name FROM presets WHERE user_id = ?1 ORDER BY name LIMIT ?2 OFFSET ?3")?;
+        let presets = stmt
+            .query_map(params![id.0, page_size, offset], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(presets)
+    }
+
+    async fn count_user_presets(&self, id: ChatId) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let count: i32 = conn.query_row("SELECT COUNT(*) FROM presets WHERE user_id = ?1", params![id.0], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    async fn get_user_presets_by_box_type(&self, id: ChatId, box_type_name: &str) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT id, name FROM presets WHERE user_id = ?1 AND box_type_name = ?2 ORDER BY name")?;
+        let presets = stmt
+            .query_map(params![id.0, box_type_name], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(presets)
+    }
+
+    async fn duplicate_preset(&self, id: ChatId, preset_id: i64) -> Result<Option<i64>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let mut conn = conn.lock().await;
+        let tx = conn.transaction()?;
+        let inserted = tx.execute(
+            "INSERT INTO presets (user_id, name, box_type_name, max_coefficient, date_from, date_to)
+             SELECT user_id, name || ' (копия)', box_type_name, max_coefficient, date_from, date_to
+             FROM presets WHERE id = ?1 AND user_id = ?2",
+            params![preset_id, id.0],
+        )?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+        let new_id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO preset_warehouses (preset_id, warehouse_id)
+             SELECT ?1, warehouse_id FROM preset_warehouses WHERE preset_id = ?2",
+            params![new_id, preset_id],
+        )?;
+        tx.commit()?;
+        Ok(Some(new_id))
+    }
+
+    async fn delete_preset(&self, id: ChatId, preset_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let mut conn = conn.lock().await;
+        let tx = conn.transaction()?;
+        let deleted = tx.execute("DELETE FROM presets WHERE id = ?1 AND user_id = ?2", params![preset_id, id.0])?;
+        // Пресет чужой или уже удален: его склады не трогаем
+        if deleted == 0 {
+            return Ok(());
+        }
+        tx.execute("DELETE FROM preset_warehouses WHERE preset_id = ?1", params![preset_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn start_cheapest_search(&self, member: Member, box_type_name: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO cheapest_searches (chat_id, user_id, box_type_name) VALUES (?1, ?2, ?3)",
+            params![member.chat.0, member.user.0, box_type_name],
+        )?;
+        Ok(())
+    }
+
+    async fn set_cheapest_search_warehouses(&self, member: Member, warehouse_ids: Option<Vec<u32>>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Склады хранятся через запятую
+        let warehouse_ids = warehouse_ids.map(|ids| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(","));
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "UPDATE cheapest_searches SET warehouse_ids = ?1 WHERE chat_id = ?2 AND user_id = ?3",
+            params![warehouse_ids, member.chat.0, member.user.0],
+        )?;
+        Ok(())
+    }
+
+    async fn set_cheapest_search_params(
+        &self,
+        member: Member,
+        limits: SlotLimits,
+        name_filter: Vec<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let name_filter = (!name_filter.is_empty()).then(|| name_filter.join(","));
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "UPDATE cheapest_searches SET max_coefficient = ?1, date_from = ?2, date_to = ?3, name_filter = ?4 WHERE chat_id = ?5 AND user_id = ?6",
+            params![limits.max_coefficient, limits.date_from, limits.date_to, name_filter, member.chat.0, member.user.0],
+        )?;
+        Ok(())
+    }
+
+    async fn get_cheapest_search(&self, member: Member) -> Result<Option<CheapestSearch>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let search = conn
+            .query_row(
+                "SELECT box_type_name, warehouse_ids, name_filter, max_coefficient, date_from, date_to FROM cheapest_searches WHERE chat_id = ?1 AND user_id = ?2",
+                params![member.chat.0, member.user.0],
+                |row| {
+                    let warehouse_ids: Option<String> = row.get(1)?;
+                    let name_filter: Option<String> = row.get(2)?;
+                    Ok(CheapestSearch {
+                        box_type_name: row.get(0)?,
+                        warehouse_ids: warehouse_ids.map(|ids| ids.split(',').filter_map(|id| id.parse().ok()).collect()),
+                        name_filter: name_filter
+                            .map(|filter| filter.split(',').map(|part| part.to_string()).collect())
+                            .unwrap_or_default(),
+                        max_coefficient: row.get(3)?,
+                        date_from: row.get(4)?,
+                        date_to: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(search)
+    }
+
+    async fn store_callback_payload(&self, payload: &str) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute("INSERT OR IGNORE INTO callback_payloads (payload) VALUES (?1)", params![payload])?;
+        let id: i64 = conn.query_row(
+            "SELECT id FROM callback_payloads WHERE payload = ?1",
+            params![payload],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    async fn load_callback_payload(&self, id: i64) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let payload = conn
+            .query_row("SELECT payload FROM callback_payloads WHERE id = ?1", params![id], |row| row.get(0))
+            .optional()?;
+        Ok(payload)
+    }
+
+    async fn create_broadcast(
+        &self,
+        admin_chat_id: ChatId,
+        text: &str,
+        photo: Option<String>,
+        buttons: &[(String, String)],
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT INTO broadcasts (admin_chat_id, text, photo, buttons, status, created_at) VALUES (?1, ?2, ?3, ?4, 'draft', ?5)",
+            params![admin_chat_id.0, text, photo, encode_broadcast_buttons(buttons), Utc::now().timestamp()],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    async fn get_broadcast(&self, id: i64) -> Result<Option<Broadcast>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let broadcast = conn
+            .query_row(
+                &format!("SELECT {} FROM broadcasts WHERE id = ?1", BROADCAST_COLUMNS),
+                params![id],
+                broadcast_from_row,
+            )
+            .optional()?;
+        Ok(broadcast)
+    }
+
+    async fn get_sending_broadcast(&self) -> Result<Option<Broadcast>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let broadcast = conn
+            .query_row(
+                &format!("SELECT {} FROM broadcasts WHERE status = 'sending' ORDER BY id LIMIT 1", BROADCAST_COLUMNS),
+                [],
+                broadcast_from_row,
+            )
+            .optional()?;
+        Ok(broadcast)
+    }
+
+    async fn start_broadcast(&self, id: i64, progress_message_id: i32, recipients: &[i64]) -> Result<Option<usize>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let mut conn = conn.lock().await;
+        let tx = conn.transaction()?;
+        let started = tx.execute(
+            "UPDATE broadcasts SET status = 'sending', progress_message_id = ?1 WHERE id = ?2 AND status = 'draft'",
+            params![progress_message_id, id],
+        )?;
+        if started == 0 {
+            return Ok(None);
+        }
+        for chat_id in recipients {
+            tx.execute(
+                "INSERT OR IGNORE INTO broadcast_recipients (broadcast_id, chat_id, status) VALUES (?1, ?2, 'pending')",
+                params![id, chat_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(Some(recipients.len()))
+    }
+
+    async fn cancel_broadcast(&self, id: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let cancelled = conn.execute(
+            "UPDATE broadcasts SET status = 'cancelled' WHERE id = ?1 AND status IN ('draft', 'sending')",
+            params![id],
+        )?;
+        Ok(cancelled > 0)
+    }
+
+    async fn finish_broadcast(&self, id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute("UPDATE broadcasts SET status = 'done' WHERE id = ?1 AND status = 'sending'", params![id])?;
+        Ok(())
+    }
+
+    async fn get_pending_recipients(&self, id: i64, limit: i64) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT chat_id FROM broadcast_recipients WHERE broadcast_id = ?1 AND status = 'pending' ORDER BY chat_id LIMIT ?2",
+        )?;
+        let recipients = stmt
+            .query_map(params![id, limit], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        Ok(recipients)
+    }
+
+    async fn set_recipient_status(&self, id: i64, chat_id: i64, status: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "UPDATE broadcast_recipients SET status = ?1 WHERE broadcast_id = ?2 AND chat_id = ?3",
+            params![status, id, chat_id],
+        )?;
+        Ok(())
+    }
+
+    async fn get_broadcast_stats(&self, id: i64) -> Result<BroadcastStats, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare("SELECT status, COUNT(*) FROM broadcast_recipients WHERE broadcast_id = ?1 GROUP BY status")?;
+        let counts = stmt
+            .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, i64)>>>()?;
+        Ok(broadcast_stats(counts))
+    }
+
+    async fn record_usage_event(&self, kind: &str, chat_id: ChatId, user_id: Option<UserId>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT INTO usage_events (kind, chat_id, user_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![kind, chat_id.0, user_id.map(|id| id.0), Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    async fn record_api_call(&self, endpoint: &str, status: Option<u16>, ok: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute(
+            "INSERT INTO api_calls (endpoint, status, ok, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![endpoint, status, ok, Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    async fn count_active_users_since(&self, since: i64) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let count = conn.query_row(
+            "SELECT COUNT(DISTINCT user_id) FROM usage_events WHERE kind != 'alert' AND created_at >= ?1",
+            params![since],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    async fn count_usage_events(&self, kind: &str, since: i64) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let count = conn.query_row(
+            "SELECT COUNT(*) FROM usage_events WHERE kind = ?1 AND created_at >= ?2",
+            params![kind, since],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    async fn get_api_call_stats(&self, since: i64) -> Result<Vec<ApiCallStats>, Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT endpoint, COUNT(*), COUNT(*) - SUM(ok) FROM api_calls WHERE created_at >= ?1 GROUP BY endpoint ORDER BY endpoint",
+        )?;
+        let stats = stmt
+            .query_map(params![since], |row| {
+                Ok(ApiCallStats { endpoint: row.get(0)?, calls: row.get(1)?, errors: row.get(2)? })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(stats)
+    }
+
+    async fn database_size(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        self.pool.size()
+    }
+
+    async fn delete_expired_events(&self, now: i64, stats_border: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.pool.connection();
+        let conn = conn.lock().await;
+        conn.execute("DELETE FROM subscription_notifications WHERE date < ?1", params![now])?;
+        conn.execute("DELETE FROM usage_events WHERE created_at < ?1", params![stats_border])?;
+        conn.execute("DELETE FROM api_calls WHERE created_at < ?1", params![stats_border])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::storage::tests::{
+        check_broadcasts_and_stats, check_presets_and_cheapest_search, check_profiles_and_roles, check_subscriptions,
+        check_users_and_states, check_warehouses_and_coefficients,
+    };
+
+    // Отдельный файл на тест: пул держит несколько соединений, а у каждого соединения с :memory: своя база.
+    // Другие модули открывают так же свое хранилище для тестов
+    pub fn open_temp(name: &str) -> (SqliteStorage, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("storage_{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        (SqliteStorage::open(path.to_str().unwrap()).unwrap(), path)
+    }
+
+    pub fn remove_temp(path: std::path::PathBuf) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn users_and_states() {
+        let (storage, path) = open_temp("users");
+        check_users_and_states(&storage).await;
+        remove_temp(path);
+    }
+
+    #[tokio::test]
+    async fn warehouses_and_coefficients() {
+        let (storage, path) = open_temp("warehouses");
+        check_warehouses_and_coefficients(&storage).await;
+        remove_temp(path);
+    }
+
+    #[tokio::test]
+    async fn profiles_and_roles() {
+        let (storage, path) = open_temp("profiles");
+        check_profiles_and_roles(&storage).await;
+        remove_temp(path);
+    }
+
+    #[tokio::test]
+    async fn subscriptions() {
+        let (storage, path) = open_temp("subscriptions");
+        check_subscriptions(&storage).await;
+        remove_temp(path);
+    }
+
+    #[tokio::test]
+    async fn presets_and_cheapest_search() {
+        let (storage, path) = open_temp("presets");
+        check_presets_and_cheapest_search(&storage).await;
+        remove_temp(path);
+    }
+
+    #[tokio::test]
+    async fn broadcasts_and_stats() {
+        let (storage, path) = open_temp("broadcasts");
+        check_broadcasts_and_stats(&storage).await;
+        remove_temp(path);
+    }
+}
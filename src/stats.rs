@@ -0,0 +1,125 @@
+use chrono::{Duration, Utc};
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::database::ApiCallStats;
+use crate::storage::Storage;
+use crate::token_decoder::token_status;
+
+// Сводка для /stats
+pub struct BotStats {
+    pub total_users: i64,
+    pub active_users: i64, // не заблокировали бота
+    pub active_24h: i64,
+    pub active_7d: i64,
+    pub users_with_valid_token: usize,
+    pub subscriptions: i64,
+    pub alerts_24h: i64,
+    pub alerts_7d: i64,
+    pub api_calls_24h: Vec<ApiCallStats>,
+    pub db_size_bytes: u64,
+}
+
+pub async fn collect_stats(storage: &dyn Storage) -> Result<BotStats, Box<dyn Error + Send + Sync>> {
+    let now = Utc::now();
+    let day_ago = (now - Duration::days(1)).timestamp();
+    let week_ago = (now - Duration::days(7)).timestamp();
+
+    let (total_users, active_users) = storage.count_users().await?;
+    // Токен считаем рабочим, если хотя бы у одного кабинета чата он не просрочен
+    let users_with_valid_token = storage.get_all_cabinets()
+        .await?
+        .into_iter()
+        .filter(|cabinet| token_status(&cabinet.token).is_usable())
+        .map(|cabinet| cabinet.chat_id)
+        .collect::<HashSet<_>>()
+        .len();
+
+    Ok(BotStats {
+        total_users,
+        active_users,
+        active_24h: storage.count_active_users_since(day_ago).await?,
+        active_7d: storage.count_active_users_since(week_ago).await?,
+        users_with_valid_token,
+        subscriptions: storage.count_subscriptions().await?,
+        alerts_24h: storage.count_usage_events("alert", day_ago).await?,
+        alerts_7d: storage.count_usage_events("alert", week_ago).await?,
+        api_calls_24h: storage.get_api_call_stats(day_ago).await?,
+        db_size_bytes: storage.database_size().await?,
+    })
+}
+
+fn error_rate(calls: i64, errors: i64) -> f64 {
+    if calls == 0 {
+        0.0
+    } else {
+        errors as f64 * 100.0 / calls as f64
+    }
+}
+
+pub fn format_stats(stats: &BotStats) -> String {
+    let mut text = format!(
+        "📊 Статистика бота\n\n👥 Пользователи: {} (не заблокировали бота: {})\nАктивны за 24ч: {}, за 7д: {}\n🔑 С действующим токеном: {}\n\n🔔 Подписок: {}\nУведомлений за 24ч: {}, за 7д: {}\n\n🌐 Запросы к WB API за 24ч:",
+        stats.total_users,
+        stats.active_users,
+        stats.active_24h,
+        stats.active_7d,
+        stats.users_with_valid_token,
+        stats.subscriptions,
+        stats.alerts_24h,
+        stats.alerts_7d,
+    );
+    if stats.api_calls_24h.is_empty() {
+        text.push_str(" не было");
+    }
+    let (mut calls, mut errors) = (0, 0);
+    for endpoint in &stats.api_calls_24h {
+        calls += endpoint.calls;
+        errors += endpoint.errors;
+        text.push_str(&format!(
+            "\n{}: {}, ошибок {:.1}%",
+            endpoint.endpoint,
+            endpoint.calls,
+            error_rate(endpoint.calls, endpoint.errors)
+        ));
+    }
+    if stats.api_calls_24h.len() > 1 {
+        text.push_str(&format!("\nВсего: {}, ошибок {:.1}%", calls, error_rate(calls, errors)));
+    }
+    text.push_str(&format!("\n\n💾 База данных: {:.1} МБ", stats.db_size_bytes as f64 / (1024.0 * 1024.0)));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_api_error_rates() {
+        let stats = BotStats {
+            total_users: 10,
+            active_users: 8,
+            active_24h: 3,
+            active_7d: 5,
+            users_with_valid_token: 4,
+            subscriptions: 7,
+            alerts_24h: 2,
+            alerts_7d: 9,
+            api_calls_24h: vec![
+                ApiCallStats { endpoint: "/api/v1/acceptance/coefficients".to_string(), calls: 40, errors: 2 },
+                ApiCallStats { endpoint: "/ping".to_string(), calls: 10, errors: 0 },
+            ],
+            db_size_bytes: 3 * 1024 * 1024 / 2,
+        };
+        let text = format_stats(&stats);
+        assert!(text.contains("Пользователи: 10 (не заблокировали бота: 8)"));
+        assert!(text.contains("/api/v1/acceptance/coefficients: 40, ошибок 5.0%"));
+        assert!(text.contains("Всего: 50, ошибок 4.0%"));
+        assert!(text.contains("База данных: 1.5 МБ"));
+    }
+
+    #[test]
+    fn no_api_calls_is_not_division_by_zero() {
+        assert_eq!(error_rate(0, 0), 0.0);
+    }
+}
@@ -0,0 +1,617 @@
+// Хранилище всех данных бота: пользователи, диалоги, кабинеты с токенами, склады и коэффициенты,
+// подписки, пресеты, профили браузера, роли, рассылки и статистика.
+// По умолчанию это файл SQLite, с фичей postgres и DATABASE_URL - общая база PostgreSQL,
+// чтобы несколько экземпляров бота работали с одними данными.
+// Обработчики получают хранилище через зависимости dptree, фоновые задачи - при запуске
+use async_trait::async_trait;
+use std::error::Error;
+use std::sync::Arc;
+use teloxide::types::{ChatId, UserId};
+
+use crate::api_reauests::{CoefficientResponse, Warehouse};
+use crate::database::{
+    ApiCallStats, BrowserProfile, Broadcast, BroadcastStats, CachedSlot, Cabinet, CheapestSearch, CoefficientChange, Member,
+    Preset, ProfileHealth, SlotLimits, Subscription, DEFAULT_CABINET_NAME,
+};
+use crate::sqlite_storage::SqliteStorage;
+use crate::token_crypto::token_cipher;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // Пользователи
+    async fn add_user(&self, id: ChatId, username: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn set_user_active(&self, id: ChatId, active: bool) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Получатели рассылки: все, кто не заблокировал бота, кроме отправителя
+    async fn get_active_users(&self, exclude: ChatId) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>>;
+    // Всего пользователей и сколько из них не заблокировали бота
+    async fn count_users(&self) -> Result<(i64, i64), Box<dyn Error + Send + Sync>>;
+    async fn get_id_by_username(&self, username: String) -> Result<i64, Box<dyn Error + Send + Sync>>;
+
+    // Диалог участника в JSON. Состояния разбирает и сохраняет dialogue::MemberStorage
+    async fn get_dialogue(&self, member: Member) -> Result<Option<String>, Box<dyn Error + Send + Sync>>;
+    async fn update_dialogue(&self, member: Member, dialogue: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn remove_dialogue(&self, member: Member) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    // Кабинеты продавца. Токены хранятся зашифрованными, наружу отдаются расшифрованными.
+    // Создает кабинет и делает его активным. Возвращает id кабинета
+    async fn create_cabinet(&self, id: ChatId, name: String, token: String) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    async fn rename_cabinet(&self, id: ChatId, cabinet_id: i64, name: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn update_cabinet_token(&self, id: ChatId, cabinet_id: i64, token: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn get_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<Option<Cabinet>, Box<dyn Error + Send + Sync>>;
+    async fn get_user_cabinets(&self, id: ChatId) -> Result<Vec<Cabinet>, Box<dyn Error + Send + Sync>>;
+    // Все кабинеты всех пользователей. Кабинеты, которые не удалось расшифровать, пропускаем
+    async fn get_all_cabinets(&self) -> Result<Vec<Cabinet>, Box<dyn Error + Send + Sync>>;
+    // Активный кабинет пользователя. Если активный не выбран или удален - первый из кабинетов
+    async fn get_active_cabinet(&self, id: ChatId) -> Result<Option<Cabinet>, Box<dyn Error + Send + Sync>>;
+    async fn set_active_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn delete_cabinet(&self, id: ChatId, cabinet_id: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Шифрует текущим ключом все токены, сохраненные открытым текстом или старым ключом.
+    // Возвращает количество перешифрованных записей
+    async fn reencrypt_user_tokens(&self) -> Result<usize, Box<dyn Error + Send + Sync>>;
+    // Отмечаем напоминание о сроке токена. Возвращает true, если такого напоминания еще не было
+    async fn mark_token_reminder_sent(&self, chat_id: i64, exp: i64, kind: &str) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    // Снимает отметку, если напоминание не удалось отправить
+    async fn unmark_token_reminder_sent(&self, chat_id: i64, exp: i64, kind: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    // Сохраняет токен в активный кабинет, а если кабинетов еще нет - создает кабинет по умолчанию
+    async fn set_user_token(&self, id: ChatId, token: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.get_active_cabinet(id).await? {
+            Some(cabinet) => self.update_cabinet_token(id, cabinet.id, token).await,
+            None => self.create_cabinet(id, DEFAULT_CABINET_NAME.to_string(), token).await.map(|_| ()),
+        }
+    }
+
+    // Токен активного кабинета. Пустая строка, если кабинетов нет
+    async fn get_user_token(&self, id: ChatId) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.get_active_cabinet(id).await?.map(|c| c.token).unwrap_or_default())
+    }
+
+    // Склады
+    async fn insert_warehouses(&self, warehouses: Vec<Warehouse>) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Страница складов, избранные склады пользователя идут первыми. Возвращает (склад, в избранном ли)
+    async fn get_warehouses_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<(Warehouse, bool)>, Box<dyn Error + Send + Sync>>;
+    async fn get_all_warehouses(&self) -> Result<Vec<Warehouse>, Box<dyn Error + Send + Sync>>;
+    async fn count_warehouses(&self) -> Result<i32, Box<dyn Error + Send + Sync>>;
+    async fn get_warehouse_name(&self, warehouse_id: i32) -> Result<Option<String>, Box<dyn Error + Send + Sync>>;
+    // Добавляет склад в избранное или убирает из него. Возвращает true, если склад теперь в избранном
+    async fn toggle_favourite_warehouse(&self, id: ChatId, warehouse_id: i32) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    async fn is_favourite_warehouse(&self, id: ChatId, warehouse_id: i32) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    async fn get_favourite_warehouses(&self, id: ChatId) -> Result<Vec<Warehouse>, Box<dyn Error + Send + Sync>>;
+
+    // Коэффициенты: кэш последних значений и история изменений
+    async fn add_or_update_warehouse_coefficents(&self, coefficients: Vec<CoefficientResponse>) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // История изменений коэффициентов склада и типа поставки для дат не раньше date_from,
+    // по возрастанию даты поставки и времени наблюдения
+    async fn get_coefficient_history(
+        &self,
+        warehouse_id: i32,
+        box_type_name: &str,
+        date_from: i64,
+    ) -> Result<Vec<CoefficientChange>, Box<dyn Error + Send + Sync>>;
+    // Коэффициенты склада и типа поставки за период [date_from, date_to), включая недоступные (-1)
+    async fn get_warehouse_coefficients(
+        &self,
+        warehouse_id: i32,
+        box_type_name: &str,
+        date_from: i64,
+        date_to: i64,
+    ) -> Result<Vec<(i64, i32)>, Box<dyn Error + Send + Sync>>;
+    // Доступные даты склада и типа поставки, от самого низкого коэффициента
+    async fn get_available_coefficients(&self, warehouse_id: i32, box_type_name: &str) -> Result<Vec<(i64, i32)>, Box<dyn Error + Send + Sync>>;
+    async fn get_unique_box_types(&self, warehouse_id: i32) -> Result<Vec<String>, Box<dyn Error + Send + Sync>>;
+    // Все закэшированные коэффициенты по типу поставки
+    async fn get_box_type_slots(&self, box_type_name: &str) -> Result<Vec<CachedSlot>, Box<dyn Error + Send + Sync>>;
+    // Типы поставки, по которым есть коэффициенты в кэше
+    async fn get_all_box_types(&self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>>;
+    // Удаляет прошедшие слоты из кэша и историю, наблюдавшуюся раньше history_border
+    async fn delete_expired_coefficients(&self, now: i64, history_border: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    // Роли администраторов, выданные командой /grantrole. Владельцы из настроек сюда не пишутся
+    async fn set_admin_role(&self, id: UserId, role: &str, granted_by: UserId) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Возвращает true, если роль была
+    async fn delete_admin_role(&self, id: UserId) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    async fn get_admin_role(&self, id: UserId) -> Result<Option<String>, Box<dyn Error + Send + Sync>>;
+    // Все выданные роли с именами пользователей, если они запускали бота
+    async fn get_admins(&self) -> Result<Vec<(i64, String, Option<String>)>, Box<dyn Error + Send + Sync>>;
+
+    // Профили браузера с выполненным входом в кабинет по номеру phone (10 цифр без +7)
+    async fn save_browser_profile(&self, id: ChatId, phone: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn get_browser_profile(&self, id: ChatId, phone: &str) -> Result<Option<BrowserProfile>, Box<dyn Error + Send + Sync>>;
+    // Страница номеров чата
+    async fn get_user_browser_profiles_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<String>, Box<dyn Error + Send + Sync>>;
+    async fn count_user_numbers(&self, id: ChatId) -> Result<i32, Box<dyn Error + Send + Sync>>;
+    async fn get_all_browser_profiles(&self) -> Result<Vec<BrowserProfile>, Box<dyn Error + Send + Sync>>;
+    // Записывает результат проверки профиля. Возвращает true, если состояние изменилось
+    async fn set_profile_health(&self, id: ChatId, phone: &str, health: ProfileHealth) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    // Удаляет профиль из чата и выключает автобронирование с ним. Возвращает true, если профиль был в чате
+    async fn delete_browser_profile(&self, id: ChatId, phone: &str) -> Result<bool, Box<dyn Error + Send + Sync>>;
+
+    // Подписки на коэффициенты. Название склада сохраняется в подписке на момент создания
+    async fn add_subscription(
+        &self,
+        id: ChatId,
+        warehouse_id: i32,
+        warehouse_name: String,
+        box_type_name: String,
+        limits: SlotLimits,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn get_user_subscriptions(&self, id: ChatId) -> Result<Vec<Subscription>, Box<dyn Error + Send + Sync>>;
+    // Подписки для проверки: без чатов, которые заблокировали бота (они снова активны после /start)
+    async fn get_all_subscriptions(&self) -> Result<Vec<Subscription>, Box<dyn Error + Send + Sync>>;
+    async fn count_subscriptions(&self) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    async fn delete_subscription(&self, id: ChatId, subscription_id: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Включает автобронирование с профилем номера phone или выключает его (None)
+    async fn set_subscription_auto_book(&self, id: ChatId, subscription_id: i64, phone: Option<&str>) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn get_subscription_auto_book(&self, subscription_id: i64) -> Result<Option<String>, Box<dyn Error + Send + Sync>>;
+    // Отмечаем, что о слоте уже сообщили. Возвращает true, если слот новый
+    async fn mark_slot_notified(&self, subscription_id: i64, date: i64) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    // Снимаем отметку, если уведомление о слоте не дошло: сообщим при следующей проверке
+    async fn unmark_slot_notified(&self, subscription_id: i64, date: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    // Пресеты: именованные наборы складов с условиями отбора слотов. Возвращает id пресета
+    async fn create_preset(
+        &self,
+        id: ChatId,
+        name: String,
+        warehouse_id: i32,
+        box_type_name: String,
+        limits: SlotLimits,
+    ) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    async fn update_preset(&self, id: ChatId, preset_id: i64, name: String, limits: SlotLimits) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn add_warehouse_to_preset(&self, id: ChatId, preset_id: i64, warehouse_id: i32) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn remove_warehouse_from_preset(&self, id: ChatId, preset_id: i64, warehouse_id: i32) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Пресет вместе со складами по названию. Склад, которого нет в списке складов, называется по id
+    async fn get_preset(&self, id: ChatId, preset_id: i64) -> Result<Option<Preset>, Box<dyn Error + Send + Sync>>;
+    // Страница пресетов пользователя: (id, название)
+    async fn get_user_presets_page(&self, id: ChatId, page: i32, page_size: i32) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>>;
+    async fn count_user_presets(&self, id: ChatId) -> Result<i32, Box<dyn Error + Send + Sync>>;
+    // Пресеты пользователя с нужным типом поставки: (id, название)
+    async fn get_user_presets_by_box_type(&self, id: ChatId, box_type_name: &str) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>>;
+    // Копия пресета со складами. None, если пресет не найден
+    async fn duplicate_preset(&self, id: ChatId, preset_id: i64) -> Result<Option<i64>, Box<dyn Error + Send + Sync>>;
+    async fn delete_preset(&self, id: ChatId, preset_id: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    // Поиск самой дешевой приемки участника. Новый поиск начинается с типа поставки, остальное сбрасывается
+    async fn start_cheapest_search(&self, member: Member, box_type_name: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn set_cheapest_search_warehouses(&self, member: Member, warehouse_ids: Option<Vec<u32>>) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn set_cheapest_search_params(
+        &self,
+        member: Member,
+        limits: SlotLimits,
+        name_filter: Vec<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn get_cheapest_search(&self, member: Member) -> Result<Option<CheapestSearch>, Box<dyn Error + Send + Sync>>;
+
+    // Длинные callback данные, не влезающие в лимит Telegram. Одинаковые данные получают один id
+    async fn store_callback_payload(&self, payload: &str) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    async fn load_callback_payload(&self, id: i64) -> Result<Option<String>, Box<dyn Error + Send + Sync>>;
+
+    // Рассылки: draft -> sending -> done, отменить можно до завершения
+    async fn create_broadcast(
+        &self,
+        admin_chat_id: ChatId,
+        text: &str,
+        photo: Option<String>,
+        buttons: &[(String, String)],
+    ) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    async fn get_broadcast(&self, id: i64) -> Result<Option<Broadcast>, Box<dyn Error + Send + Sync>>;
+    // Самая старая рассылка, которая еще отправляется (в том числе прерванная перезапуском)
+    async fn get_sending_broadcast(&self) -> Result<Option<Broadcast>, Box<dyn Error + Send + Sync>>;
+    // Ставит черновик в очередь вместе со списком получателей. None, если рассылка уже запущена или отменена
+    async fn start_broadcast(&self, id: i64, progress_message_id: i32, recipients: &[i64]) -> Result<Option<usize>, Box<dyn Error + Send + Sync>>;
+    // Возвращает true, если рассылка еще не была завершена или отменена
+    async fn cancel_broadcast(&self, id: i64) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    async fn finish_broadcast(&self, id: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn get_pending_recipients(&self, id: i64, limit: i64) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>>;
+    // status: sent, blocked или failed
+    async fn set_recipient_status(&self, id: i64, chat_id: i64, status: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+    async fn get_broadcast_stats(&self, id: i64) -> Result<BroadcastStats, Box<dyn Error + Send + Sync>>;
+
+    // Статистика для /stats. Событие: command, message, callback или alert (уведомление по подписке)
+    async fn record_usage_event(&self, kind: &str, chat_id: ChatId, user_id: Option<UserId>) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Один HTTP-запрос к WB API. status отсутствует при сетевой ошибке
+    async fn record_api_call(&self, endpoint: &str, status: Option<u16>, ok: bool) -> Result<(), Box<dyn Error + Send + Sync>>;
+    // Сколько разных пользователей писали боту или нажимали кнопки начиная с since
+    async fn count_active_users_since(&self, since: i64) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    async fn count_usage_events(&self, kind: &str, since: i64) -> Result<i64, Box<dyn Error + Send + Sync>>;
+    async fn get_api_call_stats(&self, since: i64) -> Result<Vec<ApiCallStats>, Box<dyn Error + Send + Sync>>;
+    // Размер базы в байтах
+    async fn database_size(&self) -> Result<u64, Box<dyn Error + Send + Sync>>;
+    // Удаляет отметки об уведомлениях о прошедших слотах и статистику старше stats_border
+    async fn delete_expired_events(&self, now: i64, stats_border: i64) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+// Кабинет из базы: (id, chat_id, name, token в том виде, в котором сохранен)
+pub type StoredCabinet = (i64, i64, String, String);
+
+pub fn decrypt_cabinet((id, chat_id, name, stored): StoredCabinet) -> Result<Cabinet, Box<dyn Error + Send + Sync>> {
+    Ok(Cabinet {
+        id,
+        chat_id,
+        name,
+        token: token_cipher()?.decrypt(&stored)?,
+    })
+}
+
+// Расшифровывает кабинеты, пропуская те, что не расшифровались (например, ключ потерян)
+pub fn decrypt_cabinets_skipping_broken(rows: Vec<StoredCabinet>) -> Vec<Cabinet> {
+    let mut cabinets = Vec::new();
+    for row in rows {
+        let (cabinet_id, chat_id) = (row.0, row.1);
+        match decrypt_cabinet(row) {
+            Ok(cabinet) => cabinets.push(cabinet),
+            Err(e) => eprintln!("Не удалось расшифровать токен кабинета {} пользователя {}: {}", cabinet_id, chat_id, e),
+        }
+    }
+    cabinets
+}
+
+// Склады пресета: (id, название из списка складов). Склады без названия называем по id, сортируем по названию
+pub fn name_preset_warehouses(rows: Vec<(u32, Option<String>)>) -> Vec<Warehouse> {
+    let mut warehouses: Vec<Warehouse> = rows
+        .into_iter()
+        .map(|(id, name)| Warehouse { id, name: name.unwrap_or_else(|| format!("Склад {}", id)) })
+        .collect();
+    warehouses.sort_by(|a, b| a.name.cmp(&b.name));
+    warehouses
+}
+
+// Число получателей рассылки по статусам: (статус, количество)
+pub fn broadcast_stats(counts: Vec<(String, i64)>) -> BroadcastStats {
+    let mut stats = BroadcastStats::default();
+    for (status, count) in counts {
+        stats.total += count;
+        match status.as_str() {
+            "sent" => stats.sent = count,
+            "blocked" => stats.blocked = count,
+            "failed" => stats.failed = count,
+            _ => {}
+        }
+    }
+    stats
+}
+
+// Открывает хранилище по переменным окружения: DATABASE_URL (postgres://...) или файл SQLite из DB_PATH
+pub async fn open_from_env(db_path: &str) -> Result<Arc<dyn Storage>, Box<dyn Error + Send + Sync>> {
+    match std::env::var("DATABASE_URL").ok().filter(|url| !url.trim().is_empty()) {
+        #[cfg(feature = "postgres")]
+        Some(url) => Ok(Arc::new(crate::postgres_storage::PostgresStorage::connect(&url).await?)),
+        #[cfg(not(feature = "postgres"))]
+        Some(_) => Err("DATABASE_URL задан, но бот собран без поддержки PostgreSQL (cargo build --features postgres)".into()),
+        None => Ok(Arc::new(SqliteStorage::open(db_path)?)),
+    }
+}
+
+// Общие проверки для всех реализаций хранилища
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn coefficient(date: &str, warehouse_id: u32, box_type_name: &str, coefficient: i32) -> CoefficientResponse {
+        CoefficientResponse {
+            date: date.to_string(),
+            coefficient,
+            warehouse_id,
+            warehouse_name: format!("Склад {}", warehouse_id),
+            box_type_name: box_type_name.to_string(),
+            box_type_id: None,
+        }
+    }
+
+    pub async fn check_users_and_states(storage: &dyn Storage) {
+        storage.add_user(ChatId(1), "first".to_string()).await.unwrap();
+        storage.add_user(ChatId(2), "second".to_string()).await.unwrap();
+        storage.add_user(ChatId(3), "third".to_string()).await.unwrap();
+        storage.set_user_active(ChatId(3), false).await.unwrap();
+        assert_eq!(storage.count_users().await.unwrap(), (3, 2));
+        assert_eq!(storage.get_active_users(ChatId(1)).await.unwrap(), vec![2]);
+        assert_eq!(storage.get_id_by_username("second".to_string()).await.unwrap(), 2);
+        assert!(storage.get_id_by_username("nobody".to_string()).await.is_err());
+        // Повторный /start возвращает пользователя в рассылки
+        storage.add_user(ChatId(3), "third".to_string()).await.unwrap();
+        assert_eq!(storage.count_users().await.unwrap(), (3, 3));
+
+        let member = Member { chat: ChatId(-100), user: teloxide::types::UserId(1) };
+        let other = Member { chat: ChatId(-100), user: teloxide::types::UserId(2) };
+        assert_eq!(storage.get_dialogue(member).await.unwrap(), None);
+        storage.update_dialogue(member, r#""AwaitingNumber""#.to_string()).await.unwrap();
+        storage.update_dialogue(member, r#"{"AwaitingSmsCode":{"phone":"9991234567"}}"#.to_string()).await.unwrap();
+        assert_eq!(
+            storage.get_dialogue(member).await.unwrap().as_deref(),
+            Some(r#"{"AwaitingSmsCode":{"phone":"9991234567"}}"#)
+        );
+        assert_eq!(storage.get_dialogue(other).await.unwrap(), None);
+        storage.remove_dialogue(member).await.unwrap();
+        assert_eq!(storage.get_dialogue(member).await.unwrap(), None);
+
+        assert!(storage.mark_token_reminder_sent(1, 100, "soon").await.unwrap());
+        assert!(!storage.mark_token_reminder_sent(1, 100, "soon").await.unwrap());
+        assert!(storage.mark_token_reminder_sent(1, 100, "expired").await.unwrap());
+        storage.unmark_token_reminder_sent(1, 100, "soon").await.unwrap();
+        assert!(storage.mark_token_reminder_sent(1, 100, "soon").await.unwrap());
+        assert!(!storage.mark_token_reminder_sent(1, 100, "expired").await.unwrap());
+    }
+
+    pub async fn check_warehouses_and_coefficients(storage: &dyn Storage) {
+        let warehouses = vec![
+            Warehouse { id: 507, name: "Коледино".to_string() },
+            Warehouse { id: 117986, name: "Казань".to_string() },
+            Warehouse { id: 1733, name: "Екатеринбург".to_string() },
+        ];
+        storage.insert_warehouses(warehouses).await.unwrap();
+        storage.insert_warehouses(vec![Warehouse { id: 507, name: "Коледино КБТ".to_string() }]).await.unwrap();
+        assert_eq!(storage.count_warehouses().await.unwrap(), 3);
+        assert_eq!(storage.get_warehouse_name(507).await.unwrap().as_deref(), Some("Коледино КБТ"));
+        assert_eq!(storage.get_warehouse_name(1).await.unwrap(), None);
+
+        // Избранные склады идут в начале списка, даже если по алфавиту они последние
+        let page = |chat: i64, page: i32| async move {
+            storage
+                .get_warehouses_page(ChatId(chat), page, 2)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|(w, favourite)| (w.id, favourite))
+                .collect::<Vec<(u32, bool)>>()
+        };
+        assert!(storage.toggle_favourite_warehouse(ChatId(1), 507).await.unwrap());
+        assert!(storage.toggle_favourite_warehouse(ChatId(1), 117986).await.unwrap());
+        assert!(storage.is_favourite_warehouse(ChatId(1), 507).await.unwrap());
+        assert!(!storage.is_favourite_warehouse(ChatId(2), 507).await.unwrap());
+        assert_eq!(page(1, 0).await, vec![(117986, true), (507, true)]);
+        assert_eq!(page(1, 1).await, vec![(1733, false)]);
+        // У другого чата свое избранное
+        assert_eq!(page(2, 0).await, vec![(1733, false), (117986, false)]);
+
+        let favourites: Vec<(u32, String)> = storage
+            .get_favourite_warehouses(ChatId(1))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|w| (w.id, w.name))
+            .collect();
+        assert_eq!(favourites, vec![(117986, "Казань".to_string()), (507, "Коледино КБТ".to_string())]);
+        assert!(!storage.toggle_favourite_warehouse(ChatId(1), 117986).await.unwrap());
+        assert_eq!(page(1, 0).await, vec![(507, true), (1733, false)]);
+        assert!(!storage.toggle_favourite_warehouse(ChatId(1), 507).await.unwrap());
+        assert!(storage.get_favourite_warehouses(ChatId(1)).await.unwrap().is_empty());
+
+        let day = "2030-01-10T00:00:00Z";
+        let next_day = "2030-01-11T00:00:00Z";
+        storage
+            .add_or_update_warehouse_coefficents(vec![
+                coefficient(day, 507, "Короба", 5),
+                coefficient(next_day, 507, "Короба", -1),
+                coefficient(day, 507, "Монопаллеты", 0),
+            ])
+            .await
+            .unwrap();
+        // Повтор того же значения в историю не пишется, изменение - пишется
+        storage.add_or_update_warehouse_coefficents(vec![coefficient(day, 507, "Короба", 5)]).await.unwrap();
+        storage.add_or_update_warehouse_coefficents(vec![coefficient(day, 507, "Короба", 1)]).await.unwrap();
+
+        let day_ts = 1894233600;
+        let history: Vec<i32> = storage
+            .get_coefficient_history(507, "Короба", day_ts)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|change| change.coefficient)
+            .collect();
+        assert_eq!(history, vec![5, 1, -1]);
+        assert_eq!(
+            storage.get_warehouse_coefficients(507, "Короба", day_ts, day_ts + 2 * 86400).await.unwrap(),
+            vec![(day_ts, 1), (day_ts + 86400, -1)]
+        );
+        assert_eq!(storage.get_available_coefficients(507, "Короба").await.unwrap(), vec![(day_ts, 1)]);
+        assert_eq!(storage.get_unique_box_types(507).await.unwrap(), vec!["Короба", "Монопаллеты"]);
+        assert_eq!(storage.get_all_box_types().await.unwrap(), vec!["Короба", "Монопаллеты"]);
+        assert_eq!(storage.get_box_type_slots("Монопаллеты").await.unwrap().len(), 1);
+
+        storage.delete_expired_coefficients(day_ts + 1, day_ts + 1).await.unwrap();
+        assert_eq!(storage.get_box_type_slots("Короба").await.unwrap().len(), 1);
+        assert!(storage.get_coefficient_history(507, "Короба", 0).await.unwrap().is_empty());
+    }
+
+    pub async fn check_profiles_and_roles(storage: &dyn Storage) {
+        storage.save_browser_profile(ChatId(1), "9991234567").await.unwrap();
+        storage.save_browser_profile(ChatId(1), "9990000000").await.unwrap();
+        storage.save_browser_profile(ChatId(2), "9991234567").await.unwrap();
+        assert_eq!(storage.count_user_numbers(ChatId(1)).await.unwrap(), 2);
+        assert_eq!(storage.get_user_browser_profiles_page(ChatId(1), 0, 1).await.unwrap(), vec!["9990000000"]);
+        assert_eq!(storage.get_user_browser_profiles_page(ChatId(1), 1, 1).await.unwrap(), vec!["9991234567"]);
+        assert_eq!(storage.get_all_browser_profiles().await.unwrap().len(), 3);
+        let profile = storage.get_browser_profile(ChatId(1), "9991234567").await.unwrap().unwrap();
+        assert_eq!(profile.health, ProfileHealth::Active);
+
+        // Сообщать стоит только о смене состояния входа
+        assert!(!storage.set_profile_health(ChatId(1), "9991234567", ProfileHealth::Active).await.unwrap());
+        assert!(storage.set_profile_health(ChatId(1), "9991234567", ProfileHealth::Expired).await.unwrap());
+        assert!(!storage.set_profile_health(ChatId(1), "0000000000", ProfileHealth::Expired).await.unwrap());
+        assert_eq!(storage.get_browser_profile(ChatId(2), "9991234567").await.unwrap().unwrap().health, ProfileHealth::Active);
+
+        // Номер есть только в другом чате: удалять из этого чата нечего
+        assert!(!storage.delete_browser_profile(ChatId(3), "9991234567").await.unwrap());
+        assert!(!storage.delete_browser_profile(ChatId(1), "").await.unwrap());
+        assert!(storage.delete_browser_profile(ChatId(1), "9991234567").await.unwrap());
+        assert!(!storage.delete_browser_profile(ChatId(1), "9991234567").await.unwrap());
+
+        storage.add_user(ChatId(10), "admin".to_string()).await.unwrap();
+        storage.set_admin_role(UserId(10), "admin", UserId(1)).await.unwrap();
+        storage.set_admin_role(UserId(11), "support", UserId(1)).await.unwrap();
+        storage.set_admin_role(UserId(11), "owner", UserId(1)).await.unwrap();
+        assert_eq!(storage.get_admin_role(UserId(11)).await.unwrap().as_deref(), Some("owner"));
+        assert_eq!(
+            storage.get_admins().await.unwrap(),
+            vec![(10, "admin".to_string(), Some("admin".to_string())), (11, "owner".to_string(), None)]
+        );
+        assert!(storage.delete_admin_role(UserId(11)).await.unwrap());
+        assert!(!storage.delete_admin_role(UserId(11)).await.unwrap());
+        assert_eq!(storage.get_admin_role(UserId(11)).await.unwrap(), None);
+    }
+
+    pub async fn check_subscriptions(storage: &dyn Storage) {
+        let limits = SlotLimits { max_coefficient: 2, date_from: None, date_to: None };
+        storage.add_subscription(ChatId(1), 507, "Коледино".to_string(), "Короба".to_string(), limits).await.unwrap();
+        let subscription = storage.get_user_subscriptions(ChatId(1)).await.unwrap().remove(0);
+        assert_eq!((subscription.warehouse_id, subscription.max_coefficient), (507, 2));
+        assert_eq!(storage.count_subscriptions().await.unwrap(), 1);
+
+        storage.set_subscription_auto_book(ChatId(2), subscription.id, Some("9991234567")).await.unwrap();
+        assert_eq!(storage.get_subscription_auto_book(subscription.id).await.unwrap(), None);
+        storage.set_subscription_auto_book(ChatId(1), subscription.id, Some("9991234567")).await.unwrap();
+        assert_eq!(storage.get_subscription_auto_book(subscription.id).await.unwrap().as_deref(), Some("9991234567"));
+        assert_eq!(storage.get_all_subscriptions().await.unwrap()[0].auto_book_phone.as_deref(), Some("9991234567"));
+        storage.add_user(ChatId(1), "first".to_string()).await.unwrap();
+        storage.set_user_active(ChatId(1), false).await.unwrap();
+        assert!(storage.get_all_subscriptions().await.unwrap().is_empty());
+        storage.add_user(ChatId(1), "first".to_string()).await.unwrap();
+        assert_eq!(storage.get_all_subscriptions().await.unwrap().len(), 1);
+
+        // Неотправленное уведомление можно отправить еще раз
+        assert!(storage.mark_slot_notified(subscription.id, 1_725_494_400).await.unwrap());
+        assert!(!storage.mark_slot_notified(subscription.id, 1_725_494_400).await.unwrap());
+        storage.unmark_slot_notified(subscription.id, 1_725_494_400).await.unwrap();
+        assert!(storage.mark_slot_notified(subscription.id, 1_725_494_400).await.unwrap());
+
+        // Чужой чат не удаляет подписку и не сбрасывает ее уведомления
+        storage.delete_subscription(ChatId(2), subscription.id).await.unwrap();
+        assert_eq!(storage.get_user_subscriptions(ChatId(1)).await.unwrap().len(), 1);
+        assert!(!storage.mark_slot_notified(subscription.id, 1_725_494_400).await.unwrap());
+        storage.delete_subscription(ChatId(1), subscription.id).await.unwrap();
+        assert!(storage.get_user_subscriptions(ChatId(1)).await.unwrap().is_empty());
+        assert!(storage.mark_slot_notified(subscription.id, 1_725_494_400).await.unwrap());
+    }
+
+    pub async fn check_presets_and_cheapest_search(storage: &dyn Storage) {
+        storage
+            .insert_warehouses(vec![
+                Warehouse { id: 507, name: "Коледино".to_string() },
+                Warehouse { id: 117986, name: "Казань".to_string() },
+            ])
+            .await
+            .unwrap();
+
+        let (chat, other_chat) = (ChatId(1), ChatId(2));
+        let limits = SlotLimits { max_coefficient: 2, date_from: Some(100), date_to: Some(300) };
+        let preset_id = storage.create_preset(chat, "Москва".to_string(), 507, "Короба".to_string(), limits).await.unwrap();
+        storage.add_warehouse_to_preset(chat, preset_id, 117986).await.unwrap();
+        storage.add_warehouse_to_preset(chat, preset_id, 1733).await.unwrap();
+        storage.add_warehouse_to_preset(other_chat, preset_id, 1).await.unwrap();
+        let preset = storage.get_preset(chat, preset_id).await.unwrap().unwrap();
+        let names: Vec<String> = preset.warehouses.into_iter().map(|w| w.name).collect();
+        // Склада нет в хранилище - показываем его id
+        assert_eq!(names, vec!["Казань", "Коледино", "Склад 1733"]);
+        assert_eq!((preset.max_coefficient, preset.date_from, preset.date_to), (2, Some(100), Some(300)));
+
+        assert_eq!(storage.duplicate_preset(other_chat, preset_id).await.unwrap(), None);
+        let copy_id = storage.duplicate_preset(chat, preset_id).await.unwrap().unwrap();
+        let copy = storage.get_preset(chat, copy_id).await.unwrap().unwrap();
+        assert_eq!(copy.name, "Москва (копия)");
+        assert_eq!(copy.warehouses.len(), 3);
+        assert_eq!(storage.count_user_presets(chat).await.unwrap(), 2);
+        assert_eq!(
+            storage.get_user_presets_page(chat, 0, 1).await.unwrap(),
+            vec![(preset_id, "Москва".to_string())]
+        );
+        assert_eq!(storage.get_user_presets_by_box_type(chat, "Короба").await.unwrap().len(), 2);
+        assert!(storage.get_user_presets_by_box_type(chat, "Монопаллеты").await.unwrap().is_empty());
+
+        // Склады копии меняются независимо, чужой чат их не трогает
+        storage.remove_warehouse_from_preset(other_chat, copy_id, 507).await.unwrap();
+        storage.remove_warehouse_from_preset(chat, copy_id, 507).await.unwrap();
+        let ids = |preset: Preset| preset.warehouses.into_iter().map(|w| w.id).collect::<Vec<_>>();
+        assert_eq!(ids(storage.get_preset(chat, copy_id).await.unwrap().unwrap()), vec![117986, 1733]);
+        assert_eq!(ids(storage.get_preset(chat, preset_id).await.unwrap().unwrap()).len(), 3);
+        assert!(storage.get_preset(other_chat, preset_id).await.unwrap().is_none());
+
+        let new_limits = SlotLimits { max_coefficient: 0, date_from: None, date_to: None };
+        storage.update_preset(other_chat, copy_id, "Чужой".to_string(), new_limits).await.unwrap();
+        storage.update_preset(chat, copy_id, "Казань".to_string(), new_limits).await.unwrap();
+        let copy = storage.get_preset(chat, copy_id).await.unwrap().unwrap();
+        assert_eq!((copy.name.as_str(), copy.max_coefficient, copy.date_from), ("Казань", 0, None));
+
+        storage.delete_preset(other_chat, copy_id).await.unwrap();
+        storage.delete_preset(chat, copy_id).await.unwrap();
+        assert!(storage.get_preset(chat, copy_id).await.unwrap().is_none());
+        assert_eq!(storage.count_user_presets(chat).await.unwrap(), 1);
+
+        // Поиск дешевой приемки у каждого участника группы свой
+        let member = Member { chat: ChatId(-100), user: UserId(1) };
+        let other = Member { chat: ChatId(-100), user: UserId(2) };
+        storage.start_cheapest_search(member, "Короба".to_string()).await.unwrap();
+        storage.set_cheapest_search_warehouses(member, Some(vec![507, 117986])).await.unwrap();
+        storage.set_cheapest_search_params(member, limits, vec!["москва".to_string()]).await.unwrap();
+        assert_eq!(
+            storage.get_cheapest_search(member).await.unwrap(),
+            Some(CheapestSearch {
+                box_type_name: "Короба".to_string(),
+                warehouse_ids: Some(vec![507, 117986]),
+                name_filter: vec!["москва".to_string()],
+                max_coefficient: Some(2),
+                date_from: Some(100),
+                date_to: Some(300),
+            })
+        );
+        assert_eq!(storage.get_cheapest_search(other).await.unwrap(), None);
+        // Новый поиск сбрасывает склады и параметры прошлого
+        storage.start_cheapest_search(member, "Монопаллеты".to_string()).await.unwrap();
+        let search = storage.get_cheapest_search(member).await.unwrap().unwrap();
+        assert_eq!((search.warehouse_ids, search.name_filter, search.max_coefficient), (None, vec![], None));
+    }
+
+    pub async fn check_broadcasts_and_stats(storage: &dyn Storage) {
+        let first = storage.store_callback_payload("preset_put:1:507").await.unwrap();
+        assert_eq!(storage.store_callback_payload("preset_put:1:507").await.unwrap(), first);
+        assert_eq!(storage.load_callback_payload(first).await.unwrap().as_deref(), Some("preset_put:1:507"));
+        assert_eq!(storage.load_callback_payload(first + 100).await.unwrap(), None);
+
+        let buttons = vec![("Сайт".to_string(), "https://example.com".to_string())];
+        let id = storage.create_broadcast(ChatId(1), "<b>Новости</b>", Some("photo".to_string()), &buttons).await.unwrap();
+        let broadcast = storage.get_broadcast(id).await.unwrap().unwrap();
+        assert_eq!((broadcast.text.as_str(), broadcast.photo.as_deref()), ("<b>Новости</b>", Some("photo")));
+        assert_eq!((broadcast.buttons, broadcast.status.as_str()), (buttons, "draft"));
+        assert!(storage.get_sending_broadcast().await.unwrap().is_none());
+
+        assert_eq!(storage.start_broadcast(id, 42, &[2, 3, 4]).await.unwrap(), Some(3));
+        // Повторное подтверждение не запускает рассылку второй раз
+        assert_eq!(storage.start_broadcast(id, 43, &[2, 3, 4]).await.unwrap(), None);
+        let sending = storage.get_sending_broadcast().await.unwrap().unwrap();
+        assert_eq!((sending.id, sending.progress_message_id), (id, Some(42)));
+        assert_eq!(storage.get_pending_recipients(id, 2).await.unwrap(), vec![2, 3]);
+        storage.set_recipient_status(id, 2, "sent").await.unwrap();
+        storage.set_recipient_status(id, 3, "blocked").await.unwrap();
+        assert_eq!(storage.get_pending_recipients(id, 2).await.unwrap(), vec![4]);
+        assert_eq!(
+            storage.get_broadcast_stats(id).await.unwrap(),
+            BroadcastStats { total: 3, sent: 1, blocked: 1, failed: 0 }
+        );
+        storage.finish_broadcast(id).await.unwrap();
+        assert_eq!(storage.get_broadcast(id).await.unwrap().unwrap().status, "done");
+        assert!(!storage.cancel_broadcast(id).await.unwrap());
+        let draft = storage.create_broadcast(ChatId(1), "Черновик", None, &[]).await.unwrap();
+        assert!(storage.cancel_broadcast(draft).await.unwrap());
+
+        let now = chrono::Utc::now().timestamp();
+        storage.record_usage_event("command", ChatId(1), Some(UserId(1))).await.unwrap();
+        storage.record_usage_event("callback", ChatId(-100), Some(UserId(2))).await.unwrap();
+        storage.record_usage_event("alert", ChatId(3), None).await.unwrap();
+        storage.record_api_call("/api/v1/acceptance/coefficients", Some(200), true).await.unwrap();
+        storage.record_api_call("/api/v1/acceptance/coefficients", Some(429), false).await.unwrap();
+        storage.record_api_call("/api/v1/warehouses", None, false).await.unwrap();
+        // Уведомления подписок не считаются активностью пользователей
+        assert_eq!(storage.count_active_users_since(now - 60).await.unwrap(), 2);
+        assert_eq!(storage.count_usage_events("alert", now - 60).await.unwrap(), 1);
+        assert_eq!(
+            storage.get_api_call_stats(now - 60).await.unwrap(),
+            vec![
+                ApiCallStats { endpoint: "/api/v1/acceptance/coefficients".to_string(), calls: 2, errors: 1 },
+                ApiCallStats { endpoint: "/api/v1/warehouses".to_string(), calls: 1, errors: 1 },
+            ]
+        );
+        assert!(storage.database_size().await.unwrap() > 0);
+        storage.delete_expired_events(now, now + 60).await.unwrap();
+        assert_eq!(storage.count_usage_events("command", 0).await.unwrap(), 0);
+        assert!(storage.get_api_call_stats(0).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{decode as base64_decode, encode as base64_encode};
+use std::error::Error;
+use std::sync::OnceLock;
+
+// Префикс зашифрованного значения в базе. Всё без префикса - старые незашифрованные токены
+const ENCRYPTED_PREFIX: &str = "enc1:";
+// Длина nonce AES-GCM в байтах
+const NONCE_LEN: usize = 12;
+
+// Текущий ключ шифрует, старые ключи только расшифровывают (нужны на время ротации)
+pub struct TokenCipher {
+    current: Aes256Gcm,
+    old: Vec<Aes256Gcm>,
+}
+
+static TOKEN_CIPHER: OnceLock<TokenCipher> = OnceLock::new();
+
+fn parse_key(key_base64: &str) -> Result<Aes256Gcm, Box<dyn Error + Send + Sync>> {
+    let key = base64_decode(key_base64.trim())
+        .map_err(|_| "Ключ шифрования токенов должен быть в base64")?;
+    if key.len() != 32 {
+        return Err("Ключ шифрования токенов должен быть длиной 32 байта".into());
+    }
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+impl TokenCipher {
+    pub fn new(current_key: &str, old_keys: &[&str]) -> Result<TokenCipher, Box<dyn Error + Send + Sync>> {
+        Ok(TokenCipher {
+            current: parse_key(current_key)?,
+            old: old_keys
+                .iter()
+                .filter(|k| !k.trim().is_empty())
+                .map(|k| parse_key(k))
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+
+    pub fn encrypt(&self, token: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .current
+            .encrypt(&nonce, token.as_bytes())
+            .map_err(|_| "Не удалось зашифровать токен")?;
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, base64_encode(payload)))
+    }
+
+    pub fn decrypt(&self, stored: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let encoded = match stored.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encoded) => encoded,
+            None => return Ok(stored.to_string()),
+        };
+        let payload = base64_decode(encoded).map_err(|_| "Поврежденный зашифрованный токен")?;
+        if payload.len() <= NONCE_LEN {
+            return Err("Поврежденный зашифрованный токен".into());
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let nonce = Nonce::from_slice(nonce);
+
+        for cipher in std::iter::once(&self.current).chain(self.old.iter()) {
+            if let Ok(plaintext) = cipher.decrypt(nonce, ciphertext) {
+                return Ok(String::from_utf8(plaintext).map_err(|_| "Ошибка преобразования в строку")?);
+            }
+        }
+        Err("Не удалось расшифровать токен ни одним из ключей".into())
+    }
+
+    // Нужно ли перешифровать значение текущим ключом
+    pub fn needs_reencryption(&self, stored: &str) -> bool {
+        let encoded = match stored.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encoded) => encoded,
+            None => return true,
+        };
+        match base64_decode(encoded) {
+            Ok(payload) if payload.len() > NONCE_LEN => {
+                let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+                self.current.decrypt(Nonce::from_slice(nonce), ciphertext).is_err()
+            }
+            _ => false,
+        }
+    }
+}
+
+// Ключи берутся из TOKEN_ENCRYPTION_KEY (текущий) и TOKEN_ENCRYPTION_OLD_KEYS (через запятую)
+pub fn init_from_env() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let current = std::env::var("TOKEN_ENCRYPTION_KEY")
+        .map_err(|_| "Не задан TOKEN_ENCRYPTION_KEY (32 байта в base64, например `openssl rand -base64 32`)")?;
+    let old = std::env::var("TOKEN_ENCRYPTION_OLD_KEYS").unwrap_or_default();
+    let old: Vec<&str> = old.split(',').collect();
+    TOKEN_CIPHER
+        .set(TokenCipher::new(&current, &old)?)
+        .map_err(|_| "Шифрование токенов уже инициализировано")?;
+    Ok(())
+}
+
+pub fn token_cipher() -> Result<&'static TokenCipher, Box<dyn Error + Send + Sync>> {
+    TOKEN_CIPHER
+        .get()
+        .ok_or_else(|| "Шифрование токенов не инициализировано".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const KEY_B: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    #[test]
+    fn round_trip_and_plaintext_passthrough() {
+        let cipher = TokenCipher::new(KEY_A, &[]).unwrap();
+        let stored = cipher.encrypt("secret.token.value").unwrap();
+        assert!(stored.starts_with(ENCRYPTED_PREFIX));
+        assert!(!stored.contains("secret"));
+        assert_eq!(cipher.decrypt(&stored).unwrap(), "secret.token.value");
+        assert_eq!(cipher.decrypt("legacy.plain.token").unwrap(), "legacy.plain.token");
+    }
+
+    #[test]
+    fn old_keys_decrypt_and_mark_for_rotation() {
+        let old = TokenCipher::new(KEY_A, &[]).unwrap();
+        let stored = old.encrypt("token").unwrap();
+
+        let rotated = TokenCipher::new(KEY_B, &[KEY_A]).unwrap();
+        assert_eq!(rotated.decrypt(&stored).unwrap(), "token");
+        assert!(rotated.needs_reencryption(&stored));
+        assert!(!rotated.needs_reencryption(&rotated.encrypt("token").unwrap()));
+
+        let without_old = TokenCipher::new(KEY_B, &[]).unwrap();
+        assert!(without_old.decrypt(&stored).is_err());
+    }
+
+    #[test]
+    fn tampered_value_is_rejected() {
+        let cipher = TokenCipher::new(KEY_A, &[]).unwrap();
+        let stored = cipher.encrypt("token").unwrap();
+        let mut payload = base64_decode(&stored[ENCRYPTED_PREFIX.len()..]).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        let tampered = format!("{}{}", ENCRYPTED_PREFIX, base64_encode(payload));
+        assert!(cipher.decrypt(&tampered).is_err());
+    }
+
+    #[test]
+    fn rejects_short_keys() {
+        assert!(TokenCipher::new("c2hvcnQ=", &[]).is_err());
+    }
+}
@@ -1,11 +1,14 @@
 use std::error::Error;
-use base64::decode as base64_decode;
+use base64::{decode_config as base64_decode_config, STANDARD_NO_PAD, URL_SAFE_NO_PAD};
 use chrono::{Duration, TimeZone, Utc};
-use serde_json::Value;
+use serde::Deserialize;
 use std::str;
 
-#[derive(Debug)]
-pub enum _Mask {
+use crate::config;
+
+// Номера битов в поле s токена
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenScope {
     Content = 1,            //Контент
     Analytics = 2,          //Аналитика
     PricesDiscounts = 3,   //Цены и скидки
@@ -18,19 +21,107 @@ pub enum _Mask {
     Supplies = 10,          //Поставки
     CustomerReturns = 11,  //Возвраты покупателям
     Documents = 12,         //Документы
-    ReadOnly = 30,         //Токен только на чтение
 }
 
-//Получение информации из токена
-async fn decode_payload_from_token(token: String) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let token_str = token.as_str();
-    let parts: Vec<&str> = token_str.split('.').collect();
+impl TokenScope {
+    pub const ALL: [TokenScope; 12] = [
+        TokenScope::Content,
+        TokenScope::Analytics,
+        TokenScope::PricesDiscounts,
+        TokenScope::Marketplace,
+        TokenScope::Statistics,
+        TokenScope::Promotion,
+        TokenScope::QuestionsFeedback,
+        TokenScope::Recommendations,
+        TokenScope::ChatWithBuyers,
+        TokenScope::Supplies,
+        TokenScope::CustomerReturns,
+        TokenScope::Documents,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            TokenScope::Content => "Контент",
+            TokenScope::Analytics => "Аналитика",
+            TokenScope::PricesDiscounts => "Цены и скидки",
+            TokenScope::Marketplace => "Маркетплейс",
+            TokenScope::Statistics => "Статистика",
+            TokenScope::Promotion => "Продвижение",
+            TokenScope::QuestionsFeedback => "Вопросы и отзывы",
+            TokenScope::Recommendations => "Рекомендации",
+            TokenScope::ChatWithBuyers => "Чат с покупателями",
+            TokenScope::Supplies => "Поставки",
+            TokenScope::CustomerReturns => "Возвраты покупателям",
+            TokenScope::Documents => "Документы",
+        }
+    }
+}
+
+// Бит "токен только на чтение" в поле s
+const READ_ONLY_BIT: u32 = 30;
+
+#[derive(Deserialize)]
+struct RawClaims {
+    exp: i64,
+    #[serde(default)]
+    s: i64,
+    sid: Option<String>,
+    id: Option<String>,
+}
+
+// Данные из payload токена WB
+#[derive(Debug, Clone)]
+pub struct TokenClaims {
+    pub exp: i64,                  // unix время окончания действия
+    pub scope_mask: i64,           // битовая маска категорий (поле s)
+    #[allow(dead_code)] // Подавляем варнинг, поле пока не используется
+    pub seller_id: Option<String>, // id продавца (поле sid)
+    #[allow(dead_code)] // Подавляем варнинг, поле пока не используется
+    pub token_id: Option<String>,  // id токена (поле id)
+    pub read_only: bool,
+}
+
+impl TokenClaims {
+    pub fn parse(token: &str) -> Result<TokenClaims, Box<dyn Error + Send + Sync>> {
+        let raw: RawClaims = serde_json::from_str(&decode_payload_from_token(token)?)
+            .map_err(|_| "Поле 'exp' не найдено или имеет неверный тип")?;
+        Ok(TokenClaims {
+            exp: raw.exp,
+            scope_mask: raw.s,
+            seller_id: raw.sid,
+            token_id: raw.id,
+            read_only: raw.s & (1 << READ_ONLY_BIT) != 0,
+        })
+    }
+
+    pub fn has_scope(&self, scope: TokenScope) -> bool {
+        self.scope_mask & (1 << scope as u32) != 0
+    }
+
+    pub fn scopes(&self) -> Vec<TokenScope> {
+        TokenScope::ALL.into_iter().filter(|s| self.has_scope(*s)).collect()
+    }
+
+    // Список категорий для сообщения пользователю
+    pub fn scopes_str(&self) -> String {
+        let scopes = self.scopes();
+        if scopes.is_empty() {
+            return "нет".to_string();
+        }
+        scopes.iter().map(|s| s.title()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+//Получение информации из токена. Части JWT закодированы в base64url без паддинга
+fn decode_payload_from_token(token: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
     if parts.len() != 3 {
         return Err("Некорректный токен: токен содержит менее трех частей".into());
     }
 
-    let payload = parts[1];
-    let decoded_payload = base64_decode(payload)?;
+    let payload = parts[1].trim_end_matches('=');
+    let decoded_payload = base64_decode_config(payload, URL_SAFE_NO_PAD)
+        .or_else(|_| base64_decode_config(payload, STANDARD_NO_PAD))?;
 
     let payload_str = String::from_utf8(decoded_payload)
         .map_err(|_| "Ошибка преобразования в строку")?;
@@ -40,16 +131,38 @@ async fn decode_payload_from_token(token: String) -> Result<String, Box<dyn Erro
 
 //Получение поля exp из токена
 async fn extract_exp_from_token(token: String) -> Result<i64, Box<dyn Error + Send + Sync>> {
-    let json: Value = serde_json::from_str(&decode_payload_from_token(token).await?)?;
-    let exp = json["exp"].as_i64().ok_or("Поле 'exp' не найдено или имеет неверный тип")?;
-    Ok(exp)
+    Ok(TokenClaims::parse(&token)?.exp)
 }
 
-//Проверка просрочен ли токен
-pub async fn is_token_expired(token: String) -> Result<bool, Box<dyn Error + Send + Sync>> {
-    let token_exp_time_unix = extract_exp_from_token(token).await?;
-    let current_time_unix = Utc::now().timestamp();
-    Ok(token_exp_time_unix > current_time_unix)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenStatus {
+    Valid { exp: i64 },
+    ExpiringSoon { exp: i64 }, // истекает в ближайшие alerts.token_expiry_warning_days дней
+    Expired { exp: i64 },
+    Malformed,                 // не удалось разобрать токен
+}
+
+impl TokenStatus {
+    // Токеном еще можно пользоваться
+    pub fn is_usable(&self) -> bool {
+        matches!(self, TokenStatus::Valid { .. } | TokenStatus::ExpiringSoon { .. })
+    }
+}
+
+//Состояние токена на момент now (unix время)
+pub fn token_status_at(token: &str, now: i64) -> TokenStatus {
+    match TokenClaims::parse(token) {
+        Ok(claims) if claims.exp <= now => TokenStatus::Expired { exp: claims.exp },
+        Ok(claims) if claims.exp <= now + Duration::days(config::get().alerts.token_expiry_warning_days).num_seconds() => {
+            TokenStatus::ExpiringSoon { exp: claims.exp }
+        }
+        Ok(claims) => TokenStatus::Valid { exp: claims.exp },
+        Err(_) => TokenStatus::Malformed,
+    }
+}
+
+pub fn token_status(token: &str) -> TokenStatus {
+    token_status_at(token, Utc::now().timestamp())
 }
 
 //Получение строки с тем когда выходит срок токена
@@ -60,21 +173,65 @@ pub async fn get_lifetime_str(token: String) -> Result<String, Box<dyn Error + S
         _ => return Err("Некорректное значение времени".into()),
     };
 
-    let moscow_time = token_exp_time + Duration::hours(3);
+    let moscow_time = token_exp_time + config::utc_offset();
 
     let token_exp_time_string = moscow_time.format("%d.%m.%Y %H:%M:%S").to_string();
 
     Ok(token_exp_time_string)
 }
 
-//Получение поля s из токена (это поле несет в себе свойства, к чему относится токен, поставки и тд)
-async fn _extract_s_from_token(token: String) -> Result<i64, Box<dyn Error + Send + Sync>> {
-    let json: Value = serde_json::from_str(&decode_payload_from_token(token).await?)?;
-    let s = json["s"].as_i64().ok_or("Поле 'exp' не найдено или имеет неверный тип")?;
-    Ok(s)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{encode_config, URL_SAFE_NO_PAD};
 
-pub async fn _is_token_property_set(token: String, bit: _Mask) -> Result<bool, Box<dyn Error + Send + Sync>> {
-    let s_bit_mask = _extract_s_from_token(token).await?;
-    Ok((s_bit_mask & bit as i64) != 0) 
-}
\ No newline at end of file
+    fn token_with_payload(payload: &str) -> String {
+        format!("eyJhbGciOiJFUzI1NiJ9.{}.signature", encode_config(payload, URL_SAFE_NO_PAD))
+    }
+
+    #[test]
+    fn parses_claims_and_scopes() {
+        // s = Поставки (бит 10) + Аналитика (бит 2) + только чтение (бит 30)
+        let s = (1i64 << 10) | (1 << 2) | (1 << 30);
+        let token = token_with_payload(&format!(
+            r#"{{"exp":1767225600,"s":{},"sid":"seller-1","id":"token-1"}}"#,
+            s
+        ));
+        let claims = TokenClaims::parse(&token).unwrap();
+        assert_eq!(claims.exp, 1767225600);
+        assert_eq!(claims.seller_id.as_deref(), Some("seller-1"));
+        assert_eq!(claims.token_id.as_deref(), Some("token-1"));
+        assert!(claims.read_only);
+        assert!(claims.has_scope(TokenScope::Supplies));
+        assert!(!claims.has_scope(TokenScope::Content));
+        assert_eq!(claims.scopes_str(), "Аналитика, Поставки");
+    }
+
+    #[test]
+    fn decodes_base64url_payload() {
+        // символы '>' и '?' дают '-' и '_' в base64url, которые не понимает стандартный base64
+        let token = token_with_payload(r#"{"exp":1,"s":0,"sid":">>>???"}"#);
+        assert!(token.contains('-') || token.contains('_'));
+        let claims = TokenClaims::parse(&token).unwrap();
+        assert_eq!(claims.seller_id.as_deref(), Some(">>>???"));
+        assert_eq!(claims.scopes_str(), "нет");
+    }
+
+    #[test]
+    fn reports_token_status() {
+        let token = token_with_payload(r#"{"exp":1000000,"s":1024}"#);
+        let day = Duration::days(1).num_seconds();
+        assert_eq!(token_status_at(&token, 0), TokenStatus::Valid { exp: 1000000 });
+        assert_eq!(token_status_at(&token, 1000000 - day), TokenStatus::ExpiringSoon { exp: 1000000 });
+        assert_eq!(token_status_at(&token, 1000000), TokenStatus::Expired { exp: 1000000 });
+        assert_eq!(token_status_at("garbage", 0), TokenStatus::Malformed);
+        assert!(TokenStatus::ExpiringSoon { exp: 0 }.is_usable());
+        assert!(!TokenStatus::Expired { exp: 0 }.is_usable());
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        assert!(TokenClaims::parse("not-a-token").is_err());
+        assert!(TokenClaims::parse(&token_with_payload(r#"{"s":1}"#)).is_err());
+    }
+}
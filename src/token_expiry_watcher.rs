@@ -0,0 +1,71 @@
+use std::error::Error;
+use teloxide::prelude::*;
+
+use crate::config;
+use crate::database::{Cabinet, Member};
+use crate::dialogue::{load_dialogue, save_dialogue, CabinetDraft, DialogueState};
+use crate::keyboards::enter_token_keyboard;
+use crate::storage::Storage;
+use crate::token_decoder::{get_lifetime_str, token_status, TokenStatus};
+
+// Предупреждаем за alerts.token_expiry_warning_days дней до окончания срока токена и еще раз, когда он истек.
+// Каждое напоминание отправляется один раз на конкретный срок токена
+pub async fn check_token_expiry(bot: &Bot, storage: &dyn Storage) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for cabinet in storage.get_all_cabinets().await? {
+        if let Err(e) = remind_if_needed(bot, storage, &cabinet).await {
+            eprintln!("Ошибка при напоминании о сроке токена пользователю {}: {:?}", cabinet.chat_id, e);
+        }
+    }
+    Ok(())
+}
+
+async fn remind_if_needed(bot: &Bot, storage: &dyn Storage, cabinet: &Cabinet) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let chat_id = cabinet.chat_id;
+    let (exp, kind, msg_to_user) = match token_status(&cabinet.token) {
+        TokenStatus::ExpiringSoon { exp } => (
+            exp,
+            "soon",
+            format!(
+                "⏳ Срок действия токена WB кабинета «{}» заканчивается {} (меньше чем через {} дн.).\n\nСоздайте новый токен с категорией <b>'Поставки'</b> и введите его, чтобы уведомления о коэффициентах не прервались",
+                cabinet.name,
+                get_lifetime_str(cabinet.token.clone()).await?,
+                config::get().alerts.token_expiry_warning_days
+            ),
+        ),
+        TokenStatus::Expired { exp } => (
+            exp,
+            "expired",
+            format!(
+                "⛔️ Срок действия токена WB кабинета «{}» истек {}.\n\nВведите новый токен\n\nТокен должен быть создан для работы с категорией <b>'Поставки'</b>",
+                cabinet.name,
+                get_lifetime_str(cabinet.token.clone()).await?
+            ),
+        ),
+        TokenStatus::Valid { .. } | TokenStatus::Malformed => return Ok(()),
+    };
+
+    if !storage.mark_token_reminder_sent(chat_id, exp, kind).await? {
+        return Ok(());
+    }
+    if let Err(e) = bot
+        .send_message(ChatId(chat_id), msg_to_user)
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(enter_token_keyboard(cabinet.id))
+        .await
+    {
+        // Неотправленное напоминание повторим при следующей проверке
+        storage.unmark_token_reminder_sent(chat_id, exp, kind).await?;
+        return Err(e.into());
+    }
+
+    // В личном чате сразу ждем новый токен, в группе его введет администратор по кнопке.
+    // Начатый пользователем диалог не перебиваем
+    if let (true, Some(user)) = (kind == "expired", ChatId(chat_id).as_user()) {
+        let member = Member { chat: ChatId(chat_id), user };
+        if load_dialogue(storage, member).await?.unwrap_or_default() == DialogueState::Idle {
+            let state = DialogueState::AwaitingToken { cabinet: Some(CabinetDraft::Edit { cabinet_id: cabinet.id }) };
+            save_dialogue(storage, member, &state).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+// Поиск складов по части названия: без учета регистра и разницы ё/е, с допуском опечаток
+use crate::api_reauests::Warehouse;
+
+// Сколько складов показывать в результатах поиска
+pub const SEARCH_RESULTS_LIMIT: usize = 20;
+
+// Нижний регистр, ё -> е, всё кроме букв и цифр становится пробелом
+pub fn normalize(text: &str) -> String {
+    let cleaned: String = text
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'ё' => 'е',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect();
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            current[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+// Сколько опечаток допускаем в зависимости от длины запроса
+fn allowed_typos(query_len: usize) -> usize {
+    match query_len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+// Оценка совпадения, чем меньше, тем лучше. None - склад не подходит
+pub fn match_score(query: &str, name: &str) -> Option<usize> {
+    let query = normalize(query);
+    let name = normalize(name);
+    if query.is_empty() {
+        return None;
+    }
+    if name.starts_with(&query) {
+        return Some(0);
+    }
+    if name.split(' ').any(|word| word.starts_with(&query)) {
+        return Some(1);
+    }
+    if name.contains(&query) {
+        return Some(2);
+    }
+
+    // Нечеткое совпадение: запрос сравниваем с началом каждого слова названия той же длины (±1)
+    let query_chars: Vec<char> = query.chars().collect();
+    let max_typos = allowed_typos(query_chars.len());
+    if max_typos == 0 {
+        return None;
+    }
+    let words: Vec<Vec<char>> = name.split(' ').map(|w| w.chars().collect()).collect();
+    let query_len = query_chars.len();
+    let mut best: Option<usize> = None;
+    for start in 0..words.len() {
+        // Запрос может состоять из нескольких слов, поэтому берем хвост названия с этого слова
+        let tail: Vec<char> = words[start..].join(&' ');
+        for len in query_len.saturating_sub(1)..=query_len + 1 {
+            if len == 0 || len > tail.len() {
+                continue;
+            }
+            let typos = levenshtein(&query_chars, &tail[..len]);
+            best = Some(best.map_or(typos, |b| b.min(typos)));
+        }
+    }
+    best.filter(|typos| *typos <= max_typos).map(|typos| 2 + typos)
+}
+
+// Подходящие склады, лучшие совпадения первыми
+pub fn search_warehouses(query: &str, warehouses: Vec<Warehouse>) -> Vec<Warehouse> {
+    let mut found: Vec<(usize, Warehouse)> = warehouses
+        .into_iter()
+        .filter_map(|w| match_score(query, &w.name).map(|score| (score, w)))
+        .collect();
+    found.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+    found.into_iter().take(SEARCH_RESULTS_LIMIT).map(|(_, w)| w).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warehouse(id: u32, name: &str) -> Warehouse {
+        Warehouse { id, name: name.to_string() }
+    }
+
+    #[test]
+    fn normalizes_case_and_yo() {
+        assert_eq!(normalize("  Щёлково-2 "), "щелково 2");
+        assert_eq!(normalize("СЦ Ёлки"), "сц елки");
+    }
+
+    #[test]
+    fn matches_prefix_substring_and_typos() {
+        assert_eq!(match_score("колед", "Коледино"), Some(0));
+        assert_eq!(match_score("щелково", "Щёлково"), Some(0));
+        assert_eq!(match_score("подольск", "СЦ Подольск 3"), Some(1));
+        assert_eq!(match_score("дольск", "Подольск"), Some(2));
+        assert_eq!(match_score("кладино", "Коледино"), None);
+        assert_eq!(match_score("калидино", "Коледино"), Some(4));
+        assert_eq!(match_score("казн", "Казань"), Some(3));
+        assert_eq!(match_score("тула", "Коледино"), None);
+        assert_eq!(match_score("", "Коледино"), None);
+    }
+
+    #[test]
+    fn orders_best_matches_first() {
+        let warehouses = vec![
+            warehouse(1, "СЦ Казань"),
+            warehouse(2, "Казань"),
+            warehouse(3, "Коледино"),
+            warehouse(4, "Казан"),
+        ];
+        let ids: Vec<u32> = search_warehouses("казань", warehouses).iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![2, 1, 4]);
+    }
+}
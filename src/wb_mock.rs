@@ -0,0 +1,161 @@
+// Локальный мок WB API для тестов: отдает заготовленные ответы на /ping, /api/v1/warehouses
+// и /api/v1/acceptance/coefficients, а ответы из очереди позволяют задать сценарий (null, 401, 429 и т.д.)
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl MockResponse {
+    pub fn json(body: &str) -> MockResponse {
+        MockResponse {
+            status: 200,
+            body: body.to_string(),
+            headers: vec![],
+        }
+    }
+
+    pub fn status(status: u16) -> MockResponse {
+        MockResponse {
+            status,
+            body: String::new(),
+            headers: vec![],
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub path: String,
+    pub query: String,
+    pub authorization: Option<String>,
+}
+
+pub const PING_OK: &str = r#"{"TS":"2024-09-04T12:00:00Z","Status":"OK"}"#;
+pub const WAREHOUSES: &str = r#"[{"ID":507,"name":"Коледино"},{"ID":117986,"name":"Казань"}]"#;
+pub const COEFFICIENTS: &str = r#"[
+    {"date":"2024-09-04T00:00:00Z","coefficient":0,"warehouseID":507,"warehouseName":"Коледино","boxTypeName":"Короба","boxTypeID":2},
+    {"date":"2024-09-05T00:00:00Z","coefficient":3,"warehouseID":507,"warehouseName":"Коледино","boxTypeName":"Короба","boxTypeID":2},
+    {"date":"2024-09-04T00:00:00Z","coefficient":-1,"warehouseID":507,"warehouseName":"Коледино","boxTypeName":"QR-поставка с коробами","boxTypeID":null}
+]"#;
+
+pub struct MockWbServer {
+    url: String,
+    scripted: Arc<Mutex<HashMap<String, VecDeque<MockResponse>>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockWbServer {
+    pub async fn start() -> MockWbServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let scripted: Arc<Mutex<HashMap<String, VecDeque<MockResponse>>>> = Arc::default();
+        let requests: Arc<Mutex<Vec<RecordedRequest>>> = Arc::default();
+
+        let (scripted_task, requests_task) = (scripted.clone(), requests.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (scripted, requests) = (scripted_task.clone(), requests_task.clone());
+                tokio::spawn(async move {
+                    let _ = handle_connection(stream, scripted, requests).await;
+                });
+            }
+        });
+
+        MockWbServer {
+            url,
+            scripted,
+            requests,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    // Следующий запрос на path получит этот ответ вместо заготовленного
+    pub fn enqueue(&self, path: &str, response: MockResponse) {
+        self.scripted
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push_back(response);
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    scripted: Arc<Mutex<HashMap<String, VecDeque<MockResponse>>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+) -> std::io::Result<()> {
+    // Читаем только заголовки: моку приходят GET без тела
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&buf).to_string();
+    let mut lines = head.lines();
+    let target = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .unwrap_or("/")
+        .to_string();
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let authorization = lines
+        .filter_map(|l| l.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .map(|(_, value)| value.trim().to_string());
+
+    requests.lock().unwrap().push(RecordedRequest {
+        path: path.to_string(),
+        query: query.to_string(),
+        authorization,
+    });
+
+    let scripted_response = scripted
+        .lock()
+        .unwrap()
+        .get_mut(path)
+        .and_then(|queue| queue.pop_front());
+    let response = scripted_response.unwrap_or_else(|| match path {
+        "/ping" => MockResponse::json(PING_OK),
+        "/api/v1/warehouses" => MockResponse::json(WAREHOUSES),
+        "/api/v1/acceptance/coefficients" => MockResponse::json(COEFFICIENTS),
+        _ => MockResponse::status(404),
+    });
+
+    let mut raw = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str("\r\n");
+    raw.push_str(&response.body);
+    stream.write_all(raw.as_bytes()).await?;
+    stream.shutdown().await
+}
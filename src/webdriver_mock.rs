@@ -0,0 +1,278 @@
+// Локальный WebDriver для тестов автобронирования: понимает те команды W3C WebDriver,
+// которые использует auto_booking.rs, и "открывает" по любому адресу одну статичную страницу.
+// Элементы ищутся по селекторам вида [attr="value"][attr2="value2"], элемент с data-after="X"
+// становится видимым после клика по элементу с data-testid="X"
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const MOCK_PORTAL: &str = include_str!("../assets/mock_portal.html");
+pub const MOCK_LOGIN: &str = include_str!("../assets/mock_login.html");
+// Заглушка вместо настоящего скриншота: сигнатура PNG
+pub const SCREENSHOT: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+const ELEMENT_KEY: &str = "element-6066-11e4-a52e-4f735466cecf";
+const SESSION_ID: &str = "mock-session";
+
+struct MockElement {
+    attrs: Vec<(String, String)>,
+    text: String,
+}
+
+impl MockElement {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Default)]
+struct Browser {
+    elements: Vec<MockElement>,
+    revealed: HashSet<String>,
+    capabilities: Value,
+    visited: Vec<String>,
+    clicked: Vec<String>,
+    typed: Vec<(String, String)>,
+    quit: bool,
+}
+
+impl Browser {
+    fn is_displayed(&self, element: &MockElement) -> bool {
+        element
+            .attr("data-after")
+            .is_none_or(|after| self.revealed.contains(after))
+    }
+}
+
+pub struct MockWebDriver {
+    url: String,
+    browser: Arc<Mutex<Browser>>,
+}
+
+impl MockWebDriver {
+    pub async fn start(page: &str) -> MockWebDriver {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let browser = Arc::new(Mutex::new(Browser {
+            elements: parse_page(page),
+            ..Default::default()
+        }));
+
+        let browser_task = browser.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let browser = browser_task.clone();
+                tokio::spawn(async move {
+                    let _ = handle_connection(stream, browser).await;
+                });
+            }
+        });
+
+        MockWebDriver { url, browser }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    // Аргументы запуска Chrome из goog:chromeOptions
+    pub fn chrome_args(&self) -> Vec<String> {
+        let browser = self.browser.lock().unwrap();
+        browser.capabilities["desiredCapabilities"]["goog:chromeOptions"]["args"]
+            .as_array()
+            .map(|args| args.iter().filter_map(|a| a.as_str().map(String::from)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn visited(&self) -> Vec<String> {
+        self.browser.lock().unwrap().visited.clone()
+    }
+
+    // data-testid нажатых элементов по порядку
+    pub fn clicked(&self) -> Vec<String> {
+        self.browser.lock().unwrap().clicked.clone()
+    }
+
+    // Введенный текст: data-testid поля и значение
+    pub fn typed(&self) -> Vec<(String, String)> {
+        self.browser.lock().unwrap().typed.clone()
+    }
+
+    pub fn quit(&self) -> bool {
+        self.browser.lock().unwrap().quit
+    }
+}
+
+// Разбор открывающих тегов страницы. Содержимое <script> пропускаем
+fn parse_page(page: &str) -> Vec<MockElement> {
+    let mut elements = vec![];
+    let mut rest = page;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if rest.starts_with("script") {
+            match rest.find("</script>") {
+                Some(end) => rest = &rest[end..],
+                None => break,
+            }
+            continue;
+        }
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+        let Some(end) = rest.find('>') else { break };
+        let attrs = parse_attrs(&rest[..end]);
+        rest = &rest[end + 1..];
+        let text = rest[..rest.find('<').unwrap_or(rest.len())].trim().to_string();
+        elements.push(MockElement { attrs, text });
+    }
+    elements
+}
+
+fn parse_attrs(tag: &str) -> Vec<(String, String)> {
+    let mut attrs = vec![];
+    let mut rest = tag.split_once(char::is_whitespace).map(|(_, a)| a).unwrap_or("");
+    loop {
+        rest = rest.trim_start();
+        let name_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        if name_end == 0 {
+            break;
+        }
+        let name = rest[..name_end].to_string();
+        rest = &rest[name_end..];
+        if let Some(value) = rest.strip_prefix("=\"") {
+            let value_end = value.find('"').unwrap_or(value.len());
+            attrs.push((name, value[..value_end].to_string()));
+            rest = value.get(value_end + 1..).unwrap_or("");
+        } else {
+            attrs.push((name, String::new()));
+        }
+    }
+    attrs
+}
+
+// [attr="value"][attr2="value2"] -> список пар
+fn parse_selector(selector: &str) -> Vec<(String, String)> {
+    selector
+        .split(']')
+        .filter_map(|part| part.trim().strip_prefix('['))
+        .filter_map(|part| part.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.trim_matches('"').to_string()))
+        .collect()
+}
+
+fn error(status: u16, error: &str) -> (u16, Value) {
+    (status, json!({"value": {"error": error, "message": error, "stacktrace": ""}}))
+}
+
+fn handle_command(browser: &mut Browser, method: &str, path: &str, body: Value) -> (u16, Value) {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["session"]) => {
+            browser.capabilities = body;
+            (200, json!({"value": {"sessionId": SESSION_ID, "capabilities": {}}}))
+        }
+        ("DELETE", ["session", _]) => {
+            browser.quit = true;
+            (200, json!({"value": null}))
+        }
+        ("POST", ["session", _, "timeouts"]) => (200, json!({"value": null})),
+        ("POST", ["session", _, "url"]) => {
+            browser.visited.push(body["url"].as_str().unwrap_or_default().to_string());
+            (200, json!({"value": null}))
+        }
+        ("POST", ["session", _, "elements"]) => {
+            let wanted = parse_selector(body["value"].as_str().unwrap_or_default());
+            let found: Vec<Value> = browser
+                .elements
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| wanted.iter().all(|(name, value)| e.attr(name) == Some(value.as_str())))
+                .map(|(i, _)| json!({ ELEMENT_KEY: i.to_string() }))
+                .collect();
+            (200, json!({ "value": found }))
+        }
+        ("GET", ["session", _, "screenshot"]) => (200, json!({"value": base64::encode(SCREENSHOT)})),
+        (_, ["session", _, "element", id, action]) => {
+            let Some(element) = id.parse::<usize>().ok().and_then(|i| browser.elements.get(i)) else {
+                return error(404, "no such element");
+            };
+            match (method, *action) {
+                ("GET", "displayed") => (200, json!({"value": browser.is_displayed(element)})),
+                ("GET", "text") => {
+                    let text = if browser.is_displayed(element) { element.text.clone() } else { String::new() };
+                    (200, json!({ "value": text }))
+                }
+                ("POST", "click") => {
+                    if !browser.is_displayed(element) {
+                        return error(400, "element not interactable");
+                    }
+                    let testid = element.attr("data-testid").unwrap_or_default().to_string();
+                    browser.revealed.insert(testid.clone());
+                    browser.clicked.push(testid);
+                    (200, json!({"value": null}))
+                }
+                ("POST", "clear") => (200, json!({"value": null})),
+                ("POST", "value") => {
+                    if !browser.is_displayed(element) {
+                        return error(400, "element not interactable");
+                    }
+                    let testid = element.attr("data-testid").unwrap_or_default().to_string();
+                    browser.typed.push((testid, body["text"].as_str().unwrap_or_default().to_string()));
+                    (200, json!({"value": null}))
+                }
+                ("GET", "screenshot") => (200, json!({"value": base64::encode(SCREENSHOT)})),
+                _ => error(404, "unknown command"),
+            }
+        }
+        _ => error(404, "unknown command"),
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, browser: Arc<Mutex<Browser>>) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or("GET").to_string();
+    let path = request_line.next().unwrap_or("/").to_string();
+    let content_length = lines
+        .filter_map(|l| l.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    // Команды WebDriver передают параметры JSON-телом
+    while buf.len() < head_end + content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let body = serde_json::from_slice(&buf[head_end..]).unwrap_or(Value::Null);
+
+    let (status, response) = handle_command(&mut browser.lock().unwrap(), &method, &path, body);
+    let response = response.to_string();
+    let raw = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    );
+    stream.write_all(raw.as_bytes()).await?;
+    stream.shutdown().await
+}